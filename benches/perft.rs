@@ -0,0 +1,123 @@
+//! Times perft on the reference positions and compares the magic sliding
+//! attack lookups with computing the same attacks ray by ray.
+//!
+//! Run with `cargo bench`.
+//!
+//! Move generation no longer computes attacks ray by ray, so the perft
+//! speedup was measured with the `perft` subcommand just before and after
+//! the switch to magic lookups, on two of Martin Sedlak's positions without
+//! pawns at depth 4, best of six release runs:
+//!
+//!     r3k2r/1b4bq/8/8/8/8/7B/R3K2R w KQkq - 0 1   494 ms ray by ray, 274 ms magic
+//!     r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1    668 ms ray by ray, 439 ms magic
+
+use chess_engine::magicattacks::{bishop_attacks, rook_attacks};
+use chess_engine::perft::perft;
+use chess_engine::rayattacks::Rays;
+use chess_engine::Game;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const POSITIONS: [(&str, &str, usize); 3] = [
+    (
+        "start",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        5,
+    ),
+    (
+        "kiwipete",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        4,
+    ),
+    (
+        "position 4",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        4,
+    ),
+];
+
+fn main() {
+    bench_sliding_attacks();
+    println!();
+    bench_perft();
+}
+
+fn bench_sliding_attacks() {
+    let rays = Rays::new();
+    let mut seed = 0x2545_F491_4F6C_DD1D_u64;
+    let occupancies = (0..4096)
+        .map(|_| {
+            let mut next = || {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed
+            };
+            next() & next()
+        })
+        .collect::<Vec<u64>>();
+
+    // Build the magic tables before timing them.
+    black_box(rook_attacks(0, 0));
+
+    let magic = time(|| {
+        let mut acc = 0;
+        for &occupancy in &occupancies {
+            for square in 0..64 {
+                acc ^= rook_attacks(square, occupancy) ^ bishop_attacks(square, occupancy);
+            }
+        }
+        acc
+    });
+    let by_ray = time(|| {
+        let mut acc = 0;
+        for &occupancy in &occupancies {
+            for square in 0..64 {
+                acc ^=
+                    rays.rook_attacks(square, occupancy) ^ rays.bishop_attacks(square, occupancy);
+            }
+        }
+        acc
+    });
+
+    let lookups = occupancies.len() as f64 * 64.0 * 2.0;
+    println!("sliding attacks ({} lookups)", lookups);
+    println!(
+        "  magic:       {:>8.2} ns/lookup",
+        magic.as_nanos() as f64 / lookups
+    );
+    println!(
+        "  ray by ray:  {:>8.2} ns/lookup",
+        by_ray.as_nanos() as f64 / lookups
+    );
+    println!(
+        "  speedup:     {:>8.2}x",
+        by_ray.as_secs_f64() / magic.as_secs_f64()
+    );
+}
+
+fn bench_perft() {
+    println!("perft");
+    for (name, fen, depth) in POSITIONS {
+        let mut game = Game::from_fen(fen).unwrap();
+        let mut nodes = 0;
+        let elapsed = time(|| {
+            nodes = perft(&mut game, depth);
+            nodes
+        });
+        println!(
+            "  {:<12} depth {}: {:>10} nodes in {:>6} ms, {:>8.0} knps",
+            name,
+            depth,
+            nodes,
+            elapsed.as_millis(),
+            nodes as f64 / elapsed.as_secs_f64() / 1000.0
+        );
+    }
+}
+
+fn time<T>(f: impl FnOnce() -> T) -> Duration {
+    let start = Instant::now();
+    black_box(f());
+    start.elapsed()
+}
@@ -1,449 +1,481 @@
-use bitflags::bitflags;
-use std::collections::VecDeque;
+use crate::kingattacks::KingAttacks;
+use crate::knightattacks::KnightAttacks;
+use crate::magicattacks::{bishop_attacks, rook_attacks};
+use crate::movegeneration::{generate_moves, king_is_in_check};
+use crate::pawnattacks::{self, PawnAttacks};
+use crate::position::*;
+use crate::rayattacks::Rays;
+use crate::san::{self, SanError};
+use crate::search::{self, SearchLimits, SearchResult};
+use crate::transpositiontable::TranspositionTable;
 use crate::utils::*;
+use std::sync::atomic::AtomicBool;
 
-type PiecePosition = u64;
-
-pub fn bit_to_position(bit: PiecePosition) -> Result<String, String> {
-    if bit == 0 {
-        return Err("No piece present!".to_string());
-    } else {
-        let onebit_index = bit_scan(bit);
-        return Ok(index_to_position(onebit_index));
-    }
-}
-
-
-pub fn position_to_bit(position: &str) -> Result<PiecePosition, String> {
-    if position.len() != 2 {
-        return Err(format!("Invalid length: {}, string: '{}'", position.len(), position));
-    }
-
-    let bytes = position.as_bytes();
-    let byte0 = bytes[0];
-    if byte0 < 97 || byte0 >= 97 + 8 {
-        return Err(format!("Invalid column character: {}, string: '{}'", byte0 as char, position));
-    }
-
-    let column = (byte0 - 97) as u32;
-
-    let byte1 = bytes[1];
-    let row;
-
-    match (byte1 as char).to_digit(10) {
-        Some(number) => if number < 1 || number > 8 {
-            return Err(format!("Invalid row character: {}, string: '{}'", byte1 as char, position));
-        } else {
-            row = number - 1;
-        },
-        None => return Err(format!("Invalid row character: {}, string '{}'", byte1 as char, position)),
-    }
-
-    let square_number = row * 8 + column;
-    let bit = (1 as u64) << square_number;
-
-    Ok(bit)
-}
-
-static COL_MAP: [char; 8] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
-
-pub fn index_to_position(index: usize) -> String {
-    let column = index % 8;
-    let row = index / 8 + 1;
-    return format!("{}{}", COL_MAP[column], row);
+/// A position together with the precomputed attack tables the move
+/// generator needs. Building the tables is not free, so create a `Game` once
+/// and reuse it rather than making one per position.
+#[derive(Clone)]
+pub struct Game {
+    pub position: Position,
+    pub knight_attacks: KnightAttacks,
+    pub king_attacks: KingAttacks,
+    pub pawn_attacks: PawnAttacks,
+    pub ray_attacks: Rays,
+    /// The hash of the position before each move played with `make_move`,
+    /// oldest first, for spotting repetitions.
+    pub history: Vec<u64>,
 }
 
-
-
+/// How a game has ended, see `Game::outcome`.
 #[derive(Debug, PartialEq, Clone, Copy)]
-pub enum Color {
-    White,
-    Black
+pub enum Outcome {
+    Checkmate {
+        winner: Color,
+    },
+    Stalemate,
+    /// Fifty moves by each side without a capture or pawn move; a draw once
+    /// either player claims it.
+    FiftyMoveRule,
+    /// Seventy-five moves by each side without a capture or pawn move.
+    SeventyFiveMoveRule,
+    /// The same position for the third time; a draw once claimed.
+    ThreefoldRepetition,
+    FivefoldRepetition,
+    /// Neither side has the material left to give mate.
+    InsufficientMaterial,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum PieceType {
-    Pawn,
-    Rook,
-    Knight,
-    Bishop,
-    Queen,
-    King
-}
-
-#[derive(Debug, PartialEq)]
-pub struct Piece {
-    position: PiecePosition,
-    color: Color,
-    piece_type: PieceType
-}
-
-
-impl Piece {
-    fn to_string(&self) -> String {
-        let mut result = match self.piece_type {
-            PieceType::Pawn => "p ",
-            PieceType::Rook => "r ",
-            PieceType::Knight => "n ",
-            PieceType::Bishop => "b ",
-            PieceType::Queen => "q ",
-            PieceType::King => "k ",
-        }.to_string();
-
-        if self.color == Color::White {
-            result.make_ascii_uppercase();
+impl Outcome {
+    /// The side that won, or `None` for a draw.
+    pub fn winner(&self) -> Option<Color> {
+        match self {
+            Outcome::Checkmate { winner } => Some(*winner),
+            _ => None,
         }
-
-        result
     }
-}
-
-
-
-
-#[derive(Debug, Copy, Clone)]
-pub enum Square {
-    Empty,
-    Occupied(usize),
-}
-
 
-
-bitflags! {
-    pub struct CastlingRights: u8 {
-        const NONE = 0;
-        const WHITEKINGSIDE = 1 << 0;
-        const WHITEQUEENSIDE = 1 << 1;
-        const BLACKKINGSIDE = 1 << 2;
-        const BLACKQUEENSIDE = 1 << 3;
-        const ALL =
-            Self::WHITEKINGSIDE.bits
-            | Self::WHITEQUEENSIDE.bits
-            | Self::BLACKKINGSIDE.bits
-            | Self::BLACKQUEENSIDE.bits;
+    pub fn is_draw(&self) -> bool {
+        self.winner().is_none()
     }
 }
 
+impl Game {
+    /// The standard starting position.
+    pub fn new() -> Self {
+        Self::with_position(Position::new())
+    }
 
+    /// A game starting from `position`.
+    pub fn with_position(position: Position) -> Self {
+        Self {
+            position,
+            knight_attacks: KnightAttacks::new(),
+            king_attacks: KingAttacks::new(),
+            pawn_attacks: PawnAttacks::new(),
+            ray_attacks: Rays::new(),
+            history: vec![],
+        }
+    }
 
-// Game type to own the data
-pub struct Game {
-    pub pieces: Vec<Piece>,
-    pub squares: Vec<Square>,
-    pub active_color: Color,
-    pub castling_rights: CastlingRights,
-    pub en_passant: Option<PiecePosition>,
-    pub halfmove_clock: usize,
-    pub fullmove_number: usize,
-}
+    /// A game starting from a validated FEN, see `Position::from_fen`.
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        Ok(Self::with_position(Position::from_fen(fen)?))
+    }
 
+    /// A game starting from an unvalidated FEN, see `Position::read_FEN`.
+    #[allow(non_snake_case)]
+    pub fn read_FEN(fen: &str) -> Self {
+        Self::with_position(Position::read_FEN(fen))
+    }
 
+    /// A game on an empty board, to be filled with `add`.
+    pub fn empty() -> Self {
+        Self::with_position(Position::empty())
+    }
 
+    pub fn add(mut self, piece_color: Color, piece_type: PieceType, square: &str) -> Self {
+        self.position.add(piece_color, piece_type, square);
+        self
+    }
 
-impl Game {
+    /// Plays `m` and records the position it was played from, so that
+    /// `outcome` can see repetitions.
+    pub fn make_move(&mut self, m: Move) -> Undo {
+        self.history.push(self.position.hash);
+        self.position.make_move(m)
+    }
 
-    fn push_piece_and_square(&mut self, position: usize, color: Color,
-                             piece_type: PieceType, index: &mut usize) {
-        self.pieces.push(Piece { position: (1 as u64) << position,
-                                 color: color,
-                                 piece_type: piece_type });
-        self.squares.push(Square::Occupied(*index));
-        *index += 1;
+    /// Takes back `m`, which must be the last move played with `make_move`.
+    pub fn unmake_move(&mut self, m: Move, undo: Undo) {
+        self.position.unmake_move(m, undo);
+        self.history.pop();
     }
 
-    fn push_empty_square(&mut self) {
-        self.squares.push(Square::Empty);
+    /// The legal moves for the side to move.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        generate_moves(self)
     }
 
-    pub fn initialize() -> Game {
-        Game::read_FEN("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+    /// The legal move `uci` names in UCI long algebraic notation, see
+    /// `Position::parse_uci_move`.
+    pub fn parse_uci_move(&self, uci: &str) -> Result<Move, UciMoveError> {
+        match_uci_move(uci, &self.legal_moves())
     }
 
-    pub fn to_string(&self) -> String {
-        let mut board = "".to_owned();
-        let mut temp = "".to_owned();
+    /// `m` in Standard Algebraic Notation, see `san::to_san`.
+    pub fn san(&mut self, m: Move) -> String {
+        san::to_san(self, m)
+    }
 
-        for (i, square) in self.squares.iter().enumerate() {
-            match square {
-                Square::Empty => temp.push_str(&index_to_position(i)),
-                Square::Occupied(idx) => temp.push_str(&self.pieces[*idx].to_string()),
-            }
+    /// The legal move `text` names in (loosely written) Standard Algebraic
+    /// Notation, see `san::parse_san`.
+    pub fn parse_san(&self, text: &str) -> Result<Move, SanError> {
+        san::parse_san(self, text)
+    }
 
-            if (i + 1) % 8 == 0 {
-                temp.push_str("\n");
-                board.insert_str(0, &temp);
-                temp.clear();
-            }
+    /// The best move within `limits`, see `search::search`. Pass the same
+    /// `table` from one move of a game to the next to keep what earlier
+    /// searches learned.
+    ///
+    /// Nothing can stop this search from outside, so limits that would let
+    /// it run forever, infinite or without a depth, node or time bound, stop
+    /// it at `search::DEFAULT_DEPTH` instead. Use `search::search` with a
+    /// stop flag to search for longer.
+    pub fn search(
+        &mut self,
+        limits: &SearchLimits,
+        table: &mut TranspositionTable,
+    ) -> SearchResult {
+        let mut limits = limits.clone();
+        let bounded = limits.depth.is_some()
+            || limits.nodes.is_some()
+            || limits.time_budget(self.position.active_color).is_some();
+        if limits.infinite || !bounded {
+            limits.infinite = false;
+            limits.depth = Some(limits.depth.unwrap_or(search::DEFAULT_DEPTH));
         }
-        board.insert_str(0, &temp);
+        search::search(self, &limits, table, &AtomicBool::new(false), &mut |_| ())
+    }
 
-        board
+    /// Whether the side to move is in check.
+    pub fn is_in_check(&self) -> bool {
+        king_is_in_check(self, self.position.active_color)
     }
 
+    /// How many times the current position has occurred, counting this one.
+    /// Only positions since the last capture or pawn move can be the same,
+    /// so the search stops there.
+    pub fn repetitions(&self) -> usize {
+        let since_irreversible = self.position.halfmove_clock.min(self.history.len());
+        let earlier = self.history[self.history.len() - since_irreversible..]
+            .iter()
+            .filter(|&&hash| hash == self.position.hash)
+            .count();
+
+        earlier + 1
+    }
 
-    #[allow(non_snake_case)]
-    pub fn read_FEN(fen: &str) -> Game {
-        let mut game = Game {
-            pieces: vec![],
-            squares: vec![],
-            active_color: Color::White,
-            castling_rights: CastlingRights::ALL,
-            en_passant: None,
-            halfmove_clock: 0,
-            fullmove_number: 1};
-
-        let (position, rest) = split_on(fen, ' ');
-
-        let mut deque_squares = VecDeque::new();
-        let mut piece_index = 0;
-        let mut piece_position = 64;
-
-        for row in position.splitn(8, |ch| ch == '/') {
-            piece_position -= 8;
-            let (pieces, squares) = parse_row(&row, piece_index, piece_position);
-
-            for p in pieces {
-                game.pieces.push(p);
-                piece_index += 1;
-            }
-            for s in squares {
-                deque_squares.push_front(s);
-            }
+    /// Whether the game is over, and how. Checkmate and stalemate take
+    /// precedence over the draw rules, and the automatic draws over the ones
+    /// a player has to claim.
+    pub fn outcome(&self) -> Option<Outcome> {
+        if self.legal_moves().is_empty() {
+            return Some(if self.is_in_check() {
+                Outcome::Checkmate {
+                    winner: self.position.active_color.opposite(),
+                }
+            } else {
+                Outcome::Stalemate
+            });
         }
 
-        game.squares = Vec::from(deque_squares);
-
+        let repetitions = self.repetitions();
+        if self.position.halfmove_clock >= 150 {
+            Some(Outcome::SeventyFiveMoveRule)
+        } else if repetitions >= 5 {
+            Some(Outcome::FivefoldRepetition)
+        } else if self.has_insufficient_material() {
+            Some(Outcome::InsufficientMaterial)
+        } else if self.position.halfmove_clock >= 100 {
+            Some(Outcome::FiftyMoveRule)
+        } else if repetitions >= 3 {
+            Some(Outcome::ThreefoldRepetition)
+        } else {
+            None
+        }
+    }
 
-        let (color_to_move, rest) = split_on(rest, ' ');
-        game.active_color = match color_to_move {
-            "w" => Color::White,
-            "b" => Color::Black,
-            _ => panic!("Unknown color designator: '{}'", color_to_move),
+    /// Whether no sequence of legal moves can end in mate: bare kings, a
+    /// single minor piece, or bishops that all stand on one square color.
+    pub fn has_insufficient_material(&self) -> bool {
+        let position = &self.position;
+        let both = |piece_type: PieceType| {
+            position.bitboard(Color::White, piece_type)
+                | position.bitboard(Color::Black, piece_type)
         };
 
+        if both(PieceType::Pawn) | both(PieceType::Rook) | both(PieceType::Queen) != 0 {
+            return false;
+        }
 
-        let (castling_rights, rest) = split_on(rest, ' ');
-        let mut castling = CastlingRights::NONE;
-        for ch in castling_rights.chars() {
-            match ch {
-                'K' => castling |= CastlingRights::WHITEKINGSIDE,
-                'Q' => castling |= CastlingRights::WHITEQUEENSIDE,
-                'k' => castling |= CastlingRights::BLACKKINGSIDE,
-                'q' => castling |= CastlingRights::BLACKQUEENSIDE,
-                '-' => (),
-                other => panic!("Invalid character in castling rights: '{}'", other),
-            }
+        let knights = both(PieceType::Knight);
+        let bishops = both(PieceType::Bishop);
+        if (knights | bishops).count_ones() <= 1 {
+            return true;
         }
-        game.castling_rights = castling;
-
-        let (en_passant, rest) = split_on(rest, ' ');
-        match en_passant {
-            "-" => game.en_passant = None,
-            s => match position_to_bit(s) {
-                Err(msg) => panic!("{}", msg),
-                Ok(bit) => game.en_passant = Some(bit),
-            }
-        };
 
+        knights == 0 && (bishops & LIGHT_SQUARES == 0 || bishops & !LIGHT_SQUARES == 0)
+    }
 
-        let (halfmove_clock, rest) = split_on(rest, ' ');
-        match halfmove_clock.parse() {
-            Ok(number) => game.halfmove_clock = number,
-            Err(_) => panic!("Invalid halfmove: {}", halfmove_clock),
-        }
+    /// The pieces of `color` attacking `square`, with sliders seeing through
+    /// everything not in `occupancy`. Pieces missing from `occupancy` are
+    /// left out too, so removing a piece from it answers what would attack
+    /// the square once that piece has moved or been taken.
+    pub fn attackers_to(&self, square: usize, color: Color, occupancy: Bitboard) -> Bitboard {
+        let position = &self.position;
+        let pieces = |piece_type: PieceType| position.bitboard(color, piece_type);
+        let queens = pieces(PieceType::Queen);
+
+        let attackers = (self.pawn_attacks.attacks(color.opposite(), square)
+            & pieces(PieceType::Pawn))
+            | (self.knight_attacks.0[square] & pieces(PieceType::Knight))
+            | (self.king_attacks.0[square] & pieces(PieceType::King))
+            | (rook_attacks(square, occupancy) & (pieces(PieceType::Rook) | queens))
+            | (bishop_attacks(square, occupancy) & (pieces(PieceType::Bishop) | queens));
+
+        attackers & occupancy
+    }
 
-        let (fullmove_number, rest) = split_on(rest, ' ');
-        match fullmove_number.parse() {
-            Ok(number) => game.fullmove_number = number,
-            Err(_) => panic!("Invalid halfmove: {}", fullmove_number),
+    /// Every square attacked by at least one piece of `color`, whether or not
+    /// a piece of its own stands there.
+    pub fn attacked_squares(&self, color: Color) -> Bitboard {
+        let position = &self.position;
+        let occupancy = position.white_occupancy | position.black_occupancy;
+        let pieces = |piece_type: PieceType| position.bitboard(color, piece_type);
+        let queens = pieces(PieceType::Queen);
+
+        let mut attacked = pawnattacks::all_attacks(pieces(PieceType::Pawn), color);
+        for square in extract_bits(pieces(PieceType::Knight)) {
+            attacked |= self.knight_attacks.0[square];
+        }
+        for square in extract_bits(pieces(PieceType::King)) {
+            attacked |= self.king_attacks.0[square];
+        }
+        for square in extract_bits(pieces(PieceType::Rook) | queens) {
+            attacked |= rook_attacks(square, occupancy);
+        }
+        for square in extract_bits(pieces(PieceType::Bishop) | queens) {
+            attacked |= bishop_attacks(square, occupancy);
         }
 
-        game
+        attacked
     }
 }
 
+const LIGHT_SQUARES: Bitboard = 0x55aa_55aa_55aa_55aa;
 
-fn parse_row(row: &str, mut piece_index: usize, mut piece_position: usize) -> (Vec<Piece>, VecDeque<Square>) {
-    let mut pieces = Vec::new();
-    let mut squares = VecDeque::new();
-
-    let mut color;
-
-
-    macro_rules! add_piece {
-        ($piece_type:ident) => {
-            {
-                let piece = Piece {color: color,
-                               position: (1 as u64) << piece_position,
-                               piece_type: PieceType::$piece_type};
-                let square = Square::Occupied(piece_index);
-                pieces.push(piece);
-                squares.push_front(square);
-                piece_position += 1;
-                piece_index += 1;
-            }
-        };
+impl Default for Game {
+    fn default() -> Self {
+        Self::new()
     }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for ch in row.chars() {
-        let is_upper = ch.is_ascii_uppercase();
-        color = if is_upper {Color::White} else {Color::Black};
-        match ch.to_ascii_lowercase() {
-            'r' => add_piece!(Rook),
-            'n' => add_piece!(Knight),
-            'b' => add_piece!(Bishop),
-            'q' => add_piece!(Queen),
-            'k' => add_piece!(King),
-            'p' => add_piece!(Pawn),
-            num => {
-                match num.to_digit(10) {
-                    None => panic!("Invalid input: {}", num),
-                    Some(number) => for i in 0..number {
-                        squares.push_front(Square::Empty);
-                        piece_position += 1;
-                    }
-                }
-            }
-        }
+    #[test]
+    fn test_legal_moves_start_position() {
+        let game = Game::new();
+        assert_eq!(game.legal_moves().len(), 20);
+        assert!(!game.is_in_check());
     }
 
-    (pieces, squares)
-}
+    #[test]
+    fn test_is_in_check() {
+        let game = Game::from_fen("4k3/8/8/8/8/8/8/4RK2 b - - 0 1").unwrap();
+        assert!(game.is_in_check());
+    }
 
+    fn bits(squares: &[&str]) -> Bitboard {
+        squares.iter().map(|sq| 1 << square_to_index(sq)).sum()
+    }
 
+    #[test]
+    fn test_attackers_to() {
+        let game = Game::from_fen("4k3/8/3n4/5b2/1R2p1P1/3P4/4Q3/4K3 w - - 0 1").unwrap();
+        let occupancy = game.position.white_occupancy | game.position.black_occupancy;
+        let e4 = square_to_index("e4");
+
+        assert_eq!(
+            game.attackers_to(e4, Color::White, occupancy),
+            bits(&["b4", "d3", "e2"])
+        );
+        assert_eq!(
+            game.attackers_to(e4, Color::Black, occupancy),
+            bits(&["d6", "f5"])
+        );
+    }
 
+    #[test]
+    fn test_attackers_to_with_changed_occupancy() {
+        let game = Game::from_fen("4k3/8/8/8/4p3/8/4Q3/4R1K1 w - - 0 1").unwrap();
+        let occupancy = game.position.white_occupancy | game.position.black_occupancy;
+        let e4 = square_to_index("e4");
+        let e2 = 1 << square_to_index("e2");
+
+        assert_eq!(game.attackers_to(e4, Color::White, occupancy), e2);
+        // With the queen gone, the rook behind it takes over.
+        assert_eq!(
+            game.attackers_to(e4, Color::White, occupancy & !e2),
+            bits(&["e1"])
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_attacked_squares_start_position() {
+        let game = Game::new();
+        assert_eq!(game.attacked_squares(Color::White), 0x00ff_ff7e);
+        assert_eq!(game.attacked_squares(Color::Black), 0x7eff_ff00_0000_0000);
+    }
 
-    fn get_initial_position() -> Game {
-        let mut game = Game { pieces: vec![], squares: vec![],
-                              active_color: Color::White,
-                              castling_rights: CastlingRights::ALL,
-                              en_passant: None,
-                              halfmove_clock: 0,
-                              fullmove_number: 1
-        };
-        let mut piece_index = 0;
-
-        let color = Color::White;
-
-        game.push_piece_and_square(0, color,
-                                   PieceType::Rook, &mut piece_index);
-        game.push_piece_and_square(1, color,
-                                   PieceType::Knight, &mut piece_index);
-        game.push_piece_and_square(2, color,
-                                   PieceType::Bishop, &mut piece_index);
-        game.push_piece_and_square(3, color,
-                                   PieceType::Queen, &mut piece_index);
-        game.push_piece_and_square(4, color,
-                                   PieceType::King, &mut piece_index);
-        game.push_piece_and_square(5, color,
-                                   PieceType::Bishop, &mut piece_index);
-        game.push_piece_and_square(6, color,
-                                   PieceType::Knight, &mut piece_index);
-        game.push_piece_and_square(7, color,
-                                   PieceType::Rook, &mut piece_index);
-
-        for i in 8..16 {
-            game.push_piece_and_square(i, color,
-                                       PieceType::Pawn, &mut piece_index);
-        }
+    #[test]
+    fn test_attacked_squares_are_blocked() {
+        let game = Game::from_fen("4k3/8/8/8/8/8/8/R3K2r w - - 0 1").unwrap();
+        let black = game.attacked_squares(Color::Black);
+        assert_ne!(black & bits(&["f1"]), 0);
+        assert_ne!(black & bits(&["e1"]), 0);
+        assert_eq!(black & bits(&["d1"]), 0);
+    }
 
-        for i in 16..48 {
-            game.push_empty_square();
-        }
+    #[test]
+    fn test_from_fen_rejects_invalid_positions() {
+        assert!(Game::from_fen("8/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+    }
 
-        let color = Color::Black;
-        for i in 48..56 {
-            game.push_piece_and_square(i, color,
-                                       PieceType::Pawn, &mut piece_index);
+    fn play(game: &mut Game, moves: &[&str]) {
+        for name in moves {
+            let m = game.parse_uci_move(name).unwrap();
+            game.make_move(m);
         }
+    }
 
-        let offset = 56;
-        game.push_piece_and_square(0 + offset, color,
-                                   PieceType::Rook, &mut piece_index);
-        game.push_piece_and_square(1 + offset, color,
-                                   PieceType::Knight, &mut piece_index);
-        game.push_piece_and_square(2 + offset, color,
-                                   PieceType::Bishop, &mut piece_index);
-        game.push_piece_and_square(3 + offset, color,
-                                   PieceType::Queen, &mut piece_index);
-        game.push_piece_and_square(4 + offset, color,
-                                   PieceType::King, &mut piece_index);
-        game.push_piece_and_square(5 + offset, color,
-                                   PieceType::Bishop, &mut piece_index);
-        game.push_piece_and_square(6 + offset, color,
-                                   PieceType::Knight, &mut piece_index);
-        game.push_piece_and_square(7 + offset, color,
-                                   PieceType::Rook, &mut piece_index);
-
+    #[test]
+    fn test_outcome_checkmate() {
+        let mut game = Game::new();
+        play(&mut game, &["f2f3", "e7e5", "g2g4", "d8h4"]);
+        let outcome = game.outcome();
+
+        assert_eq!(
+            outcome,
+            Some(Outcome::Checkmate {
+                winner: Color::Black
+            })
+        );
+        assert!(!outcome.unwrap().is_draw());
+    }
 
-        game
+    #[test]
+    fn test_outcome_stalemate() {
+        let game = Game::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(game.outcome(), Some(Outcome::Stalemate));
+        assert!(game.outcome().unwrap().is_draw());
     }
 
+    #[test]
+    fn test_outcome_in_progress() {
+        assert_eq!(Game::new().outcome(), None);
+    }
 
     #[test]
-    fn read_initial_position() {
-        let game = Game::initialize();
-        let default = get_initial_position();
-        assert_eq!(game.active_color, Color::White);
-        assert_eq!(game.castling_rights, CastlingRights::ALL);
-        assert_eq!(game.en_passant, None);
-        assert_eq!(game.halfmove_clock, 0);
-        assert_eq!(game.fullmove_number, 1);
-        for i in 0..64 {
-            match (game.squares[i], default.squares[i]) {
-                (Square::Empty, Square::Empty) => (),
-                (Square::Occupied(idx1), Square::Occupied(idx2)) => assert_eq!(game.pieces[idx1], default.pieces[idx2]),
-                 _ => panic!("Wrong square at index {}", i),
-            }
-        }
+    fn test_outcome_move_rules() {
+        let fen = |clock| format!("4k3/8/8/8/8/8/4P3/4K2R w - - {} 80", clock);
+
+        assert_eq!(Game::from_fen(&fen(99)).unwrap().outcome(), None);
+        assert_eq!(
+            Game::from_fen(&fen(100)).unwrap().outcome(),
+            Some(Outcome::FiftyMoveRule)
+        );
+        assert_eq!(
+            Game::from_fen(&fen(150)).unwrap().outcome(),
+            Some(Outcome::SeventyFiveMoveRule)
+        );
     }
 
     #[test]
-    fn read_fen_black_active() {
-        let game = Game::read_FEN("rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b - - 1 2");
-        assert_eq!(game.active_color, Color::Black);
+    fn test_checkmate_beats_move_rules() {
+        let game = Game::from_fen("7k/6Q1/6K1/8/8/8/8/8 b - - 150 100").unwrap();
+        assert_eq!(
+            game.outcome(),
+            Some(Outcome::Checkmate {
+                winner: Color::White
+            })
+        );
     }
 
     #[test]
-    fn read_fen_no_castling() {
-        let game = Game::read_FEN("rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b - - 1 2");
-        assert_eq!(game.castling_rights, CastlingRights::NONE);
+    fn test_outcome_repetitions() {
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        let mut game = Game::new();
+
+        play(&mut game, &shuffle);
+        assert_eq!(game.repetitions(), 2);
+        assert_eq!(game.outcome(), None);
+
+        play(&mut game, &shuffle);
+        assert_eq!(game.outcome(), Some(Outcome::ThreefoldRepetition));
+
+        play(&mut game, &shuffle);
+        play(&mut game, &shuffle);
+        assert_eq!(game.outcome(), Some(Outcome::FivefoldRepetition));
     }
 
     #[test]
-    fn read_fen_en_passant_allowed() {
-        let en_passant_square = "g7";
-        let game = Game::read_FEN(&format!("rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq {} 1 2", en_passant_square));
-        assert_eq!(game.en_passant, Some(position_to_bit(en_passant_square).unwrap()));
+    fn test_repetitions_reset_by_pawn_move() {
+        let mut game = Game::new();
+        play(&mut game, &["g1f3", "g8f6", "f3g1", "f6g8", "e2e4", "e7e5"]);
+        play(&mut game, &["g1f3", "g8f6", "f3g1", "f6g8"]);
+        assert_eq!(game.repetitions(), 2);
     }
 
     #[test]
-    fn read_fen_moveclocks() {
-        let game = Game::read_FEN("rnbqkbnr/pp1ppppp/7P/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b - g7 1 2");
-        assert_eq!(game.halfmove_clock, 1);
-        assert_eq!(game.fullmove_number, 2);
+    fn test_unmake_move_drops_history() {
+        let mut game = Game::new();
+        let m = game.legal_moves()[0];
+        let undo = game.make_move(m);
+        assert_eq!(game.history, vec![Position::new().hash]);
+
+        game.unmake_move(m, undo);
+        assert!(game.history.is_empty());
+        assert_eq!(game.position.hash, Position::new().hash);
     }
 
     #[test]
-    fn read_all_possible_castling_rights() {
-        let mut rights = "".to_owned();
-        let right_chars = ["K", "Q", "k", "q"];
-        for i in 0..(2^4) {
-            let bitflag_rights = CastlingRights::from_bits(i).unwrap();
-            for j in 0..4 {
-                if (i >> j) & 1 != 0 {
-                    rights.push_str(right_chars[j]);
-                }
-            }
-            let fen = format!("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w {} - 0 1", rights);
-            let game = Game::read_FEN(&fen);
-            assert_eq!(game.castling_rights, bitflag_rights, "FEN: {}\n\n i: {}", fen, i);
-            rights.clear();
+    fn test_insufficient_material() {
+        let drawn = [
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4KN2 w - - 0 1",
+            "4kb2/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+        ];
+        let playable = [
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/3NKN2 w - - 0 1",
+            "4kb2/8/8/8/8/8/8/3BK3 w - - 0 1",
+            "4kn2/8/8/8/8/8/8/4KB2 w - - 0 1",
+        ];
+
+        for fen in drawn {
+            let game = Game::from_fen(fen).unwrap();
+            assert!(game.has_insufficient_material(), "{}", fen);
+            assert_eq!(game.outcome(), Some(Outcome::InsufficientMaterial));
+        }
+        for fen in playable {
+            assert!(
+                !Game::from_fen(fen).unwrap().has_insufficient_material(),
+                "{}",
+                fen
+            );
         }
     }
 }
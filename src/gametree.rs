@@ -0,0 +1,461 @@
+use crate::game::Game;
+use crate::position::*;
+
+/// Refers to a node of a `GameTree`. The root, the starting position, is 0.
+pub type NodeId = usize;
+
+/// A position in a `GameTree` together with the move that led to it.
+#[derive(Clone)]
+pub struct Node {
+    /// The move played from the parent, `None` for the root.
+    pub m: Option<Move>,
+    /// The position after `m`.
+    pub position: Position,
+    pub parent: Option<NodeId>,
+    /// The continuations from here; the first one is the main line, the
+    /// others are variations in the order they were added.
+    pub children: Vec<NodeId>,
+    pub comment: Option<String>,
+    /// Numeric annotation glyphs as used in PGN, e.g. 1 for `!` or 2 for `?`.
+    pub nags: Vec<u8>,
+    // The child `redo` steps into; the one last visited.
+    selected: usize,
+    // How many moves below the root this is.
+    depth: usize,
+    // Set once `remove_variation` has cut the node from the tree.
+    removed: bool,
+}
+
+impl Node {
+    fn new(m: Option<Move>, position: Position, parent: Option<NodeId>, depth: usize) -> Self {
+        Self {
+            m,
+            position,
+            parent,
+            children: vec![],
+            comment: None,
+            nags: vec![],
+            selected: 0,
+            depth,
+            removed: false,
+        }
+    }
+}
+
+/// A game record: the starting position and every move played from it,
+/// including side lines. A cursor marks the current node; playing a move adds
+/// it below the cursor, as a variation if a different move was already
+/// played there, and `undo`/`redo` move the cursor back and forth.
+///
+/// The `Game` at the cursor is kept up to date, with the moves leading to it
+/// in its history, so that repetitions count for `Game::outcome`.
+pub struct GameTree {
+    nodes: Vec<Node>,
+    current: NodeId,
+    starting_fen: String,
+    game: Game,
+}
+
+impl GameTree {
+    /// A record starting from the standard starting position.
+    pub fn new() -> Self {
+        Self::with_game(Game::new())
+    }
+
+    /// A record starting from a validated FEN, see `Position::from_fen`.
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        Ok(Self::with_game(Game::from_fen(fen)?))
+    }
+
+    /// A record starting from the current position of `game`. Its history
+    /// is kept, so repetitions of positions before the record began still
+    /// count.
+    pub fn with_game(game: Game) -> Self {
+        Self {
+            nodes: vec![Node::new(None, game.position.clone(), None, 0)],
+            current: 0,
+            starting_fen: game.position.to_fen(),
+            game,
+        }
+    }
+
+    pub fn starting_fen(&self) -> &str {
+        &self.starting_fen
+    }
+
+    /// The game at the current node.
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn position(&self) -> &Position {
+        &self.game.position
+    }
+
+    pub fn root(&self) -> NodeId {
+        0
+    }
+
+    pub fn current(&self) -> NodeId {
+        self.current
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    /// Plays `m`, which must be legal in the current position, and moves the
+    /// cursor to it. If `m` was already played from here the existing node
+    /// is reused, otherwise it is added after the ones already there.
+    pub fn play(&mut self, m: Move) -> NodeId {
+        debug_assert!(
+            self.game.legal_moves().contains(&m),
+            "{} is not legal in {}",
+            m,
+            self.game.position.to_fen()
+        );
+
+        let parent = self.current;
+        let index = match self.nodes[parent]
+            .children
+            .iter()
+            .position(|&child| self.nodes[child].m == Some(m))
+        {
+            Some(index) => index,
+            None => {
+                let mut position = self.game.position.clone();
+                position.make_move(m);
+                let id = self.nodes.len();
+                let depth = self.nodes[parent].depth + 1;
+                self.nodes
+                    .push(Node::new(Some(m), position, Some(parent), depth));
+                self.nodes[parent].children.push(id);
+                self.nodes[parent].children.len() - 1
+            }
+        };
+
+        self.step_into(index);
+        self.current
+    }
+
+    /// Moves the cursor to the parent node and returns the move taken back,
+    /// or `None` at the root.
+    pub fn undo(&mut self) -> Option<Move> {
+        let node = &self.nodes[self.current];
+        let (m, parent) = (node.m?, node.parent?);
+
+        self.current = parent;
+        self.game.position = self.nodes[parent].position.clone();
+        self.game.history.pop();
+        Some(m)
+    }
+
+    /// Replays the move last taken back from here, or the main line move if
+    /// none was, and returns it. `None` at the end of a line.
+    pub fn redo(&mut self) -> Option<Move> {
+        let selected = self.nodes[self.current].selected;
+        if selected >= self.nodes[self.current].children.len() {
+            return None;
+        }
+
+        self.step_into(selected);
+        self.nodes[self.current].m
+    }
+
+    fn step_into(&mut self, index: usize) {
+        let parent = &mut self.nodes[self.current];
+        parent.selected = index;
+        let child = parent.children[index];
+
+        self.game.history.push(self.game.position.hash);
+        self.game.position = self.nodes[child].position.clone();
+        self.current = child;
+    }
+
+    /// Moves the cursor to any node of the tree, taking back moves only as
+    /// far as where the current line and the one to `id` part. Returns
+    /// `false`, leaving the cursor where it was, if `id` has been removed.
+    pub fn go_to(&mut self, id: NodeId) -> bool {
+        if self.nodes[id].removed {
+            return false;
+        }
+
+        let mut path = vec![];
+        let mut node = id;
+        while self.nodes[node].depth > self.nodes[self.current].depth {
+            path.push(node);
+            node = self.nodes[node].parent.unwrap();
+        }
+        while self.nodes[self.current].depth > self.nodes[node].depth {
+            self.undo();
+        }
+        while self.current != node {
+            path.push(node);
+            node = self.nodes[node].parent.unwrap();
+            self.undo();
+        }
+
+        for id in path.into_iter().rev() {
+            let index = self.nodes[self.current]
+                .children
+                .iter()
+                .position(|&child| child == id)
+                .unwrap();
+            self.step_into(index);
+        }
+        true
+    }
+
+    /// The nodes from just below the root down to `id`.
+    fn path_to(&self, id: NodeId) -> Vec<NodeId> {
+        let mut path = vec![];
+        let mut node = id;
+        while let Some(parent) = self.nodes[node].parent {
+            path.push(node);
+            node = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// The moves from the starting position to the current node.
+    pub fn moves(&self) -> Vec<Move> {
+        self.path_to(self.current)
+            .into_iter()
+            .map(|id| self.nodes[id].m.unwrap())
+            .collect()
+    }
+
+    /// The moves of the main line, following the first child from the root.
+    pub fn mainline(&self) -> Vec<Move> {
+        let mut moves = vec![];
+        let mut node = &self.nodes[self.root()];
+        while let Some(&child) = node.children.first() {
+            node = &self.nodes[child];
+            moves.push(node.m.unwrap());
+        }
+        moves
+    }
+
+    /// Whether `id` lies on the main line.
+    pub fn is_mainline(&self, id: NodeId) -> bool {
+        self.path_to(id)
+            .into_iter()
+            .all(|id| self.nodes[self.nodes[id].parent.unwrap()].children[0] == id)
+    }
+
+    /// Makes the variation `id` belongs to the main line from its branch
+    /// point, moving the old continuation to the first variation.
+    pub fn promote_to_mainline(&mut self, id: NodeId) {
+        for id in self.path_to(id) {
+            let parent = self.nodes[id].parent.unwrap();
+            let parent = &mut self.nodes[parent];
+            let index = parent.children.iter().position(|&c| c == id).unwrap();
+            parent.children.remove(index);
+            parent.children.insert(0, id);
+            parent.selected = 0;
+        }
+    }
+
+    /// Removes `id` and everything after it. If the cursor was inside the
+    /// removed part it moves to the parent of `id`. The root can't be
+    /// removed.
+    ///
+    /// The ids of the removed nodes are not reused; `go_to` refuses them, and
+    /// no other method may be given one.
+    pub fn remove_variation(&mut self, id: NodeId) {
+        let parent = self.nodes[id].parent.expect("The root can't be removed");
+
+        if self.path_to(self.current).contains(&id) {
+            self.go_to(parent);
+        }
+
+        let parent = &mut self.nodes[parent];
+        parent.children.retain(|&child| child != id);
+        parent.selected = 0;
+
+        let mut removed = vec![id];
+        while let Some(id) = removed.pop() {
+            self.nodes[id].removed = true;
+            removed.extend(&self.nodes[id].children);
+        }
+    }
+
+    /// Sets the comment on `id`; an empty one removes it.
+    pub fn set_comment(&mut self, id: NodeId, comment: &str) {
+        self.nodes[id].comment = if comment.is_empty() {
+            None
+        } else {
+            Some(comment.to_string())
+        };
+    }
+
+    pub fn add_nag(&mut self, id: NodeId, nag: u8) {
+        if !self.nodes[id].nags.contains(&nag) {
+            self.nodes[id].nags.push(nag);
+        }
+    }
+
+    pub fn remove_nag(&mut self, id: NodeId, nag: u8) {
+        self.nodes[id].nags.retain(|&n| n != nag);
+    }
+}
+
+impl Default for GameTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Outcome;
+
+    fn play(tree: &mut GameTree, moves: &[&str]) -> NodeId {
+        for name in moves {
+            let m = tree.game().parse_uci_move(name).unwrap();
+            tree.play(m);
+        }
+        tree.current()
+    }
+
+    fn names(moves: &[Move]) -> Vec<String> {
+        moves.iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn test_play_undo_redo() {
+        let mut tree = GameTree::new();
+        play(&mut tree, &["e2e4", "e7e5", "g1f3"]);
+        assert_eq!(names(&tree.moves()), vec!["e2e4", "e7e5", "g1f3"]);
+
+        assert_eq!(tree.undo().unwrap().to_string(), "g1f3");
+        assert_eq!(tree.undo().unwrap().to_string(), "e7e5");
+        assert_eq!(names(&tree.moves()), vec!["e2e4"]);
+        assert_eq!(tree.game().history.len(), 1);
+
+        assert_eq!(tree.redo().unwrap().to_string(), "e7e5");
+        assert_eq!(tree.redo().unwrap().to_string(), "g1f3");
+        assert_eq!(tree.redo(), None);
+        assert_eq!(
+            tree.position().to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+    }
+
+    #[test]
+    fn test_undo_at_root() {
+        let mut tree = GameTree::new();
+        assert_eq!(tree.undo(), None);
+        assert_eq!(tree.current(), tree.root());
+    }
+
+    #[test]
+    fn test_variations() {
+        let mut tree = GameTree::new();
+        play(&mut tree, &["e2e4", "e7e5"]);
+        tree.undo();
+        let sicilian = play(&mut tree, &["c7c5"]);
+
+        let e4 = tree.node(tree.root()).children[0];
+        assert_eq!(tree.node(e4).children.len(), 2);
+        assert_eq!(names(&tree.mainline()), vec!["e2e4", "e7e5"]);
+        assert!(!tree.is_mainline(sicilian));
+
+        // Redo follows the line last visited.
+        tree.undo();
+        assert_eq!(tree.redo().unwrap().to_string(), "c7c5");
+
+        // Playing a move that is already there reuses its node.
+        tree.undo();
+        let nodes = tree.nodes.len();
+        play(&mut tree, &["e7e5"]);
+        assert_eq!(tree.nodes.len(), nodes);
+
+        tree.promote_to_mainline(sicilian);
+        assert_eq!(names(&tree.mainline()), vec!["e2e4", "c7c5"]);
+        assert!(tree.is_mainline(sicilian));
+    }
+
+    #[test]
+    fn test_go_to() {
+        let mut tree = GameTree::new();
+        let e5 = play(&mut tree, &["e2e4", "e7e5"]);
+        tree.go_to(tree.root());
+        let d5 = play(&mut tree, &["d2d4", "d7d5"]);
+
+        tree.go_to(e5);
+        assert_eq!(names(&tree.moves()), vec!["e2e4", "e7e5"]);
+        assert_eq!(tree.position().to_fen(), tree.node(e5).position.to_fen());
+        assert_eq!(tree.game().history.len(), 2);
+
+        tree.go_to(d5);
+        assert_eq!(names(&tree.moves()), vec!["d2d4", "d7d5"]);
+
+        let d4 = tree.node(d5).parent.unwrap();
+        tree.go_to(d4);
+        assert_eq!(names(&tree.moves()), vec!["d2d4"]);
+        assert_eq!(tree.game().history.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_variation() {
+        let mut tree = GameTree::new();
+        play(&mut tree, &["e2e4"]);
+        tree.undo();
+        let d4 = play(&mut tree, &["d2d4", "d7d5"]);
+        let d4 = tree.node(d4).parent.unwrap();
+
+        tree.remove_variation(d4);
+        assert_eq!(tree.current(), tree.root());
+        assert_eq!(tree.node(tree.root()).children.len(), 1);
+        assert_eq!(tree.redo().unwrap().to_string(), "e2e4");
+
+        // Ids of removed nodes are stale; going there leaves the cursor.
+        let e4 = tree.current();
+        assert!(!tree.go_to(d4));
+        assert!(!tree.go_to(tree.node(d4).children[0]));
+        assert_eq!(tree.current(), e4);
+        assert!(tree.go_to(tree.root()));
+    }
+
+    #[test]
+    fn test_comments_and_nags() {
+        let mut tree = GameTree::new();
+        let e4 = play(&mut tree, &["e2e4"]);
+
+        tree.set_comment(e4, "Best by test");
+        tree.add_nag(e4, 1);
+        tree.add_nag(e4, 1);
+        assert_eq!(tree.node(e4).comment.as_deref(), Some("Best by test"));
+        assert_eq!(tree.node(e4).nags, vec![1]);
+
+        tree.set_comment(e4, "");
+        tree.remove_nag(e4, 1);
+        assert_eq!(tree.node(e4).comment, None);
+        assert!(tree.node(e4).nags.is_empty());
+    }
+
+    #[test]
+    fn test_repetition_across_undo() {
+        let mut tree = GameTree::new();
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        play(&mut tree, &shuffle);
+        play(&mut tree, &shuffle);
+        assert_eq!(tree.game().outcome(), Some(Outcome::ThreefoldRepetition));
+
+        tree.undo();
+        assert_eq!(tree.game().outcome(), None);
+        tree.redo();
+        assert_eq!(tree.game().outcome(), Some(Outcome::ThreefoldRepetition));
+    }
+
+    #[test]
+    fn test_from_fen_keeps_starting_fen() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+        let tree = GameTree::from_fen(fen).unwrap();
+        assert_eq!(tree.starting_fen(), fen);
+        assert!(GameTree::from_fen("8/8/8/8/8/8/8/8 w - - 0 1").is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::utils::*;
+
+/// The squares a king attacks from each square, indexed a1 = 0.
+#[derive(Clone)]
+pub struct KingAttacks(pub Vec<Bitboard>);
+
+impl KingAttacks {
+    pub fn new() -> Self {
+        let mut attacks = vec![];
+
+        for row in 1..=8 {
+            for col in 1..=8 {
+                attacks.push(king_attacks(row, col));
+            }
+        }
+
+        Self(attacks)
+    }
+}
+
+impl Default for KingAttacks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn king_attacks(row: i32, col: i32) -> Bitboard {
+    let mut bitboard = 0;
+
+    for r in -1..=1 {
+        for c in -1..=1 {
+            if r != 0 || c != 0 {
+                bitboard |= set_bit(row + r, col + c);
+            }
+        }
+    }
+
+    bitboard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_king_attacks() {
+        let king_attacks = KingAttacks::new();
+        assert_eq!(king_attacks.0[0], 1 << 1 | 1 << 8 | 1 << 9);
+        assert_eq!(king_attacks.0[63], 1 << 62 | 1 << 55 | 1 << 54);
+        let expected_28 =
+            1 << 19 | 1 << 20 | 1 << 21 | 1 << 27 | 1 << 29 | 1 << 35 | 1 << 36 | 1 << 37;
+        assert_eq!(king_attacks.0[28], expected_28);
+    }
+}
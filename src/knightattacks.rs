@@ -2,6 +2,8 @@ use crate::utils::*;
 
 type Bitboard = u64;
 
+/// The squares a knight attacks from each square, indexed a1 = 0.
+#[derive(Clone)]
 pub struct KnightAttacks(pub Vec<Bitboard>);
 
 impl KnightAttacks {
@@ -19,6 +21,12 @@ impl KnightAttacks {
     }
 }
 
+impl Default for KnightAttacks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 fn knight_attacks(row: i32, col: i32) -> Bitboard {
     let attack_pairs = [
         (1, 2),
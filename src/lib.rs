@@ -0,0 +1,47 @@
+//! A chess move generator built on bitboards.
+//!
+//! [`Position`] holds the board and the FEN state and knows how to make and
+//! unmake a [`Move`]. [`Game`] pairs a position with the attack tables used
+//! to generate legal moves and detect check.
+//!
+//! ```
+//! use chess_engine::{perft::perft, Game};
+//!
+//! let mut game = Game::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+//!     .expect("valid FEN");
+//! let moves = game.legal_moves();
+//! assert_eq!(moves.len(), 20);
+//!
+//! let undo = game.position.make_move(moves[0]);
+//! assert_eq!(perft(&mut game, 1), 20);
+//! game.position.unmake_move(moves[0], undo);
+//! ```
+
+pub mod game;
+pub mod gametree;
+pub mod kingattacks;
+pub mod knightattacks;
+pub mod magicattacks;
+pub mod movegeneration;
+pub mod pawnattacks;
+pub mod perft;
+pub mod pgn;
+pub mod position;
+pub mod rayattacks;
+pub mod san;
+pub mod search;
+pub mod transpositiontable;
+pub mod uci;
+pub mod utils;
+pub mod xboard;
+pub mod zobrist;
+
+pub use game::{Game, Outcome};
+pub use gametree::{GameTree, Node, NodeId};
+pub use movegeneration::{generate_moves, generate_tactical_moves, king_is_in_check};
+pub use position::{
+    index_to_position, position_to_index, CastlingRights, Color, FenError, FenField, Move,
+    MoveFlags, Piece, PieceType, Position, Square, UciMoveError, Undo,
+};
+pub use san::{parse_san, to_san, SanError};
+pub use search::{search, SearchLimits, SearchResult};
@@ -0,0 +1,420 @@
+use crate::rayattacks::Rays;
+use crate::utils::*;
+use std::sync::OnceLock;
+
+/// Where the attack sets of one square live in the shared table: the
+/// relevant blockers are multiplied by `magic` and the top bits of the
+/// product select the entry.
+struct Magic {
+    mask: Bitboard,
+    magic: u64,
+    shift: u32,
+    offset: usize,
+}
+
+impl Magic {
+    fn index(&self, occupancy: Bitboard) -> usize {
+        self.offset + ((occupancy & self.mask).wrapping_mul(self.magic) >> self.shift) as usize
+    }
+}
+
+/// Rook and bishop attack sets for every square and every arrangement of
+/// blockers, looked up with magic multiplication.
+struct MagicAttacks {
+    rook_magics: Vec<Magic>,
+    bishop_magics: Vec<Magic>,
+    attacks: Vec<Bitboard>,
+}
+
+static MAGIC_ATTACKS: OnceLock<MagicAttacks> = OnceLock::new();
+
+fn magic_attacks() -> &'static MagicAttacks {
+    MAGIC_ATTACKS.get_or_init(MagicAttacks::new)
+}
+
+/// The squares a rook on `square` attacks, given the occupied squares. Own
+/// pieces are included; mask them out for moves.
+pub fn rook_attacks(square: usize, occupancy: Bitboard) -> Bitboard {
+    let tables = magic_attacks();
+    tables.attacks[tables.rook_magics[square].index(occupancy)]
+}
+
+/// The squares a bishop on `square` attacks, given the occupied squares.
+pub fn bishop_attacks(square: usize, occupancy: Bitboard) -> Bitboard {
+    let tables = magic_attacks();
+    tables.attacks[tables.bishop_magics[square].index(occupancy)]
+}
+
+/// `rook_attacks | bishop_attacks`.
+pub fn queen_attacks(square: usize, occupancy: Bitboard) -> Bitboard {
+    rook_attacks(square, occupancy) | bishop_attacks(square, occupancy)
+}
+
+const RANK_1: Bitboard = 0xff;
+const RANK_8: Bitboard = 0xff << 56;
+const FILE_A: Bitboard = 0x0101_0101_0101_0101;
+const FILE_H: Bitboard = FILE_A << 7;
+
+impl MagicAttacks {
+    fn new() -> Self {
+        let rays = Rays::new();
+        let mut attacks = vec![];
+
+        let rook_magics = (0..64)
+            .map(|square| {
+                let mask = rook_mask(&rays, square);
+                let magic = Magic {
+                    mask,
+                    magic: ROOK_MAGICS[square],
+                    shift: 64 - mask.count_ones(),
+                    offset: attacks.len(),
+                };
+                fill_table(&magic, &mut attacks, |occ| rays.rook_attacks(square, occ));
+                magic
+            })
+            .collect();
+
+        let bishop_magics = (0..64)
+            .map(|square| {
+                let mask = bishop_mask(&rays, square);
+                let magic = Magic {
+                    mask,
+                    magic: BISHOP_MAGICS[square],
+                    shift: 64 - mask.count_ones(),
+                    offset: attacks.len(),
+                };
+                fill_table(&magic, &mut attacks, |occ| rays.bishop_attacks(square, occ));
+                magic
+            })
+            .collect();
+
+        Self {
+            rook_magics,
+            bishop_magics,
+            attacks,
+        }
+    }
+}
+
+/// The squares whose occupancy can change a rook's attacks from `square`.
+/// The last square of each ray never blocks anything beyond it, so it is
+/// left out.
+fn rook_mask(rays: &Rays, square: usize) -> Bitboard {
+    (rays.n_rays[square] & !RANK_8)
+        | (rays.s_rays[square] & !RANK_1)
+        | (rays.e_rays[square] & !FILE_H)
+        | (rays.w_rays[square] & !FILE_A)
+}
+
+fn bishop_mask(rays: &Rays, square: usize) -> Bitboard {
+    let edges = RANK_1 | RANK_8 | FILE_A | FILE_H;
+    (rays.ne_rays[square] | rays.nw_rays[square] | rays.se_rays[square] | rays.sw_rays[square])
+        & !edges
+}
+
+/// Every subset of `mask`, enumerated with the carry-rippler trick.
+fn subsets(mask: Bitboard) -> Vec<Bitboard> {
+    let mut subsets = vec![];
+    let mut subset: Bitboard = 0;
+    loop {
+        subsets.push(subset);
+        subset = subset.wrapping_sub(mask) & mask;
+        if subset == 0 {
+            return subsets;
+        }
+    }
+}
+
+fn fill_table(
+    magic: &Magic,
+    attacks: &mut Vec<Bitboard>,
+    slow_attacks: impl Fn(Bitboard) -> Bitboard,
+) {
+    attacks.resize(magic.offset + (1 << magic.mask.count_ones()), 0);
+    for occupancy in subsets(magic.mask) {
+        let index = magic.index(occupancy);
+        let attack = slow_attacks(occupancy);
+        assert!(
+            attacks[index] == 0 || attacks[index] == attack,
+            "Magic {:#x} maps two different attack sets to entry {}",
+            magic.magic,
+            index - magic.offset
+        );
+        attacks[index] = attack;
+    }
+}
+
+const ROOK_MAGICS: [u64; 64] = [
+    0x4180_0080_20d4_c000,
+    0x0840_0020_0010_0040,
+    0x1200_0882_0040_2010,
+    0x4200_0410_c03a_0060,
+    0x3200_0820_a600_5014,
+    0x0a00_0826_0004_1011,
+    0x4100_0084_0200_4100,
+    0x0200_0401_0424_4082,
+    0x0101_8000_4000_8028,
+    0x2003_0040_0081_0022,
+    0x0002_0010_2042_0480,
+    0x2221_0009_0020_1001,
+    0x0200_8080_0800_0400,
+    0x1041_0002_0804_0100,
+    0x0404_00c8_0201_0410,
+    0x2240_8000_8000_4100,
+    0x0040_8080_0040_0030,
+    0x8000_8180_4000_2000,
+    0x0101_0500_2000_4014,
+    0x0080_8080_1000_0800,
+    0x0900_8280_0800_4400,
+    0x0000_8080_0200_0400,
+    0x0024_0400_0882_5001,
+    0x9800_0200_0844_890c,
+    0x1880_0048_4000_2000,
+    0x0020_1000_4000_2041,
+    0x4050_0020_2008_0402,
+    0x0000_0801_8010_0180,
+    0x0009_0011_0004_0800,
+    0x0000_0400_8002_0080,
+    0x8400_1854_0010_0a01,
+    0x1882_0042_0001_0084,
+    0x0040_8040_0080_0025,
+    0x0000_4000_8080_2004,
+    0x1020_0021_8180_1000,
+    0x680d_1120_4200_0a02,
+    0x1029_0008_0100_0410,
+    0x0102_8004_0080_0201,
+    0x0c04_1061_0400_0228,
+    0x2010_104a_8200_0423,
+    0x4000_9240_0021_8001,
+    0x2030_0040_2000_4014,
+    0x0104_1200_8042_0020,
+    0x0148_0800_1000_8080,
+    0x0410_0800_0501_0010,
+    0x1222_0030_0406_0008,
+    0x8000_0201_1814_0030,
+    0x1004_0c14_8142_0021,
+    0x0002_0040_8100_2200,
+    0x2801_0608_4820_8200,
+    0xc100_2000_8210_0880,
+    0x10d0_0080_0804_1180,
+    0x0100_8008_0004_0080,
+    0x2002_0200_8004_0080,
+    0x2aa0_1108_0230_8400,
+    0x0808_8411_1080_4200,
+    0x0524_2240_1080_0b01,
+    0x410a_2102_8040_0019,
+    0x0005_0008_4020_0011,
+    0x0010_0500_1000_0821,
+    0x0501_0010_0204_0801,
+    0x0011_002a_a804_0013,
+    0x0006_0008_5500_8c06,
+    0x0001_0000_8208_3041,
+];
+
+const BISHOP_MAGICS: [u64; 64] = [
+    0x0002_0831_0800_8100,
+    0x4020_020a_6047_0000,
+    0x0108_2821_0020_1009,
+    0x4011_0400_8800_2130,
+    0x0101_1040_0000_ac00,
+    0x0002_0812_4900_0080,
+    0x4201_0818_0424_0000,
+    0x4002_0041_0401_2041,
+    0x0000_4032_0801_1301,
+    0x000c_1822_2802_0124,
+    0x4000_0414_0414_4040,
+    0x0120_5104_0082_0010,
+    0x0004_0404_2000_1140,
+    0x8000_4082_2020_0121,
+    0x2201_40b2_0802_4000,
+    0x0411_0600_8404_0308,
+    0x1408_0020_88d0_0080,
+    0x0020_c418_024c_0040,
+    0x000c_2208_0722_2200,
+    0x4414_8018_0208_408c,
+    0x0002_0024_1202_0300,
+    0x08e1_0000_8060_0200,
+    0x8008_8041_0848_0202,
+    0x1232_0001_8484_4100,
+    0x0090_0800_1020_8110,
+    0x8012_8200_2024_0414,
+    0x0002_0200_4108_0200,
+    0xa040_0400_0602_0908,
+    0x0180_4040_2c01_0044,
+    0x0010_0900_1024_0100,
+    0x2004_8280_0908_2800,
+    0x0302_0084_0044_4804,
+    0x1488_04c0_0085_0808,
+    0x8004_1004_0003_2400,
+    0x0802_0a05_0022_0800,
+    0x0034_0401_081c_0100,
+    0x0140_5080_2002_0200,
+    0xa010_1000_4100_2400,
+    0x8605_4602_0111_9800,
+    0xa209_0042_1000_8a00,
+    0x008a_0904_4041_2202,
+    0x2020_9801_1025_08a4,
+    0x4400_0844_1000_0200,
+    0x000a_40c2_0081_580c,
+    0x000e_e0a0_0882_2900,
+    0x0b40_8080_8080_0500,
+    0x0220_0154_0090_0114,
+    0x0942_2801_0223_8904,
+    0x4001_0401_4440_8000,
+    0x00c0_2402_1884_0000,
+    0x0981_b820_9410_0018,
+    0x4020_c012_0a02_0450,
+    0x8004_0204_4504_0821,
+    0x0000_6024_104c_9000,
+    0x5805_5002_0821_1100,
+    0x0030_8102_0482_0002,
+    0xe019_0080_80a0_1000,
+    0x0000_4282_2809_0400,
+    0x9800_0900_3402_0800,
+    0x0032_9200_0084_0400,
+    0x1008_1014_2102_4412,
+    0x1409_6820_2491_3a08,
+    0x0000_2002_1052_4080,
+    0x0468_1000_a081_0208,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::square_to_index;
+
+    /// Searches for a magic that maps every subset of `mask` to an entry
+    /// holding its attack set. This is how `ROOK_MAGICS` and `BISHOP_MAGICS`
+    /// were found.
+    fn find_magic(
+        mask: Bitboard,
+        slow_attacks: impl Fn(Bitboard) -> Bitboard,
+        seed: &mut u64,
+    ) -> u64 {
+        let occupancies = subsets(mask);
+        let attacks = occupancies
+            .iter()
+            .map(|&occ| slow_attacks(occ))
+            .collect::<Vec<Bitboard>>();
+        let shift = 64 - mask.count_ones();
+        let mut table = vec![0; occupancies.len()];
+
+        loop {
+            // Magics with few set bits tend to work best.
+            let magic = xorshift(seed) & xorshift(seed) & xorshift(seed);
+            if (mask.wrapping_mul(magic) >> 56).count_ones() < 6 {
+                continue;
+            }
+
+            table.fill(0);
+            let collides = occupancies.iter().zip(&attacks).any(|(&occ, &attack)| {
+                let index = (occ.wrapping_mul(magic) >> shift) as usize;
+                if table[index] == 0 {
+                    table[index] = attack;
+                }
+                table[index] != attack
+            });
+            if !collides {
+                return magic;
+            }
+        }
+    }
+
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    fn random_occupancy(seed: &mut u64) -> Bitboard {
+        xorshift(seed) & xorshift(seed)
+    }
+
+    #[test]
+    fn test_rook_attacks_match_rays() {
+        let rays = Rays::new();
+        let mut seed = 0x5EED_1234_ABCD_0001;
+        for square in 0..64 {
+            for _ in 0..200 {
+                let occupancy = random_occupancy(&mut seed);
+                assert_eq!(
+                    rook_attacks(square, occupancy),
+                    rays.rook_attacks(square, occupancy),
+                    "square {} occupancy {:#x}",
+                    square,
+                    occupancy
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bishop_attacks_match_rays() {
+        let rays = Rays::new();
+        let mut seed = 0x5EED_1234_ABCD_0002;
+        for square in 0..64 {
+            for _ in 0..200 {
+                let occupancy = random_occupancy(&mut seed);
+                assert_eq!(
+                    bishop_attacks(square, occupancy),
+                    rays.bishop_attacks(square, occupancy),
+                    "square {} occupancy {:#x}",
+                    square,
+                    occupancy
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_rook_attacks_stop_at_blockers() {
+        let occupancy = position_to_bit_unchecked(&["d6", "f4", "d2", "b4"]);
+        assert_eq!(
+            rook_attacks(square_to_index("d4"), occupancy),
+            position_to_bit_unchecked(&["d5", "d6", "e4", "f4", "d3", "d2", "c4", "b4"])
+        );
+    }
+
+    #[test]
+    fn test_bishop_attacks_on_empty_board() {
+        assert_eq!(
+            bishop_attacks(square_to_index("a1"), 0),
+            position_to_bit_unchecked(&["b2", "c3", "d4", "e5", "f6", "g7", "h8"])
+        );
+    }
+
+    fn position_to_bit_unchecked(squares: &[&str]) -> Bitboard {
+        squares.iter().map(|sq| 1 << square_to_index(sq)).sum()
+    }
+
+    #[test]
+    fn test_find_magic() {
+        let rays = Rays::new();
+        let mut seed = 0x1234_5678_9ABC_DEF1;
+        for square in [square_to_index("e4"), square_to_index("a1")] {
+            let rook = |occ| rays.rook_attacks(square, occ);
+            let bishop = |occ| rays.bishop_attacks(square, occ);
+            let masks: [(Bitboard, &dyn Fn(Bitboard) -> Bitboard); 2] = [
+                (rook_mask(&rays, square), &rook),
+                (bishop_mask(&rays, square), &bishop),
+            ];
+
+            for (mask, slow_attacks) in masks {
+                let magic = Magic {
+                    mask,
+                    magic: find_magic(mask, slow_attacks, &mut seed),
+                    shift: 64 - mask.count_ones(),
+                    offset: 0,
+                };
+                let mut attacks = vec![];
+                fill_table(&magic, &mut attacks, slow_attacks);
+
+                // Blockers may share an entry only if they attack the same.
+                for occupancy in subsets(mask) {
+                    assert_eq!(attacks[magic.index(occupancy)], slow_attacks(occupancy));
+                }
+            }
+        }
+    }
+}
@@ -1,64 +1,81 @@
-mod knightattacks;
-mod movegeneration;
-mod pawnattacks;
-mod position;
-mod rayattacks;
-mod utils;
+use chess_engine::perft;
+use chess_engine::uci::Uci;
+use chess_engine::xboard::XBoard;
+use chess_engine::Game;
+use std::io::{self, BufRead};
 
-use knightattacks::KnightAttacks;
-use position::*;
-use rayattacks::Rays;
-use utils::*;
-
-pub struct Game {
-    position: Position,
-    knight_attacks: KnightAttacks,
-    ray_attacks: Rays,
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|s| s.as_str()) {
+        Some("perft") => run_perft(&args[1..], false),
+        Some("divide") => run_perft(&args[1..], true),
+        Some("xboard") => run_engine(true),
+        _ => run_engine(false),
+    }
 }
 
-impl Game {
-    fn new() -> Self {
-        Self {
-            position: Position::new(),
-            knight_attacks: KnightAttacks::new(),
-            ray_attacks: Rays::new(),
+/// `perft <depth> [fen]` and `divide <depth> [fen]`, defaulting to the
+/// start position.
+fn run_perft(args: &[String], divide: bool) {
+    // Below the root there is nothing to divide, so divide needs a depth of 1.
+    let depth = match args.first().map(|d| d.parse::<usize>()) {
+        Some(Ok(depth)) if depth > 0 || !divide => depth,
+        _ => {
+            eprintln!("usage: chess_engine perft|divide <depth> [fen]");
+            std::process::exit(1);
         }
-    }
+    };
 
-    #[allow(non_snake_case)]
-    fn read_FEN(fen: &str) -> Self {
-        Self {
-            position: Position::read_FEN(fen),
-            knight_attacks: KnightAttacks::new(),
-            ray_attacks: Rays::new(),
+    let mut game = if args.len() > 1 {
+        match Game::from_fen(&args[1..].join(" ")) {
+            Ok(game) => game,
+            Err(err) => {
+                eprintln!("invalid FEN: {}", err);
+                std::process::exit(1);
+            }
         }
-    }
+    } else {
+        Game::new()
+    };
 
-    fn empty() -> Self {
-        Self {
-            position: Position::empty(),
-            knight_attacks: KnightAttacks::new(),
-            ray_attacks: Rays::new(),
+    let start = std::time::Instant::now();
+    let nodes = if divide {
+        let divided = perft::divide(&mut game, depth);
+        for (m, nodes) in &divided {
+            println!("{}: {}", m, nodes);
         }
-    }
+        println!();
+        divided.iter().map(|(_, nodes)| nodes).sum()
+    } else {
+        perft::perft(&mut game, depth)
+    };
 
-    fn add(mut self, piece_color: Color, piece_type: PieceType, square: &str) -> Self {
-        self.position.add(piece_color, piece_type, square);
-        self
-    }
+    println!("Nodes searched: {}", nodes);
+    eprintln!("Time: {} ms", start.elapsed().as_millis());
 }
 
-fn main() {
-    let fen_str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
-    let not_alot = "8/8/8/4N3/8/8/8/8 w - - 0 1";
-    let not_alot2 = "8/8/8/4N3/2N5/8/8/8 w - - 0 1";
-    let game = Position::read_FEN(not_alot2);
+/// Speaks UCI on stdin and stdout until `quit`, or XBoard if asked to or
+/// if that is what the GUI starts with.
+fn run_engine(xboard: bool) {
+    let mut input = io::stdin().lock();
+    let mut first = String::new();
 
-    let (first_row, rest) = split_on(fen_str, ' ');
-    println!("First: {}, second: {}", first_row, rest);
-    println!("{}", game.to_string());
-    println!(
-        "{:?}, {:?}, {}",
-        game.active_color, game.en_passant, game.fullmove_number
-    );
+    let result = input.read_line(&mut first).and_then(|_| {
+        if xboard || first.trim() == "xboard" {
+            let mut xboard = XBoard::new(io::stdout());
+            xboard.handle(&first);
+            xboard.run(input)
+        } else {
+            let mut uci = Uci::new(io::stdout());
+            match uci.handle(&first) {
+                true => uci.run(input),
+                false => Ok(()),
+            }
+        }
+    });
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
 }
@@ -1,403 +1,426 @@
-#[allow(unused_imports)]
-use crate::knightattacks::*;
+use crate::game::Game;
+use crate::magicattacks::{bishop_attacks, queen_attacks, rook_attacks};
+use crate::pawnattacks::*;
 use crate::position::PieceType::*;
 use crate::position::*;
-use crate::rayattacks::*;
-use crate::utils::{bit_scan, extract_bits, index, rowcol};
-use crate::Game;
+use crate::utils::{bit_scan, extract_bits, Bitboard};
+
+/// The legal moves for the side to move.
+///
+/// Rather than trying each move and looking for check afterwards, this works
+/// out up front which pieces give check and which are pinned. With two
+/// checkers only the king can move; with one, every other move has to take
+/// the checker or block it; a pinned piece has to stay on the line of its
+/// pin.
+pub fn generate_moves(game: &Game) -> Vec<Move> {
+    generate_legal_moves(game, false)
+}
 
-fn generate_moves(game: &Game) -> Vec<Position> {
-    let mut new_positions = vec![];
+/// The legal moves that change the material on the board, for the
+/// quiescence search: captures and promotions to a queen. In check, every
+/// legal move is returned instead, since all of them are needed to tell
+/// whether the side to move is mated.
+pub fn generate_tactical_moves(game: &Game) -> Vec<Move> {
+    generate_legal_moves(game, true)
+}
 
-    for piece in &game.position.pieces {
-        if piece.color == game.position.active_color {
-            let positions;
-            match &piece.piece_type {
-                Knight => {
-                    positions = generate_knight_moves(&piece, &game);
-                }
-                Bishop => {
-                    positions = generate_bishop_moves(&piece, &game);
-                }
-                Rook => {
-                    positions = generate_rook_moves(&piece, &game);
-                }
-                Queen => {
-                    positions = generate_queen_moves(&piece, &game);
-                }
-                King => {
-                    positions = generate_king_moves(&piece, &game);
-                }
-                Pawn => {
-                    positions = generate_pawn_moves(&piece, &game);
-                }
-            }
+fn generate_legal_moves(game: &Game, tactical: bool) -> Vec<Move> {
+    let position = &game.position;
+    let color = position.active_color;
+    let enemy = color.opposite();
+    let king = position.bitboard(color, King);
+    assert!(king != 0, "The king was missing");
+    let king_square = bit_scan(king);
+    let occupancy = position.white_occupancy | position.black_occupancy;
+
+    let checkers = game.attackers_to(king_square, enemy, occupancy);
+    let tactical = tactical && checkers == 0;
+
+    // The king may not step along the line of a slider checking it, so it
+    // has to be taken off the board when looking at its target squares.
+    let mut moves = generate_king_moves(position.piece_at(king_square).unwrap(), game)
+        .into_iter()
+        .filter(|m| !tactical || m.is_capture())
+        .filter(|m| m.is_castle() || game.attackers_to(m.to(), enemy, occupancy & !king) == 0)
+        .collect::<Vec<Move>>();
+
+    let check_mask = match checkers.count_ones() {
+        0 => !0,
+        1 => checkers | game.ray_attacks.between(king_square, bit_scan(checkers)),
+        _ => return moves,
+    };
+    let pinned = pinned_pieces(game, king_square, color);
 
-            for pos in positions {
-                if king_is_in_check(
-                    &pos,
-                    game.position.active_color,
-                    &game.ray_attacks,
-                    &game.knight_attacks,
-                ) {
-                    continue;
-                }
-                new_positions.push(pos);
-            }
+    let candidates = if tactical {
+        generate_pseudo_legal_tactical_moves(game)
+    } else {
+        generate_pseudo_legal_moves(game, false)
+    };
+    for m in candidates {
+        let is_legal = if m.is_en_passant() {
+            en_passant_is_legal(game, m, king_square)
+        } else {
+            (1 << m.to()) & check_mask != 0
+                && ((1 << m.from()) & pinned == 0
+                    || (1 << m.to()) & game.ray_attacks.line(king_square, m.from()) != 0)
+        };
+
+        if is_legal {
+            moves.push(m);
         }
     }
 
-    new_positions
+    moves
 }
 
-macro_rules! check_if_attacked {
-    ($rays:expr, $forward:expr, $king:ident, $position:ident, $types:expr) => {{
-        let (own_occupancy, enemy_occupancy) = match $king.color {
-            Color::White => ($position.white_occupancy, $position.black_occupancy),
-            Color::Black => ($position.black_occupancy, $position.white_occupancy),
-        };
+/// The legal moves found the slow way: every pseudo-legal move is made and
+/// kept if it does not leave the own king in check. Kept as a reference for
+/// `generate_moves`.
+pub fn generate_moves_by_filtering(game: &mut Game) -> Vec<Move> {
+    let color = game.position.active_color;
+    let mut legal_moves = vec![];
 
-        let ray_attacks = blocked_ray_attack(
-            $rays[bit_scan($king.position)],
-            &$rays,
-            $forward,
-            own_occupancy,
-            enemy_occupancy,
-        );
+    for m in generate_pseudo_legal_moves(game, true) {
+        let undo = game.position.make_move(m);
+        let in_check = king_is_in_check(game, color);
+        game.position.unmake_move(m, undo);
 
-        let overlap = ray_attacks & enemy_occupancy;
-        if overlap == 0 {
-            false
-        } else {
-            let locations = extract_bits(overlap);
-            let mut is_attacked = false;
-            for loc in locations {
-                let square = $position.squares[loc];
-                match square {
-                    Square::Empty => panic!("Something has gone wrong"),
-                    Square::Occupied(pidx) => {
-                        let attacker = $position.pieces[pidx];
-                        assert!(attacker.alive);
-                        for atype in $types {
-                            is_attacked |= attacker.piece_type == atype;
-                            if is_attacked {
-                                break;
-                            }
-                        }
-                        if is_attacked {
-                            break;
-                        }
-                    }
-                }
-            }
-            is_attacked
+        if !in_check {
+            legal_moves.push(m);
         }
-    }};
+    }
+
+    legal_moves
 }
 
-fn king_is_in_check(
-    position: &Position,
-    color: Color,
-    ray_attacks: &Rays,
-    knight_attacks: &KnightAttacks,
-) -> bool {
-    let enemy_color = color.opposite();
-    // let ray_attacks = &game.ray_attacks;
-
-    let king = position
-        .pieces
-        .iter()
-        .find(|p| p.piece_type == King && p.color == color)
-        .expect("The king was missing");
-
-    macro_rules! check {
-        ($rays:expr, $forward:expr, $types:expr) => {
-            check_if_attacked!($rays, $forward, king, position, $types)
-        };
-    }
+fn generate_pseudo_legal_moves(game: &Game, with_king: bool) -> Vec<Move> {
+    let color = game.position.active_color;
+    let mut moves = vec![];
 
-    let is_ray_attacked = check!(ray_attacks.n_rays, true, vec![Queen, Rook])
-        || check!(ray_attacks.ne_rays, true, vec![Queen, Bishop])
-        || check!(ray_attacks.e_rays, true, vec![Queen, Rook])
-        || check!(ray_attacks.se_rays, false, vec![Queen, Bishop])
-        || check!(ray_attacks.s_rays, false, vec![Queen, Rook])
-        || check!(ray_attacks.sw_rays, false, vec![Queen, Bishop])
-        || check!(ray_attacks.w_rays, false, vec![Queen, Rook])
-        || check!(ray_attacks.nw_rays, true, vec![Queen, Bishop]);
-    if is_ray_attacked {
-        return true;
+    for piece in &game.position.pieces {
+        if piece.alive && piece.color == color {
+            match &piece.piece_type {
+                Knight => moves.extend(generate_knight_moves(piece, game)),
+                Bishop => moves.extend(generate_bishop_moves(piece, game)),
+                Rook => moves.extend(generate_rook_moves(piece, game)),
+                Queen => moves.extend(generate_queen_moves(piece, game)),
+                King if with_king => moves.extend(generate_king_moves(piece, game)),
+                King | Pawn => (),
+            }
+        }
     }
+    moves.extend(generate_all_pawn_moves(game));
 
-    let knight_attacks = knight_attacks.0[bit_scan(king.position)];
-    let enemy_occupancy = match king.color {
-        Color::White => position.black_occupancy,
-        Color::Black => position.white_occupancy,
-    };
+    moves
+}
 
-    let overlap = knight_attacks & enemy_occupancy;
-    if overlap != 0 {
-        let positions = extract_bits(overlap);
-        for pos in positions {
-            let square = position.squares[pos];
-            match square {
-                Square::Empty => panic!("Empty square, but expected occupied"),
-                Square::Occupied(pidx) => {
-                    let attacker = position.pieces[pidx];
-                    if attacker.piece_type == Knight {
-                        return true;
-                    }
-                }
-            }
+/// The captures and queen promotions of everything but the king, found
+/// from the attack tables restricted to enemy pieces rather than by
+/// generating every move and throwing the quiet ones away.
+fn generate_pseudo_legal_tactical_moves(game: &Game) -> Vec<Move> {
+    let position = &game.position;
+    let color = position.active_color;
+    let enemy_occupancy = position.occupancy(color.opposite());
+    let occupancy = position.white_occupancy | position.black_occupancy;
+    let mut moves = vec![];
+
+    for piece in &game.position.pieces {
+        if !piece.alive || piece.color != color {
+            continue;
+        }
+
+        let from = bit_scan(piece.position);
+        let attacks = match piece.piece_type {
+            Knight => game.knight_attacks.0[from],
+            Bishop => bishop_attacks(from, occupancy),
+            Rook => rook_attacks(from, occupancy),
+            Queen => queen_attacks(from, occupancy),
+            King | Pawn => continue,
+        };
+        for to in extract_bits(attacks & enemy_occupancy) {
+            moves.push(Move::new(from, to, MoveFlags::CAPTURE));
         }
     }
 
-    let (row, col) = rowcol(bit_scan(king.position));
-    let direction = match king.color {
-        Color::White => 1,
-        Color::Black => -1,
+    let pawns = position.bitboard(color, Pawn);
+    let forward = match color {
+        Color::White => 8,
+        Color::Black => -8,
     };
 
-    for row_offset in -1..=1 {
-        for col_offset in -1..=1 {
-            let new_row = row + row_offset;
-            let new_col = col + col_offset;
-            if let Some(idx) = index(new_row, new_col) {
-                match position.squares[idx] {
-                    Square::Empty => (),
-                    Square::Occupied(pidx) => {
-                        let piece = position.pieces[pidx];
-                        if piece.color != king.color {
-                            if piece.piece_type == King {
-                                return true;
-                            }
-                            if (new_col - col).abs() == 1 && (new_row - row) == direction {
-                                if piece.piece_type == Pawn {
-                                    return true;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    for to in extract_bits(push_all(pawns, color) & !occupancy & PROMOTION_RANKS) {
+        moves.push(Move::with_promotion(
+            offset(to, -forward),
+            to,
+            Queen,
+            MoveFlags::QUIET,
+        ));
+    }
+
+    let captures = [
+        (east_attacks(pawns, color), -forward - 1),
+        (west_attacks(pawns, color), -forward + 1),
+    ];
+    for (targets, back) in captures {
+        for to in extract_bits(targets & enemy_occupancy) {
+            let from = offset(to, back);
+            moves.push(if (1 << to) & PROMOTION_RANKS != 0 {
+                Move::with_promotion(from, to, Queen, MoveFlags::CAPTURE)
+            } else {
+                Move::new(from, to, MoveFlags::CAPTURE)
+            });
         }
     }
 
-    false
+    add_en_passant_moves(&mut moves, game);
+
+    moves
 }
 
-fn generate_knight_moves(piece: &Piece, game: &Game) -> Vec<Position> {
-    let mut attacks = game.knight_attacks.0[bit_scan(piece.position)];
+/// The pieces of `color` that are the only thing standing between their king
+/// and an enemy slider.
+fn pinned_pieces(game: &Game, king_square: usize, color: Color) -> Bitboard {
     let position = &game.position;
-
-    let own_occupancy = match piece.color {
-        Color::White => position.white_occupancy,
-        Color::Black => position.black_occupancy,
-    };
-    attacks &= !own_occupancy;
-
-    let potential_moves = extract_bits(attacks);
-    let mut new_positions = vec![];
-    for pmove in potential_moves {
-        let mut new_position = (*game).position.clone();
-        new_position.move_piece(piece.position, pmove);
-        new_positions.push(new_position);
+    let enemy = color.opposite();
+    let occupancy = position.white_occupancy | position.black_occupancy;
+    let queens = position.bitboard(enemy, Queen);
+    let snipers = (rook_attacks(king_square, 0) & (position.bitboard(enemy, Rook) | queens))
+        | (bishop_attacks(king_square, 0) & (position.bitboard(enemy, Bishop) | queens));
+
+    let mut pinned = 0;
+    for sniper in extract_bits(snipers) {
+        let blockers = game.ray_attacks.between(king_square, sniper) & occupancy;
+        if blockers.count_ones() == 1 {
+            pinned |= blockers & position.occupancy(color);
+        }
     }
 
-    new_positions
+    pinned
 }
 
-macro_rules! get_attacks {
-    ($rays:expr, $forward:expr, $positions:ident, $piece:ident, $game:ident) => {
-        let (own_occupancy, enemy_occupancy) = match $piece.color {
-            Color::White => (
-                $game.position.white_occupancy,
-                $game.position.black_occupancy,
-            ),
-            Color::Black => (
-                $game.position.black_occupancy,
-                $game.position.white_occupancy,
-            ),
-        };
+/// En passant moves two pawns at once, so instead of reasoning about pins
+/// and check masks the board after the capture is checked directly.
+fn en_passant_is_legal(game: &Game, m: Move, king_square: usize) -> bool {
+    let position = &game.position;
+    let color = position.active_color;
+    let captured = m.from() / 8 * 8 + m.to() % 8;
+    let occupancy = (position.white_occupancy | position.black_occupancy)
+        & !(1 << m.from() | 1 << captured)
+        | 1 << m.to();
 
-        let ray_attacks = blocked_ray_attack(
-            $rays[bit_scan($piece.position)],
-            &$rays,
-            $forward,
-            own_occupancy,
-            enemy_occupancy,
-        );
-        let potential_moves = extract_bits(ray_attacks);
+    game.attackers_to(king_square, color.opposite(), occupancy) == 0
+}
 
-        for pmove in potential_moves {
-            let mut new_position = (*$game).position.clone();
-            new_position.move_piece($piece.position, pmove);
-            $positions.push(new_position);
-        }
+/// A move to `to`, flagged as a capture if an enemy piece stands there.
+fn quiet_or_capture(from: usize, to: usize, enemy_occupancy: Bitboard) -> Move {
+    let flags = if (1 << to) & enemy_occupancy != 0 {
+        MoveFlags::CAPTURE
+    } else {
+        MoveFlags::QUIET
     };
+
+    Move::new(from, to, flags)
 }
 
-fn generate_bishop_moves(piece: &Piece, game: &Game) -> Vec<Position> {
-    let attacks = &game.ray_attacks;
+/// Whether the king of `color` is attacked.
+pub fn king_is_in_check(game: &Game, color: Color) -> bool {
     let position = &game.position;
-    let mut new_positions = vec![];
-
-    get_attacks!(attacks.nw_rays, true, new_positions, piece, game);
-    get_attacks!(attacks.sw_rays, false, new_positions, piece, game);
-    get_attacks!(attacks.ne_rays, true, new_positions, piece, game);
-    get_attacks!(attacks.se_rays, false, new_positions, piece, game);
+    let king = position.bitboard(color, King);
+    assert!(king != 0, "The king was missing");
 
-    new_positions
+    let occupancy = position.white_occupancy | position.black_occupancy;
+    game.attackers_to(bit_scan(king), color.opposite(), occupancy) != 0
 }
 
-fn generate_rook_moves(piece: &Piece, game: &Game) -> Vec<Position> {
-    let attacks = &game.ray_attacks;
+fn generate_knight_moves(piece: &Piece, game: &Game) -> Vec<Move> {
+    let from = bit_scan(piece.position);
+    let mut attacks = game.knight_attacks.0[from];
     let position = &game.position;
 
     let (own_occupancy, enemy_occupancy) = match piece.color {
         Color::White => (position.white_occupancy, position.black_occupancy),
         Color::Black => (position.black_occupancy, position.white_occupancy),
     };
-    let mut new_positions = vec![];
-
-    get_attacks!(attacks.n_rays, true, new_positions, piece, game);
-    get_attacks!(attacks.s_rays, false, new_positions, piece, game);
-    get_attacks!(attacks.e_rays, true, new_positions, piece, game);
-    get_attacks!(attacks.w_rays, false, new_positions, piece, game);
+    attacks &= !own_occupancy;
 
-    new_positions
+    extract_bits(attacks)
+        .into_iter()
+        .map(|to| quiet_or_capture(from, to, enemy_occupancy))
+        .collect()
 }
 
-fn generate_queen_moves(piece: &Piece, game: &Game) -> Vec<Position> {
-    let bishop_moves = generate_bishop_moves(piece, game);
-    let mut rook_moves = generate_rook_moves(piece, game);
+/// Moves for a rook, bishop or queen, whose attacks `attacks` looks up.
+fn generate_slider_moves(
+    piece: &Piece,
+    game: &Game,
+    attacks: fn(usize, Bitboard) -> Bitboard,
+) -> Vec<Move> {
+    let from = bit_scan(piece.position);
+    let position = &game.position;
+    let own_occupancy = position.occupancy(piece.color);
+    let enemy_occupancy = position.occupancy(piece.color.opposite());
 
-    rook_moves.extend(bishop_moves);
+    let targets = attacks(from, own_occupancy | enemy_occupancy) & !own_occupancy;
 
-    rook_moves
+    extract_bits(targets)
+        .into_iter()
+        .map(|to| quiet_or_capture(from, to, enemy_occupancy))
+        .collect()
 }
 
-fn generate_king_moves(piece: &Piece, game: &Game) -> Vec<Position> {
-    let position_index = bit_scan(piece.position);
-    let row = (position_index / 8) as i32;
-    let col = (position_index % 8) as i32;
+fn generate_bishop_moves(piece: &Piece, game: &Game) -> Vec<Move> {
+    generate_slider_moves(piece, game, bishop_attacks)
+}
 
-    let own_occupancy = match piece.color {
-        Color::White => game.position.white_occupancy,
-        Color::Black => game.position.black_occupancy,
-    };
+fn generate_rook_moves(piece: &Piece, game: &Game) -> Vec<Move> {
+    generate_slider_moves(piece, game, rook_attacks)
+}
 
-    let mut potential_moves = vec![];
-    let mut moves_looked_at = 0;
-    for row_offset in -1..=1 {
-        for col_offset in -1..=1 {
-            if row_offset == 0 && col_offset == 0 {
-                continue;
-            }
-            let new_row = row + row_offset;
-            let new_col = col + col_offset;
-            moves_looked_at += 1;
+fn generate_queen_moves(piece: &Piece, game: &Game) -> Vec<Move> {
+    generate_slider_moves(piece, game, queen_attacks)
+}
 
-            if new_row >= 0 && new_row < 8 && new_col >= 0 && new_col < 8 {
-                let position_index = (new_row * 8 + new_col) as usize;
-                let position_bit = 1 << position_index;
+fn generate_king_moves(piece: &Piece, game: &Game) -> Vec<Move> {
+    let from = bit_scan(piece.position);
+    let own_occupancy = game.position.occupancy(piece.color);
+    let enemy_occupancy = game.position.occupancy(piece.color.opposite());
 
-                if position_bit & own_occupancy == 0 {
-                    potential_moves.push(position_index);
-                }
-            }
-        }
-    }
+    let mut moves = extract_bits(game.king_attacks.0[from] & !own_occupancy)
+        .into_iter()
+        .map(|to| quiet_or_capture(from, to, enemy_occupancy))
+        .collect::<Vec<Move>>();
 
-    let mut positions = vec![];
-    for pmove in potential_moves {
-        let mut new_position = (*game).position.clone();
-        new_position.move_piece(piece.position, pmove);
-        positions.push(new_position);
-    }
+    moves.extend(generate_castling_moves(piece, game));
 
-    positions
+    moves
 }
 
-fn generate_pawn_moves(piece: &Piece, game: &Game) -> Vec<Position> {
-    // Consider all possible moves (en passant is possible if the en passant field in the position is set)
-    let position_index = bit_scan(piece.position);
-    let (row, col) = rowcol(position_index);
-
-    let (own_occupancy, enemy_occupancy) = match piece.color {
-        Color::White => (game.position.white_occupancy, game.position.black_occupancy),
-        Color::Black => (game.position.black_occupancy, game.position.white_occupancy),
+fn generate_castling_moves(piece: &Piece, game: &Game) -> Vec<Move> {
+    let position = &game.position;
+    let (kingside, queenside, back_rank) = match piece.color {
+        Color::White => (
+            CastlingRights::WHITEKINGSIDE,
+            CastlingRights::WHITEQUEENSIDE,
+            0,
+        ),
+        Color::Black => (
+            CastlingRights::BLACKKINGSIDE,
+            CastlingRights::BLACKQUEENSIDE,
+            56,
+        ),
     };
 
-    let (direction, last_row) = match piece.color {
-        Color::White => (1, 7),
-        Color::Black => (-1, 2),
+    let king_square = back_rank + 4;
+    if bit_scan(piece.position) != king_square {
+        return vec![];
+    }
+
+    let occupancy = position.white_occupancy | position.black_occupancy;
+    let has_own_rook = |square: usize| {
+        matches!(position.piece_at(square),
+                 Some(p) if p.piece_type == Rook && p.color == piece.color)
+    };
+    let enemy = piece.color.opposite();
+    let is_attacked = |square: usize| game.attackers_to(square, enemy, occupancy) != 0;
+
+    let mut moves = vec![];
+
+    // The squares between king and rook must be empty, and the king may not
+    // start on, pass through or land on an attacked square.
+    let can_castle = |right: CastlingRights, rook: usize, between: &[usize], path: &[usize]| {
+        position.castling_rights.contains(right)
+            && has_own_rook(rook)
+            && between.iter().all(|&sq| (1 << sq) & occupancy == 0)
+            && !path.iter().any(|&sq| is_attacked(sq))
     };
 
-    let mut new_squares = vec![];
-    let idx = index(row + 1 * direction, col)
-        .expect("Pawn was somehow at the edge of the board and trying to move off");
-    let pos = 1 << idx;
-    if pos & enemy_occupancy == 0 && row != last_row {
-        new_squares.push(index(row + 1 * direction, col).unwrap());
+    let b = back_rank;
+    if can_castle(kingside, b + 7, &[b + 5, b + 6], &[b + 4, b + 5, b + 6]) {
+        moves.push(Move::new(king_square, b + 6, MoveFlags::CASTLE));
+    }
+    if can_castle(queenside, b, &[b + 1, b + 2, b + 3], &[b + 4, b + 3, b + 2]) {
+        moves.push(Move::new(king_square, b + 2, MoveFlags::CASTLE));
     }
 
-    if piece.color == Color::White && row == 2 {
-        let idx = index(row + 2, col).unwrap();
-        let pos = 1 << idx;
-        let idx = index(row + 1, col).unwrap();
-        let pos2 = 1 << idx;
-        if pos & enemy_occupancy == 0 && pos2 & enemy_occupancy == 0 {
-            new_squares.push(index(row + 2, col).unwrap());
-        }
+    moves
+}
+
+/// Every pawn move of the side to move, found by shifting the whole pawn
+/// bitboard at once instead of looking at one pawn at a time.
+fn generate_all_pawn_moves(game: &Game) -> Vec<Move> {
+    let position = &game.position;
+    let color = position.active_color;
+    let pawns = position.bitboard(color, Pawn);
+    let enemy_occupancy = position.occupancy(color.opposite());
+    let empty = !(position.white_occupancy | position.black_occupancy);
+    let (forward, double_push_rank) = match color {
+        Color::White => (8, RANK_3),
+        Color::Black => (-8, RANK_6),
+    };
+
+    let mut moves = vec![];
+
+    let single_pushes = push_all(pawns, color) & empty;
+    for to in extract_bits(single_pushes) {
+        add_pawn_moves(&mut moves, offset(to, -forward), to, MoveFlags::QUIET);
     }
 
-    if piece.color == Color::Black && row == 7 {
-        let idx = index(row - 2, col).unwrap();
-        let pos = 1 << idx;
-        let idx = index(row - 1, col).unwrap();
-        let pos2 = 1 << idx;
-        if pos & enemy_occupancy == 0 && pos2 & enemy_occupancy == 0 {
-            new_squares.push(index(row - 2, col).unwrap());
-        }
+    let double_pushes = push_all(single_pushes & double_push_rank, color) & empty;
+    for to in extract_bits(double_pushes) {
+        moves.push(Move::new(
+            offset(to, -2 * forward),
+            to,
+            MoveFlags::DOUBLE_PUSH,
+        ));
     }
 
-    if let Some(idx) = index(row + 1 * direction, col + 1) {
-        if (1 << idx as u64) & enemy_occupancy != 0 {
-            new_squares.push(idx);
-        }
+    for to in extract_bits(east_attacks(pawns, color) & enemy_occupancy) {
+        add_pawn_moves(&mut moves, offset(to, -forward - 1), to, MoveFlags::CAPTURE);
     }
-    if let Some(idx) = index(row + 1 * direction, col - 1) {
-        if (1 << idx as u64) & enemy_occupancy != 0 {
-            new_squares.push(idx);
-        }
+    for to in extract_bits(west_attacks(pawns, color) & enemy_occupancy) {
+        add_pawn_moves(&mut moves, offset(to, -forward + 1), to, MoveFlags::CAPTURE);
     }
 
-    let mut positions = vec![];
-    for pmove in new_squares {
-        let mut new_position = (*game).position.clone();
-        new_position.move_piece(piece.position, pmove);
-        positions.push(new_position);
-    }
+    add_en_passant_moves(&mut moves, game);
+
+    moves
+}
 
-    if row == last_row {
-        let new_pos_index = index(row + direction, col).expect("row+-1/col should be valid");
-        for tpe in [Queen, Bishop, Knight, Rook] {
-            let mut new_position = (*game).position.clone();
-            new_position.perform_promotion(piece.position, new_pos_index, tpe);
-            positions.push(new_position);
+/// Adds the en passant captures of the side to move, if any.
+fn add_en_passant_moves(moves: &mut Vec<Move>, game: &Game) {
+    let position = &game.position;
+    let color = position.active_color;
+    let pawns = position.bitboard(color, Pawn);
+
+    if let Some(square) = position.en_passant {
+        let to = bit_scan(square);
+        for from in extract_bits(game.pawn_attacks.attacks(color.opposite(), to) & pawns) {
+            moves.push(Move::new(
+                from,
+                to,
+                MoveFlags::CAPTURE | MoveFlags::EN_PASSANT,
+            ));
         }
     }
+}
 
-    if let Some(square) = game.position.en_passant {
-        let (ep_row, ep_col) = rowcol(bit_scan(square));
-        if (row + direction) == ep_row && (col - ep_col).abs() == 1 {
-            let mut new_position = (*game).position.clone();
-            new_position.take_en_passant(piece.position, square);
-            positions.push(new_position);
+/// Adds the pawn move `from`-`to`, expanded into the four promotions when it
+/// reaches the last rank.
+fn add_pawn_moves(moves: &mut Vec<Move>, from: usize, to: usize, flags: MoveFlags) {
+    if !(8..56).contains(&to) {
+        for promotion in [Queen, Bishop, Knight, Rook] {
+            moves.push(Move::with_promotion(from, to, promotion, flags));
         }
+    } else {
+        moves.push(Move::new(from, to, flags));
     }
+}
+
+/// The first and last ranks, where pawns promote.
+const PROMOTION_RANKS: Bitboard = 0xff | 0xff << 56;
 
-    positions
+fn offset(square: usize, offset: isize) -> usize {
+    (square as isize + offset) as usize
 }
 
 #[cfg(test)]
@@ -405,19 +428,26 @@ mod tests {
     use super::*;
     use crate::position::Color::*;
 
+    fn targets(moves: &[Move]) -> Vec<usize> {
+        moves.iter().map(|m| m.to()).collect()
+    }
+
+    fn squares(names: &[&str]) -> Vec<usize> {
+        names.iter().map(|s| square_to_index(s)).collect()
+    }
+
     #[test]
     fn test_generate_knight_moves() {
         let not_alot = "8/8/8/4N3/2N5/8/8/8 w - - 0 1";
         let game = Game::read_FEN(not_alot);
 
-        let positions = generate_knight_moves(&game.position.pieces[0], &game);
+        let moves = generate_knight_moves(&game.position.pieces[0], &game);
         let new_positions = [19, 21, 30, 42, 46, 51, 53];
-        assert_eq!(positions.len(), 7);
-        for pos in positions {
-            assert_eq!(pos.pieces.len(), 2);
-            let piece = &pos.pieces[0];
-            let index = bit_scan(piece.position);
-            assert!(new_positions.contains(&index));
+        assert_eq!(moves.len(), 7);
+        for m in moves {
+            assert_eq!(m.from(), 36);
+            assert!(new_positions.contains(&m.to()));
+            assert!(!m.is_capture());
         }
     }
 
@@ -426,14 +456,11 @@ mod tests {
         let not_alot = "8/5N2/8/4N3/2N5/8/8/8 w - - 0 1";
         let game = Game::read_FEN(not_alot);
 
-        let positions = generate_knight_moves(&game.position.pieces[1], &game);
+        let moves = generate_knight_moves(&game.position.pieces[1], &game);
         let new_positions = [19, 21, 30, 42, 46, 51];
-        assert_eq!(positions.len(), 6);
-        for pos in positions {
-            assert_eq!(pos.pieces.len(), 3);
-            let piece = &pos.pieces[1];
-            let index = bit_scan(piece.position);
-            assert!(new_positions.contains(&index));
+        assert_eq!(moves.len(), 6);
+        for to in targets(&moves) {
+            assert!(new_positions.contains(&to));
         }
     }
 
@@ -442,21 +469,12 @@ mod tests {
         let not_alot = "8/5n2/8/4N3/2N5/8/8/8 w - - 0 1";
         let game = Game::read_FEN(not_alot);
 
-        let positions = generate_knight_moves(&game.position.pieces[1], &game);
+        let moves = generate_knight_moves(&game.position.pieces[1], &game);
         let new_positions = [19, 21, 30, 42, 46, 51, 53];
-        assert_eq!(positions.len(), 7);
-        for pos in positions {
-            let piece;
-            if pos.pieces.len() == 3 {
-                piece = &pos.pieces[1];
-            } else if pos.pieces.len() == 2 {
-                piece = &pos.pieces[0];
-            } else {
-                panic!("Invalid number of pieces: {}", pos.pieces.len());
-            }
-            assert_eq!(piece.color, Color::White);
-            let index = bit_scan(piece.position);
-            assert!(new_positions.contains(&index));
+        assert_eq!(moves.len(), 7);
+        for m in moves {
+            assert!(new_positions.contains(&m.to()));
+            assert_eq!(m.is_capture(), m.to() == 53);
         }
     }
 
@@ -465,21 +483,11 @@ mod tests {
         let not_alot = "8/5n2/8/4N3/2n5/8/8/8 w - - 0 1";
         let game = Game::read_FEN(not_alot);
 
-        let positions = generate_knight_moves(&game.position.pieces[1], &game);
+        let moves = generate_knight_moves(&game.position.pieces[1], &game);
         let new_positions = [19, 21, 26, 30, 42, 46, 51, 53];
-        assert_eq!(positions.len(), 8);
-        for pos in positions {
-            let mut piece = &pos.pieces[0];
-            for pie in &pos.pieces {
-                if pie.color == Color::White {
-                    piece = pie;
-                    break;
-                }
-            }
-            assert!(pos.pieces.len() == 2 || pos.pieces.len() == 3);
-
-            let index = bit_scan(piece.position);
-            assert!(new_positions.contains(&index));
+        assert_eq!(moves.len(), 8);
+        for to in targets(&moves) {
+            assert!(new_positions.contains(&to));
         }
     }
 
@@ -488,21 +496,11 @@ mod tests {
         let fen = "7B/8/8/8/8/8/8/8 w - - 0 1";
         let game = Game::read_FEN(fen);
 
-        let positions = generate_bishop_moves(&game.position.pieces[0], &game);
+        let moves = generate_bishop_moves(&game.position.pieces[0], &game);
         let new_positions = [0, 9, 18, 27, 36, 45, 54];
-        assert_eq!(positions.len(), new_positions.len());
-        for pos in positions {
-            let mut piece = &pos.pieces[0];
-            for pie in &pos.pieces {
-                if pie.color == Color::White {
-                    piece = pie;
-                    break;
-                }
-            }
-            assert!(pos.pieces.len() == 1);
-
-            let index = bit_scan(piece.position);
-            assert!(new_positions.contains(&index));
+        assert_eq!(moves.len(), new_positions.len());
+        for to in targets(&moves) {
+            assert!(new_positions.contains(&to));
         }
     }
 
@@ -511,22 +509,12 @@ mod tests {
         let fen = "8/8/8/4B3/8/8/8/8 w - - 0 1";
         let game = Game::read_FEN(fen);
 
-        let positions = generate_bishop_moves(&game.position.pieces[0], &game);
+        let moves = generate_bishop_moves(&game.position.pieces[0], &game);
         let new_positions = [45, 54, 63, 27, 18, 9, 0, 43, 50, 57, 29, 22, 15];
 
-        // assert_eq!(positions.len(), new_positions.len());
-        for pos in positions {
-            let mut piece = &pos.pieces[0];
-            for pie in &pos.pieces {
-                if pie.color == Color::White {
-                    piece = pie;
-                    break;
-                }
-            }
-            assert!(pos.pieces.len() == 1);
-
-            let index = bit_scan(piece.position);
-            assert!(new_positions.contains(&index));
+        assert_eq!(moves.len(), new_positions.len());
+        for to in targets(&moves) {
+            assert!(new_positions.contains(&to));
         }
     }
 
@@ -535,16 +523,12 @@ mod tests {
         let fen = "8/8/5B2/4B3/8/8/8/8 w - - 0 1";
         let game = Game::read_FEN(fen);
 
-        let positions = generate_bishop_moves(&game.position.pieces[1], &game);
+        let moves = generate_bishop_moves(&game.position.pieces[1], &game);
         let new_positions = [27, 18, 9, 0, 43, 50, 57, 29, 22, 15];
 
-        // assert_eq!(positions.len(), new_positions.len());
-        for pos in positions {
-            let piece = &pos.pieces[1];
-            assert!(pos.pieces.len() == 2);
-
-            let index = bit_scan(piece.position);
-            assert!(new_positions.contains(&index));
+        assert_eq!(moves.len(), new_positions.len());
+        for to in targets(&moves) {
+            assert!(new_positions.contains(&to));
         }
     }
 
@@ -553,16 +537,12 @@ mod tests {
         let fen = "8/8/5b2/4b3/8/8/8/8 w - - 0 1";
         let game = Game::read_FEN(fen);
 
-        let positions = generate_bishop_moves(&game.position.pieces[1], &game);
+        let moves = generate_bishop_moves(&game.position.pieces[1], &game);
         let new_positions = [27, 18, 9, 0, 43, 50, 57, 29, 22, 15];
 
-        // assert_eq!(positions.len(), new_positions.len());
-        for pos in positions {
-            let piece = &pos.pieces[1];
-            assert!(pos.pieces.len() == 2);
-
-            let index = bit_scan(piece.position);
-            assert!(new_positions.contains(&index));
+        assert_eq!(moves.len(), new_positions.len());
+        for to in targets(&moves) {
+            assert!(new_positions.contains(&to));
         }
     }
 
@@ -571,20 +551,13 @@ mod tests {
         let fen = "8/8/3B4/4B3/3b4/8/8/8 w - - 0 1";
         let game = Game::read_FEN(fen);
 
-        let positions = generate_bishop_moves(&game.position.pieces[1], &game);
+        let moves = generate_bishop_moves(&game.position.pieces[1], &game);
         let new_positions = [45, 54, 63, 27, 29, 22, 15];
 
-        assert_eq!(positions.len(), new_positions.len());
-        'outer: for pos in positions {
-            assert!(pos.pieces.len() == 2 || pos.pieces.len() == 3);
-
-            for piece in &pos.pieces {
-                if piece.color == Color::White && new_positions.contains(&bit_scan(piece.position))
-                {
-                    continue 'outer;
-                }
-            }
-            assert!(false);
+        assert_eq!(moves.len(), new_positions.len());
+        for m in moves {
+            assert!(new_positions.contains(&m.to()));
+            assert_eq!(m.is_capture(), m.to() == 27);
         }
     }
 
@@ -592,20 +565,15 @@ mod tests {
     fn test_generate_rook_moves() {
         let game = Game::empty().add(Color::White, Rook, "e3");
 
-        let positions = generate_rook_moves(&game.position.pieces[0], &game);
-        let new_positions = [
+        let moves = generate_rook_moves(&game.position.pieces[0], &game);
+        let new_positions = squares(&[
             "e2", "e1", "a3", "b3", "c3", "d3", "f3", "g3", "h3", "e4", "e5", "e6", "e7", "e8",
-        ]
-        .iter()
-        .map(|string| square_to_index(string))
-        .collect::<Vec<usize>>();
+        ]);
 
-        assert_eq!(positions.len(), new_positions.len());
-        for pos in positions {
-            assert!(pos.pieces.len() == 1);
-            assert!(new_positions.contains(&bit_scan(pos.pieces[0].position)));
-            assert_eq!(pos.pieces[0].piece_type, Rook);
-            assert_eq!(pos.pieces[0].color, White);
+        assert_eq!(moves.len(), new_positions.len());
+        for m in moves {
+            assert_eq!(m.from(), square_to_index("e3"));
+            assert!(new_positions.contains(&m.to()));
         }
     }
 
@@ -615,21 +583,16 @@ mod tests {
 
         assert_eq!(game.position.pieces.len(), 2);
 
-        let new_positions = [
+        let new_positions = squares(&[
             "e1", "e2", "e4", "e5", "a3", "b3", "c3", "d3", "f3", "g3", "h3",
-        ]
-        .iter()
-        .map(|s| square_to_index(s))
-        .collect::<Vec<usize>>();
+        ]);
 
-        let positions = generate_rook_moves(&game.position.pieces[0], &game);
+        let moves = generate_rook_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), new_positions.len());
-        for pos in positions {
-            assert!(pos.pieces.len() == 1 || pos.pieces.len() == 2);
-            assert!(new_positions.contains(&bit_scan(pos.pieces[0].position)));
-            assert_eq!(pos.pieces[0].piece_type, Rook);
-            assert_eq!(pos.pieces[0].color, White);
+        assert_eq!(moves.len(), new_positions.len());
+        for m in moves {
+            assert!(new_positions.contains(&m.to()));
+            assert_eq!(m.is_capture(), m.to() == square_to_index("e5"));
         }
     }
 
@@ -640,22 +603,15 @@ mod tests {
             .add(White, Rook, "d2")
             .add(Black, Rook, "g5");
 
-        let new_positions = [
+        let new_positions = squares(&[
             "d3", "d4", "d6", "d7", "d8", "a5", "b5", "c5", "e5", "f5", "g5",
-        ]
-        .iter()
-        .map(|s| square_to_index(s))
-        .collect::<Vec<usize>>();
+        ]);
 
-        let positions = generate_rook_moves(&game.position.pieces[0], &game);
+        let moves = generate_rook_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), new_positions.len());
-
-        for pos in positions {
-            assert!(pos.pieces.len() == 3 || pos.pieces.len() == 2);
-            assert!(new_positions.contains(&bit_scan(pos.pieces[0].position)));
-            assert_eq!(pos.pieces[0].piece_type, Rook);
-            assert_eq!(pos.pieces[0].color, White);
+        assert_eq!(moves.len(), new_positions.len());
+        for to in targets(&moves) {
+            assert!(new_positions.contains(&to));
         }
     }
 
@@ -663,22 +619,16 @@ mod tests {
     fn test_generate_queen_moves() {
         let game = Game::empty().add(White, Queen, "d5");
 
-        let expected_positions = [
+        let expected_positions = squares(&[
             "a5", "b5", "c5", "e5", "f5", "g5", "h5", "d1", "d2", "d3", "d4", "d6", "d7", "d8",
             "c4", "b3", "a2", "e6", "f7", "g8", "c6", "b7", "a8", "e4", "f3", "g2", "h1",
-        ]
-        .iter()
-        .map(|s| square_to_index(s))
-        .collect::<Vec<usize>>();
+        ]);
 
-        let positions = generate_queen_moves(&game.position.pieces[0], &game);
+        let moves = generate_queen_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), expected_positions.len());
-        for pos in positions {
-            assert_eq!(pos.pieces.len(), 1);
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[0].position)));
-            assert_eq!(pos.pieces[0].piece_type, Queen);
-            assert_eq!(pos.pieces[0].color, White);
+        assert_eq!(moves.len(), expected_positions.len());
+        for to in targets(&moves) {
+            assert!(expected_positions.contains(&to));
         }
     }
 
@@ -686,22 +636,16 @@ mod tests {
     fn test_generate_queen_moves_one_enemy() {
         let game = Game::empty().add(White, Queen, "d5").add(Black, Pawn, "c6");
 
-        let expected_positions = [
+        let expected_positions = squares(&[
             "a5", "b5", "c5", "e5", "f5", "g5", "h5", "d1", "d2", "d3", "d4", "d6", "d7", "d8",
             "c4", "b3", "a2", "e6", "f7", "g8", "c6", "e4", "f3", "g2", "h1",
-        ]
-        .iter()
-        .map(|s| square_to_index(s))
-        .collect::<Vec<usize>>();
+        ]);
 
-        let positions = generate_queen_moves(&game.position.pieces[0], &game);
+        let moves = generate_queen_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), expected_positions.len());
-        for pos in positions {
-            assert!(pos.pieces.len() == 1 || pos.pieces.len() == 2);
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[0].position)));
-            assert_eq!(pos.pieces[0].piece_type, Queen);
-            assert_eq!(pos.pieces[0].color, White);
+        assert_eq!(moves.len(), expected_positions.len());
+        for to in targets(&moves) {
+            assert!(expected_positions.contains(&to));
         }
     }
 
@@ -712,22 +656,16 @@ mod tests {
             .add(Black, Pawn, "d6")
             .add(White, Pawn, "c5");
 
-        let expected_positions = [
+        let expected_positions = squares(&[
             "e5", "f5", "g5", "h5", "d1", "d2", "d3", "d4", "d6", "c4", "b3", "a2", "e6", "f7",
             "g8", "c6", "b7", "a8", "e4", "f3", "g2", "h1",
-        ]
-        .iter()
-        .map(|s| square_to_index(s))
-        .collect::<Vec<usize>>();
+        ]);
 
-        let positions = generate_queen_moves(&game.position.pieces[0], &game);
+        let moves = generate_queen_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), expected_positions.len());
-        for pos in positions {
-            assert!(pos.pieces.len() == 2 || pos.pieces.len() == 3);
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[0].position)));
-            assert_eq!(pos.pieces[0].piece_type, Queen);
-            assert_eq!(pos.pieces[0].color, White);
+        assert_eq!(moves.len(), expected_positions.len());
+        for to in targets(&moves) {
+            assert!(expected_positions.contains(&to));
         }
     }
 
@@ -735,19 +673,13 @@ mod tests {
     fn test_generate_king_moves() {
         let game = Game::empty().add(White, King, "d5");
 
-        let expected_positions = ["c5", "e5", "d4", "d6", "c4", "e6", "c6", "e4"]
-            .iter()
-            .map(|s| square_to_index(s))
-            .collect::<Vec<usize>>();
+        let expected_positions = squares(&["c5", "e5", "d4", "d6", "c4", "e6", "c6", "e4"]);
 
-        let positions = generate_king_moves(&game.position.pieces[0], &game);
+        let moves = generate_king_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), expected_positions.len());
-        for pos in positions {
-            assert_eq!(pos.pieces.len(), 1);
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[0].position)));
-            assert_eq!(pos.pieces[0].piece_type, King);
-            assert_eq!(pos.pieces[0].color, White);
+        assert_eq!(moves.len(), expected_positions.len());
+        for to in targets(&moves) {
+            assert!(expected_positions.contains(&to));
         }
     }
 
@@ -755,21 +687,14 @@ mod tests {
     fn test_generate_king_moves_one_enemy() {
         let game = Game::empty().add(White, King, "d5").add(Black, King, "d6");
 
-        let expected_positions = ["c5", "e5", "d4", "d6", "c4", "e6", "c6", "e4"]
-            .iter()
-            .map(|s| square_to_index(s))
-            .collect::<Vec<usize>>();
+        let expected_positions = squares(&["c5", "e5", "d4", "d6", "c4", "e6", "c6", "e4"]);
 
-        let positions = generate_king_moves(&game.position.pieces[0], &game);
+        let moves = generate_king_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), expected_positions.len());
-        for pos in positions {
-            let alive_count = pos.pieces.iter().filter(|p| p.alive).count();
-            assert!(alive_count == 1 || alive_count == 2);
-            assert_eq!(pos.pieces.len(), 2);
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[0].position)));
-            assert_eq!(pos.pieces[0].piece_type, King);
-            assert_eq!(pos.pieces[0].color, White);
+        assert_eq!(moves.len(), expected_positions.len());
+        for m in moves {
+            assert!(expected_positions.contains(&m.to()));
+            assert_eq!(m.is_capture(), m.to() == square_to_index("d6"));
         }
     }
 
@@ -780,19 +705,13 @@ mod tests {
             .add(Black, King, "d6")
             .add(White, King, "d4");
 
-        let expected_positions = ["c5", "e5", "d6", "c4", "e6", "c6", "e4"]
-            .iter()
-            .map(|s| square_to_index(s))
-            .collect::<Vec<usize>>();
+        let expected_positions = squares(&["c5", "e5", "d6", "c4", "e6", "c6", "e4"]);
 
-        let positions = generate_king_moves(&game.position.pieces[0], &game);
+        let moves = generate_king_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), expected_positions.len());
-        for pos in positions {
-            assert!(pos.pieces.len() == 3 || pos.pieces.len() == 2);
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[0].position)));
-            assert_eq!(pos.pieces[0].piece_type, King);
-            assert_eq!(pos.pieces[0].color, White);
+        assert_eq!(moves.len(), expected_positions.len());
+        for to in targets(&moves) {
+            assert!(expected_positions.contains(&to));
         }
     }
 
@@ -800,17 +719,14 @@ mod tests {
     fn test_generate_pawn_moves() {
         let game = Game::empty().add(White, Pawn, "d2");
 
-        let expected_positions = ["d3", "d4"]
-            .iter()
-            .map(|s| square_to_index(s))
-            .collect::<Vec<usize>>();
+        let expected_positions = squares(&["d3", "d4"]);
 
-        let positions = generate_pawn_moves(&game.position.pieces[0], &game);
+        let moves = generate_pawn_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), 2);
-
-        for pos in positions {
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[0].position)));
+        assert_eq!(moves.len(), 2);
+        for m in moves {
+            assert!(expected_positions.contains(&m.to()));
+            assert_eq!(m.is_double_push(), m.to() == square_to_index("d4"));
         }
     }
 
@@ -818,22 +734,20 @@ mod tests {
     fn test_generate_pawn_moves_en_passant() {
         let mut game = Game::empty().add(White, Pawn, "d2").add(Black, Pawn, "c4");
 
-        game.position.move_piece(
-            game.position.pieces[0].position,
-            position_to_index("d4").unwrap(),
-        );
-
-        let expected_positions = ["d3", "c3"]
-            .iter()
-            .map(|s| square_to_index(s))
-            .collect::<Vec<usize>>();
+        game.position.make_move(Move::new(
+            square_to_index("d2"),
+            square_to_index("d4"),
+            MoveFlags::DOUBLE_PUSH,
+        ));
 
-        let positions = generate_pawn_moves(&game.position.pieces[1], &game);
+        let expected_positions = squares(&["d3", "c3"]);
 
-        assert_eq!(positions.len(), 2);
+        let moves = generate_pawn_moves(&game.position.pieces[1], &game);
 
-        for pos in positions {
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[1].position)));
+        assert_eq!(moves.len(), 2);
+        for m in moves {
+            assert!(expected_positions.contains(&m.to()));
+            assert_eq!(m.is_en_passant(), m.to() == square_to_index("d3"));
         }
     }
 
@@ -841,51 +755,241 @@ mod tests {
     fn test_generate_pawn_moves_middle() {
         let game = Game::empty().add(White, Pawn, "d4");
 
-        let expected_positions = ["d5"]
-            .iter()
-            .map(|s| square_to_index(s))
-            .collect::<Vec<usize>>();
+        let moves = generate_pawn_moves(&game.position.pieces[0], &game);
+
+        assert_eq!(targets(&moves), squares(&["d5"]));
+    }
+
+    #[test]
+    fn test_pawn_blocked() {
+        let game = Game::empty().add(White, Pawn, "d4").add(Black, Pawn, "d5");
+
+        let moves = generate_pawn_moves(&game.position.pieces[0], &game);
+
+        assert_eq!(moves.len(), 0);
+    }
+
+    /// The moves of one pawn, worked out square by square. The engine
+    /// generates all pawn moves at once; this is kept to check it against.
+    fn generate_pawn_moves(piece: &Piece, game: &Game) -> Vec<Move> {
+        let from = bit_scan(piece.position);
+        let position = &game.position;
+        let occupancy = position.white_occupancy | position.black_occupancy;
+        let enemy_occupancy = position.occupancy(piece.color.opposite());
+        let attacks = game.pawn_attacks.attacks(piece.color, from);
+
+        let mut moves = vec![];
+
+        let single_push = push_all(piece.position, piece.color);
+        if single_push & occupancy == 0 {
+            add_pawn_moves(&mut moves, from, bit_scan(single_push), MoveFlags::QUIET);
+
+            let double_push = game.pawn_attacks.pushes(piece.color, from) & !single_push;
+            if double_push != 0 && double_push & occupancy == 0 {
+                moves.push(Move::new(
+                    from,
+                    bit_scan(double_push),
+                    MoveFlags::DOUBLE_PUSH,
+                ));
+            }
+        }
+
+        for to in extract_bits(attacks & enemy_occupancy) {
+            add_pawn_moves(&mut moves, from, to, MoveFlags::CAPTURE);
+        }
+
+        if let Some(square) = position.en_passant {
+            if attacks & square != 0 {
+                moves.push(Move::new(
+                    from,
+                    bit_scan(square),
+                    MoveFlags::CAPTURE | MoveFlags::EN_PASSANT,
+                ));
+            }
+        }
+
+        moves
+    }
+
+    /// The moves of the pawn on `square`, checking that the per-pawn and the
+    /// set-wise generator agree on them.
+    fn pawn_moves(fen: &str, square: &str) -> Vec<String> {
+        let game = Game::read_FEN(fen);
+        let from = square_to_index(square);
+        let piece = game.position.piece_at(from).unwrap();
+        assert_eq!(piece.piece_type, Pawn);
+
+        let per_pawn = sorted_strings(&generate_pawn_moves(piece, &game));
+        if piece.color == game.position.active_color {
+            let setwise = generate_all_pawn_moves(&game)
+                .into_iter()
+                .filter(|m| m.from() == from)
+                .collect::<Vec<Move>>();
+            assert_eq!(sorted_strings(&setwise), per_pawn);
+        }
+        per_pawn
+    }
+
+    #[test]
+    fn test_pawn_push_blocked_by_own_piece() {
+        assert!(pawn_moves("4k3/8/8/8/8/4N3/4P3/4K3 w - - 0 1", "e2").is_empty());
+        assert!(pawn_moves("4k3/4p3/4n3/8/8/8/8/4K3 b - - 0 1", "e7").is_empty());
+    }
+
+    #[test]
+    fn test_double_push_blocked() {
+        // Something on the square in between stops both pushes, something on
+        // the target square only the double push.
+        assert!(pawn_moves("4k3/8/8/8/8/4n3/4P3/4K3 w - - 0 1", "e2").is_empty());
+        assert_eq!(
+            pawn_moves("4k3/8/8/8/4n3/8/4P3/4K3 w - - 0 1", "e2"),
+            ["e2e3"]
+        );
+        assert_eq!(
+            pawn_moves("4k3/4p3/8/4N3/8/8/8/4K3 b - - 0 1", "e7"),
+            ["e7e6"]
+        );
+    }
+
+    #[test]
+    fn test_double_push_only_from_start_rank() {
+        assert_eq!(
+            pawn_moves("4k3/8/8/8/8/4P3/8/4K3 w - - 0 1", "e3"),
+            ["e3e4"]
+        );
+        assert_eq!(
+            pawn_moves("4k3/4p3/8/8/8/8/8/4K3 b - - 0 1", "e7"),
+            ["e7e5", "e7e6"]
+        );
+    }
 
-        let positions = generate_pawn_moves(&game.position.pieces[0], &game);
+    #[test]
+    fn test_pawn_does_not_capture_forward() {
+        assert!(pawn_moves("4k3/8/8/8/4p3/4P3/8/4K3 w - - 0 1", "e3").is_empty());
+        assert!(pawn_moves("4k3/8/8/8/4p3/4P3/8/4K3 b - - 0 1", "e4").is_empty());
+    }
+
+    #[test]
+    fn test_pawn_captures_diagonally_only_enemies() {
+        assert_eq!(
+            pawn_moves("4k3/8/8/3p1P2/4P3/8/8/4K3 w - - 0 1", "e4"),
+            ["e4d5", "e4e5"]
+        );
+        assert_eq!(
+            pawn_moves("4k3/8/8/3p4/2P1P3/8/8/4K3 b - - 0 1", "d5"),
+            ["d5c4", "d5d4", "d5e4"]
+        );
+    }
+
+    #[test]
+    fn test_pawn_captures_do_not_wrap_around_the_board() {
+        assert_eq!(
+            pawn_moves("4k3/8/8/7p/P7/8/8/4K3 w - - 0 1", "a4"),
+            ["a4a5"]
+        );
+        assert_eq!(
+            pawn_moves("4k3/8/8/7p/P7/8/8/4K3 b - - 0 1", "h5"),
+            ["h5h4"]
+        );
+    }
 
-        assert_eq!(positions.len(), 1);
+    #[test]
+    fn test_black_promotes_on_first_rank() {
+        assert_eq!(
+            pawn_moves("4k3/8/8/8/8/8/3p4/K7 b - - 0 1", "d2"),
+            ["d2d1b", "d2d1n", "d2d1q", "d2d1r"]
+        );
+        assert_eq!(
+            pawn_moves("4k3/3p4/8/8/8/8/8/K7 b - - 0 1", "d7"),
+            ["d7d5", "d7d6"]
+        );
+    }
 
-        for pos in positions {
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[0].position)));
+    #[test]
+    fn test_capture_promotions() {
+        let fen = "2n1k3/1P6/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(
+            pawn_moves(fen, "b7"),
+            ["b7b8b", "b7b8n", "b7b8q", "b7b8r", "b7c8b", "b7c8n", "b7c8q", "b7c8r"]
+        );
+
+        let game = Game::read_FEN(fen);
+        let pawn = game.position.piece_at(square_to_index("b7")).unwrap();
+        for m in generate_pawn_moves(pawn, &game) {
+            assert_eq!(m.is_capture(), m.to() == square_to_index("c8"));
         }
     }
 
     #[test]
-    fn test_pawn_blocked() {
-        let game = Game::empty().add(White, Pawn, "d4").add(Black, Pawn, "d5");
+    fn test_en_passant_both_colors() {
+        assert_eq!(
+            pawn_moves("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5"),
+            ["e5d6", "e5e6"]
+        );
+        assert_eq!(
+            pawn_moves("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1", "d4"),
+            ["d4d3", "d4e3"]
+        );
+        // Only right after the double push.
+        assert_eq!(
+            pawn_moves("4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1", "e5"),
+            ["e5e6"]
+        );
+    }
 
-        let positions = generate_pawn_moves(&game.position.pieces[0], &game);
+    #[test]
+    fn test_en_passant_horizontal_pin() {
+        // Pseudo-legal generation leaves this to the legality check, which
+        // both legal generators have to get right on their own.
+        fn legal_pawn_moves(fen: &str, square: &str) -> Vec<String> {
+            let mut game = Game::read_FEN(fen);
+            let from = square_to_index(square);
+            let of_pawn = |moves: Vec<Move>| {
+                sorted_strings(
+                    &moves
+                        .into_iter()
+                        .filter(|m| m.from() == from)
+                        .collect::<Vec<Move>>(),
+                )
+            };
+            let moves = of_pawn(generate_moves(&game));
+            assert_eq!(of_pawn(generate_moves_by_filtering(&mut game)), moves);
+            moves
+        }
 
-        assert_eq!(positions.len(), 0);
+        // Taking en passant would leave the rank between king and rook empty.
+        assert_eq!(
+            legal_pawn_moves("8/8/8/KPp4r/8/8/8/4k3 w - c6 0 1", "b5"),
+            ["b5b6"]
+        );
+        assert_eq!(
+            legal_pawn_moves("4K3/8/8/8/Q2pP2k/8/8/8 b - e3 0 1", "d4"),
+            ["d4d3"]
+        );
+        // Another piece on the rank still shields the king.
+        assert_eq!(
+            legal_pawn_moves("8/8/8/KPp1N2r/8/8/8/4k3 w - c6 0 1", "b5"),
+            ["b5b6", "b5c6"]
+        );
+        // A rook elsewhere on the board does not matter.
+        assert_eq!(
+            legal_pawn_moves("8/8/8/KPp5/7r/8/8/4k3 w - c6 0 1", "b5"),
+            ["b5b6", "b5c6"]
+        );
     }
 
     #[test]
     fn test_king_is_in_check() {
         let game = Game::empty().add(White, King, "e1").add(Black, Queen, "e5");
 
-        assert!(king_is_in_check(
-            &game.position,
-            White,
-            &game.ray_attacks,
-            &game.knight_attacks
-        ));
+        assert!(king_is_in_check(&game, White));
     }
 
     #[test]
     fn test_king_is_not_in_check() {
         let game = Game::empty().add(White, King, "e1").add(White, Queen, "e5");
 
-        assert!(!king_is_in_check(
-            &game.position,
-            White,
-            &game.ray_attacks,
-            &game.knight_attacks
-        ));
+        assert!(!king_is_in_check(&game, White));
     }
 
     #[test]
@@ -894,12 +998,7 @@ mod tests {
             .add(White, King, "e1")
             .add(Black, Knight, "f3");
 
-        assert!(king_is_in_check(
-            &game.position,
-            White,
-            &game.ray_attacks,
-            &game.knight_attacks
-        ));
+        assert!(king_is_in_check(&game, White));
     }
 
     #[test]
@@ -909,12 +1008,58 @@ mod tests {
             .add(Black, Bishop, "h4")
             .add(White, Pawn, "f2");
 
-        assert!(!king_is_in_check(
-            &game.position,
-            White,
-            &game.ray_attacks,
-            &game.knight_attacks
-        ));
+        assert!(!king_is_in_check(&game, White));
+    }
+
+    #[test]
+    fn test_king_is_in_pawn_check() {
+        let checked = Game::read_FEN("4k3/8/8/8/8/8/3p4/4K3 w - - 0 1");
+        assert!(checked.is_in_check());
+
+        let behind = Game::read_FEN("4k3/8/8/8/8/8/8/3pK3 w - - 0 1");
+        assert!(!behind.is_in_check());
+
+        let checked = Game::read_FEN("4k3/5P2/8/8/8/8/8/4K3 b - - 0 1");
+        assert!(checked.is_in_check());
+    }
+
+    fn sorted_strings(moves: &[Move]) -> Vec<String> {
+        let mut moves = moves.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn test_setwise_pawn_moves_match_per_pawn_moves() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/Pp2P3/2N2Q1p/1PPBBPPP/R3K2R b KQkq a3 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/8/8/1k6/3Pp3/8/8/4KQ2 b - d3 0 1",
+        ] {
+            let game = Game::read_FEN(fen);
+            let color = game.position.active_color;
+            let per_pawn = game
+                .position
+                .pieces
+                .iter()
+                .filter(|p| p.alive && p.color == color && p.piece_type == Pawn)
+                .flat_map(|p| generate_pawn_moves(p, &game))
+                .collect::<Vec<Move>>();
+            let setwise = generate_all_pawn_moves(&game);
+
+            assert_eq!(
+                sorted_strings(&setwise),
+                sorted_strings(&per_pawn),
+                "{}",
+                fen
+            );
+            for m in setwise {
+                assert!(per_pawn.contains(&m), "{} in {}", m, fen);
+            }
+        }
     }
 
     #[test]
@@ -925,33 +1070,219 @@ mod tests {
             .add(Black, Rook, "e3")
             .add(Black, Queen, "d7");
 
-        let positions = generate_moves(&game);
+        let moves = generate_moves(&game);
 
-        assert_eq!(positions.len(), 2);
+        assert_eq!(moves.len(), 2);
 
-        let expected_positions = ["f1", "f2"]
-            .iter()
-            .map(|s| square_to_index(s))
-            .collect::<Vec<usize>>();
+        let expected_positions = squares(&["f1", "f2"]);
 
-        for pos in positions {
-            assert!(expected_positions.contains(&bit_scan(pos.pieces[0].position)));
+        for m in moves {
+            assert_eq!(m.from(), square_to_index("e1"));
+            assert!(expected_positions.contains(&m.to()));
         }
     }
 
+    #[test]
+    fn test_move_generator_leaves_position_untouched() {
+        let game = Game::new();
+        let before = game.position.to_string();
+
+        let moves = generate_moves(&game);
+
+        assert_eq!(moves.len(), 20);
+        assert_eq!(game.position.to_string(), before);
+    }
+
     #[test]
     fn test_promote_single_pawn() {
         let game = Game::empty().add(White, Pawn, "e7");
 
-        let positions = generate_pawn_moves(&game.position.pieces[0], &game);
+        let moves = generate_pawn_moves(&game.position.pieces[0], &game);
 
-        assert_eq!(positions.len(), 4);
+        assert_eq!(moves.len(), 4);
 
         let allowed_types = [Queen, Bishop, Knight, Rook];
 
-        for pos in positions {
-            assert_eq!(pos.count_pieces(), 1);
-            assert!(allowed_types.contains(&pos.pieces[1].piece_type));
+        for m in moves {
+            assert_eq!(m.to(), square_to_index("e8"));
+            assert!(allowed_types.contains(&m.promotion().unwrap()));
         }
     }
+    fn castling_targets(game: &Game, color: Color) -> Vec<usize> {
+        let king = game
+            .position
+            .pieces
+            .iter()
+            .find(|p| p.piece_type == King && p.color == color)
+            .unwrap();
+        let mut targets = generate_king_moves(king, game)
+            .into_iter()
+            .filter(|m| m.is_castle())
+            .map(|m| m.to())
+            .collect::<Vec<usize>>();
+        targets.sort();
+        targets
+    }
+
+    #[test]
+    fn test_castling_both_sides() {
+        let game = Game::read_FEN("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1");
+        assert_eq!(castling_targets(&game, White), squares(&["c1", "g1"]));
+        assert_eq!(castling_targets(&game, Black), squares(&["c8", "g8"]));
+    }
+
+    #[test]
+    fn test_castling_requires_rights() {
+        let game = Game::read_FEN("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w Kq - 0 1");
+        assert_eq!(castling_targets(&game, White), squares(&["g1"]));
+        assert_eq!(castling_targets(&game, Black), squares(&["c8"]));
+    }
+
+    #[test]
+    fn test_castling_blocked_by_pieces() {
+        let game = Game::read_FEN("rn2k1nr/pppppppp/8/8/8/8/PPPPPPPP/R2QKB1R w KQkq - 0 1");
+        assert_eq!(castling_targets(&game, White), vec![]);
+        assert_eq!(castling_targets(&game, Black), vec![]);
+
+        // Only b1 is occupied: the rook passes it, but the king does not.
+        let game = Game::read_FEN("4k3/8/8/8/8/8/8/RN2K3 w Q - 0 1");
+        assert_eq!(castling_targets(&game, White), vec![]);
+    }
+
+    #[test]
+    fn test_castling_requires_rook() {
+        let game = Game::read_FEN("4k3/8/8/8/8/8/8/4K2R w KQ - 0 1");
+        assert_eq!(castling_targets(&game, White), squares(&["g1"]));
+    }
+
+    #[test]
+    fn test_cannot_castle_out_of_check() {
+        let game = Game::read_FEN("4r1k1/8/8/8/8/8/8/R3K2R w KQ - 0 1");
+        assert_eq!(castling_targets(&game, White), vec![]);
+    }
+
+    #[test]
+    fn test_cannot_castle_through_check() {
+        let game = Game::read_FEN("5rk1/8/8/8/8/8/8/R3K2R w KQ - 0 1");
+        assert_eq!(castling_targets(&game, White), squares(&["c1"]));
+
+        let game = Game::read_FEN("3r2k1/8/8/8/8/8/8/R3K2R w KQ - 0 1");
+        assert_eq!(castling_targets(&game, White), squares(&["g1"]));
+    }
+
+    #[test]
+    fn test_cannot_castle_into_check() {
+        let game = Game::read_FEN("6k1/8/8/8/8/8/7n/R3K2R w KQ - 0 1");
+        assert_eq!(castling_targets(&game, White), squares(&["c1"]));
+    }
+
+    #[test]
+    fn test_queenside_castling_allows_attacked_b_file() {
+        let game = Game::read_FEN("1r4k1/8/8/8/8/8/8/R3K3 w Q - 0 1");
+        assert_eq!(castling_targets(&game, White), squares(&["c1"]));
+    }
+
+    #[test]
+    fn test_double_check_allows_only_king_moves() {
+        // The knight gives check and uncovers the rook on the e-file.
+        let mut game = Game::from_fen("k3r3/8/8/8/8/3n4/8/3RK3 w - - 0 1").unwrap();
+        let occupancy = game.position.white_occupancy | game.position.black_occupancy;
+        let checkers = game.attackers_to(square_to_index("e1"), Black, occupancy);
+        assert_eq!(checkers.count_ones(), 2);
+
+        let moves = sorted_strings(&generate_moves(&game));
+        assert_eq!(moves, vec!["e1d2", "e1f1"], "Rxd3 takes only one checker");
+        assert_eq!(
+            sorted_strings(&generate_moves_by_filtering(&mut game)),
+            moves
+        );
+    }
+
+    #[test]
+    fn test_single_check_is_blocked_or_captured() {
+        let game = Game::from_fen("4k3/8/8/6B1/8/2N5/8/r3K3 w - - 0 1").unwrap();
+        let moves = generate_moves(&game);
+
+        assert_eq!(
+            sorted_strings(&moves),
+            vec!["c3b1", "c3d1", "e1d2", "e1e2", "e1f2", "g5c1"]
+        );
+    }
+
+    #[test]
+    fn test_pinned_piece_moves_along_pin() {
+        let game = Game::from_fen("4k3/4r3/8/8/8/8/4R3/4K3 w - - 0 1").unwrap();
+        let rook_moves = generate_moves(&game)
+            .into_iter()
+            .filter(|m| m.from() == square_to_index("e2"))
+            .collect::<Vec<Move>>();
+
+        assert_eq!(
+            sorted_strings(&rook_moves),
+            vec!["e2e3", "e2e4", "e2e5", "e2e6", "e2e7"]
+        );
+    }
+
+    #[test]
+    fn test_pinned_knight_cannot_move() {
+        let game = Game::from_fen("4k3/8/8/b7/8/8/3N4/4K3 w - - 0 1").unwrap();
+        assert!(generate_moves(&game)
+            .iter()
+            .all(|m| m.from() != square_to_index("d2")));
+    }
+
+    #[test]
+    fn test_en_passant_captures_checking_pawn() {
+        let game = Game::from_fen("8/8/8/3k4/4Pp2/8/8/4K3 b - e3 0 1").unwrap();
+        let moves = generate_moves(&game);
+
+        assert!(moves
+            .iter()
+            .any(|m| m.is_en_passant() && m.to_string() == "f4e3"));
+    }
+
+    #[test]
+    fn test_king_cannot_retreat_along_checking_ray() {
+        let game = Game::from_fen("4k3/8/8/8/8/8/8/K3R3 b - - 0 1").unwrap();
+        assert!(generate_moves(&game)
+            .iter()
+            .all(|m| m.to() != square_to_index("e7")));
+    }
+
+    #[test]
+    fn test_child_position_generates_for_other_side() {
+        let mut game = Game::new();
+        let moves = generate_moves(&game);
+        game.position.make_move(moves[0]);
+
+        let replies = generate_moves(&game);
+        assert_eq!(replies.len(), 20);
+        for m in replies {
+            assert_eq!(game.position.piece_at(m.from()).unwrap().color, Black);
+        }
+    }
+
+    #[test]
+    fn test_tactical_moves_are_captures_and_queen_promotions() {
+        let game = Game::from_fen("3n2k1/4P3/8/3p4/4P3/1q6/8/R3K2R w KQ - 0 1").unwrap();
+        let mut moves = generate_tactical_moves(&game)
+            .iter()
+            .map(|m| m.to_uci())
+            .collect::<Vec<String>>();
+        moves.sort();
+
+        assert_eq!(moves, vec!["e4d5", "e7d8q", "e7e8q"]);
+    }
+
+    #[test]
+    fn test_tactical_moves_in_check_are_all_evasions() {
+        let game = Game::from_fen("4k3/8/8/8/8/3n4/8/R3K3 w Q - 0 1").unwrap();
+        assert!(game.is_in_check());
+        assert_eq!(generate_tactical_moves(&game), generate_moves(&game));
+    }
+
+    #[test]
+    fn test_tactical_moves_of_quiet_position() {
+        assert!(generate_tactical_moves(&Game::new()).is_empty());
+    }
 }
@@ -1,6 +1,9 @@
 use crate::position::Color;
 use crate::utils::*;
 
+/// Pawn pushes and captures from every square on an empty board, for both
+/// colors, indexed a1 = 0.
+#[derive(Clone)]
 pub struct PawnAttacks {
     white_forward_moves: Vec<Bitboard>,
     white_diagonal_moves: Vec<Bitboard>,
@@ -9,7 +12,7 @@ pub struct PawnAttacks {
 }
 
 impl PawnAttacks {
-    fn initialize() -> Self {
+    pub fn new() -> Self {
         let mut w_forward = vec![];
         let mut w_diagonal = vec![];
         let mut b_forward = vec![];
@@ -38,6 +41,60 @@ impl PawnAttacks {
             black_diagonal_moves: b_diagonal,
         }
     }
+
+    /// The squares a pawn of `color` on `square` can push to, including the
+    /// double push from its starting rank, if nothing is in the way.
+    pub fn pushes(&self, color: Color, square: usize) -> Bitboard {
+        match color {
+            Color::White => self.white_forward_moves[square],
+            Color::Black => self.black_forward_moves[square],
+        }
+    }
+
+    /// The squares a pawn of `color` on `square` attacks. Turned around, these
+    /// are the squares from which pawns of the other color attack `square`.
+    pub fn attacks(&self, color: Color, square: usize) -> Bitboard {
+        match color {
+            Color::White => self.white_diagonal_moves[square],
+            Color::Black => self.black_diagonal_moves[square],
+        }
+    }
+}
+
+impl Default for PawnAttacks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const FILE_A: Bitboard = 0x0101_0101_0101_0101;
+const FILE_H: Bitboard = FILE_A << 7;
+pub const RANK_3: Bitboard = 0xff << 16;
+pub const RANK_6: Bitboard = 0xff << 40;
+
+/// Every pawn of `color` in `pawns` moved one square forward.
+pub fn push_all(pawns: Bitboard, color: Color) -> Bitboard {
+    match color {
+        Color::White => pawns << 8,
+        Color::Black => pawns >> 8,
+    }
+}
+
+/// The squares attacked by the pawns of `color` in `pawns` towards the
+/// h-file.
+pub fn east_attacks(pawns: Bitboard, color: Color) -> Bitboard {
+    push_all(pawns & !FILE_H, color) << 1
+}
+
+/// The squares attacked by the pawns of `color` in `pawns` towards the
+/// a-file.
+pub fn west_attacks(pawns: Bitboard, color: Color) -> Bitboard {
+    push_all(pawns & !FILE_A, color) >> 1
+}
+
+/// Every square attacked by at least one of `pawns`.
+pub fn all_attacks(pawns: Bitboard, color: Color) -> Bitboard {
+    east_attacks(pawns, color) | west_attacks(pawns, color)
 }
 
 fn forward_move(row: i32, col: i32, color: Color) -> Bitboard {
@@ -64,21 +121,18 @@ fn forward_move(row: i32, col: i32, color: Color) -> Bitboard {
     bitboard
 }
 
+// Unlike pushes, attacks are also filled in for the back rank: no pawn
+// stands there, but a king on it can still be attacked by one.
 fn diagonal_move(row: i32, col: i32, color: Color) -> Bitboard {
-    if row == 1 || row == 8 {
-        return 0;
-    }
     let mut bitboard = 0;
     if color == Color::White {
         if row < 8 {
             bitboard |= set_bit(row + 1, col + 1);
             bitboard |= set_bit(row + 1, col - 1);
         }
-    } else {
-        if row > 1 {
-            bitboard |= set_bit(row - 1, col + 1);
-            bitboard |= set_bit(row - 1, col - 1);
-        }
+    } else if row > 1 {
+        bitboard |= set_bit(row - 1, col + 1);
+        bitboard |= set_bit(row - 1, col - 1);
     }
     bitboard
 }
@@ -234,6 +288,40 @@ mod tests {
 
     #[test]
     fn test_pawnattacks_init() {
-        let pawnattacks = PawnAttacks::initialize();
+        let pawnattacks = PawnAttacks::new();
+        assert_eq!(pawnattacks.pushes(Color::White, 12), 1 << 20 | 1 << 28);
+        assert_eq!(pawnattacks.attacks(Color::Black, 12), 1 << 3 | 1 << 5);
+    }
+
+    #[test]
+    fn test_back_rank_attacks() {
+        let pawnattacks = PawnAttacks::new();
+        // A white king on e1 is attacked by black pawns on d2 and f2.
+        assert_eq!(pawnattacks.attacks(Color::White, 4), 1 << 11 | 1 << 13);
+        assert_eq!(pawnattacks.attacks(Color::Black, 60), 1 << 51 | 1 << 53);
+        assert_eq!(pawnattacks.attacks(Color::White, 60), 0);
+    }
+
+    #[test]
+    fn test_setwise_attacks_match_tables() {
+        let pawnattacks = PawnAttacks::new();
+        for color in [Color::White, Color::Black] {
+            for square in 8..56 {
+                assert_eq!(
+                    all_attacks(1 << square, color),
+                    pawnattacks.attacks(color, square),
+                    "{:?} pawn on {}",
+                    color,
+                    square
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_push_all() {
+        let pawns = 0xff00;
+        assert_eq!(push_all(pawns, Color::White), 0xff_0000);
+        assert_eq!(push_all(pawns, Color::Black), 0xff);
     }
 }
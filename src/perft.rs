@@ -0,0 +1,77 @@
+use crate::game::Game;
+use crate::movegeneration::generate_moves;
+use crate::position::Move;
+
+/// Counts the leaf nodes of the legal move tree `depth` plies below the
+/// current position.
+pub fn perft(game: &mut Game, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = generate_moves(game);
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for m in moves {
+        let undo = game.position.make_move(m);
+        nodes += perft(game, depth - 1);
+        game.position.unmake_move(m, undo);
+    }
+
+    nodes
+}
+
+/// The perft count below each legal move, for narrowing down where a move
+/// generator disagrees with a reference engine.
+pub fn divide(game: &mut Game, depth: usize) -> Vec<(Move, u64)> {
+    assert!(depth > 0, "divide needs a depth of at least 1");
+
+    let mut result = vec![];
+    for m in generate_moves(game) {
+        let undo = game.position.make_move(m);
+        result.push((m, perft(game, depth - 1)));
+        game.position.unmake_move(m, undo);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::CastlingRights;
+
+    #[test]
+    fn test_perft_start_position() {
+        let mut game = Game::new();
+        assert_eq!(perft(&mut game, 0), 1);
+        assert_eq!(perft(&mut game, 1), 20);
+        assert_eq!(perft(&mut game, 2), 400);
+        assert_eq!(perft(&mut game, 3), 8902);
+    }
+
+    #[test]
+    fn test_divide_sums_to_perft() {
+        let mut game = Game::new();
+        let divided = divide(&mut game, 2);
+
+        assert_eq!(divided.len(), 20);
+        assert!(divided.iter().all(|(_, nodes)| *nodes == 20));
+        assert_eq!(divided.iter().map(|(_, nodes)| nodes).sum::<u64>(), 400);
+    }
+
+    #[test]
+    fn test_perft_leaves_position_untouched() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut game = Game::read_FEN(fen);
+        let before = game.position.to_string();
+
+        perft(&mut game, 2);
+
+        assert_eq!(game.position.to_string(), before);
+        assert_eq!(game.position.castling_rights, CastlingRights::ALL);
+    }
+}
@@ -0,0 +1,1013 @@
+use crate::game::Game;
+use crate::gametree::{GameTree, NodeId};
+use crate::position::*;
+use crate::san::{to_san, SanError};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
+
+/// The tags every PGN game has, in the order they are exported.
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+
+/// Why a PGN game could not be read. Lines and columns count from 1.
+#[derive(Debug)]
+pub enum PgnError {
+    Io(io::Error),
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    InvalidFen {
+        line: usize,
+        column: usize,
+        error: FenError,
+    },
+    IllegalMove {
+        line: usize,
+        column: usize,
+        san: String,
+        error: SanError,
+    },
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnError::Io(error) => write!(f, "{}", error),
+            PgnError::Syntax {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+            PgnError::InvalidFen {
+                line,
+                column,
+                error,
+            } => write!(
+                f,
+                "line {}, column {}: invalid FEN: {}",
+                line, column, error
+            ),
+            PgnError::IllegalMove {
+                line,
+                column,
+                san,
+                error,
+            } => write!(
+                f,
+                "line {}, column {}: illegal move {}: {}",
+                line, column, san, error
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+impl From<io::Error> for PgnError {
+    fn from(error: io::Error) -> Self {
+        PgnError::Io(error)
+    }
+}
+
+/// A game as read from or written to PGN: its tags, in the order they
+/// appeared, the moves with their variations, comments and NAGs, and the
+/// result (`1-0`, `0-1`, `1/2-1/2` or `*`).
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub tree: GameTree,
+    pub result: String,
+}
+
+impl PgnGame {
+    /// A game without tags or moves from the standard starting position.
+    pub fn new() -> Self {
+        Self::with_tree(GameTree::new())
+    }
+
+    pub fn with_tree(tree: GameTree) -> Self {
+        Self {
+            tags: vec![],
+            tree,
+            result: "*".to_string(),
+        }
+    }
+
+    /// The value of the tag `name`, if there is one.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Sets the tag `name`, replacing its value if it was already there.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+}
+
+impl Default for PgnGame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Exports the game in PGN export format: the seven tag roster first, `?`
+/// where a tag is missing, `SetUp` and `FEN` tags if the game does not
+/// start from the standard position, then the movetext wrapped to 79
+/// columns, ending with the result and a blank line.
+impl fmt::Display for PgnGame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, default) in SEVEN_TAG_ROSTER {
+            let value = match name {
+                "Result" => self.result.as_str(),
+                _ => self.tag(name).unwrap_or(default),
+            };
+            writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+        }
+
+        let starting_fen = self.tree.starting_fen();
+        let is_standard = starting_fen == Position::new().to_fen();
+        if !is_standard {
+            writeln!(f, "[SetUp \"1\"]")?;
+            writeln!(f, "[FEN \"{}\"]", starting_fen)?;
+        }
+
+        for (name, value) in &self.tags {
+            let is_roster = SEVEN_TAG_ROSTER.iter().any(|(tag, _)| tag == name);
+            if !is_roster && name != "SetUp" && name != "FEN" {
+                writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+            }
+        }
+        writeln!(f)?;
+
+        let mut tokens = vec![];
+        let root = self.tree.node(self.tree.root());
+        if let Some(comment) = &root.comment {
+            tokens.push(comment_token(comment));
+        }
+        let mut scratch = Game::with_position(root.position.clone());
+        write_line(
+            &self.tree,
+            &mut scratch,
+            self.tree.root(),
+            true,
+            &mut tokens,
+        );
+        tokens.push(self.result.clone());
+
+        for line in wrap(&tokens, 79) {
+            writeln!(f, "{}", line)?;
+        }
+        writeln!(f)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// `comment` in braces. PGN has no way to escape a closing brace inside a
+/// comment, so any are dropped.
+fn comment_token(comment: &str) -> String {
+    format!("{{{}}}", comment.replace('}', ""))
+}
+
+/// Writes the main line below `parent` with each variation in parentheses
+/// after the move it replaces.
+fn write_line(
+    tree: &GameTree,
+    scratch: &mut Game,
+    parent: NodeId,
+    mut force_number: bool,
+    tokens: &mut Vec<String>,
+) {
+    let mut parent = parent;
+    while let Some(&main) = tree.node(parent).children.first() {
+        force_number = write_move(tree, scratch, main, force_number, tokens);
+
+        for &variation in &tree.node(parent).children[1..] {
+            tokens.push("(".to_string());
+            let after_variation = write_move(tree, scratch, variation, true, tokens);
+            write_line(tree, scratch, variation, after_variation, tokens);
+            tokens.push(")".to_string());
+            force_number = true;
+        }
+
+        parent = main;
+    }
+}
+
+/// Writes the move leading to `id` with its number, NAGs and comment.
+/// Returns whether the next move needs its number repeated, as a Black move
+/// does after a comment.
+fn write_move(
+    tree: &GameTree,
+    scratch: &mut Game,
+    id: NodeId,
+    force_number: bool,
+    tokens: &mut Vec<String>,
+) -> bool {
+    let node = tree.node(id);
+    let position = &tree.node(node.parent.unwrap()).position;
+
+    if position.active_color == Color::White {
+        tokens.push(format!("{}.", position.fullmove_number));
+    } else if force_number {
+        tokens.push(format!("{}...", position.fullmove_number));
+    }
+
+    scratch.position = position.clone();
+    let san = to_san(scratch, node.m.unwrap());
+    tokens.push(san.replacen(" e.p.", "", 1));
+
+    for nag in &node.nags {
+        tokens.push(format!("${}", nag));
+    }
+    match &node.comment {
+        Some(comment) => {
+            tokens.push(comment_token(comment));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Joins `tokens` into lines of at most `width` characters, keeping
+/// parentheses against what they enclose and breaking comments between
+/// words.
+fn wrap(tokens: &[String], width: usize) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    let mut open = false;
+    for token in tokens {
+        if token == ")" {
+            words.last_mut().unwrap().push(')');
+        } else if token == "(" {
+            open = true;
+        } else {
+            for (i, word) in token.split_whitespace().enumerate() {
+                if i == 0 && open {
+                    words.push(format!("({}", word));
+                    open = false;
+                } else {
+                    words.push(word.to_string());
+                }
+            }
+        }
+    }
+
+    let mut lines = vec![];
+    let mut line = String::new();
+    for word in words {
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Move(String),
+    Open,
+    Close,
+    Result(String),
+}
+
+/// Splits PGN text into tokens, reading one line at a time.
+struct Lexer<R> {
+    reader: R,
+    line: Vec<char>,
+    position: usize,
+    line_number: usize,
+    failed: bool,
+    queued: VecDeque<(Token, usize, usize)>,
+}
+
+impl<R: BufRead> Lexer<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: vec![],
+            position: 0,
+            line_number: 0,
+            failed: false,
+            queued: VecDeque::new(),
+        }
+    }
+
+    /// The next character without consuming it, reading the next line once
+    /// the current one is used up. Lines starting with `%` are skipped.
+    fn peek(&mut self) -> Result<Option<char>, PgnError> {
+        while self.position >= self.line.len() {
+            if self.failed {
+                return Ok(None);
+            }
+
+            let mut buffer = String::new();
+            let read = self
+                .reader
+                .read_line(&mut buffer)
+                .inspect_err(|_| self.failed = true)?;
+            if read == 0 {
+                return Ok(None);
+            }
+
+            self.line_number += 1;
+            self.line = buffer.chars().collect();
+            self.position = 0;
+            if self.line.first() == Some(&'%') {
+                self.line.clear();
+            }
+        }
+
+        Ok(Some(self.line[self.position]))
+    }
+
+    fn bump(&mut self) -> Result<Option<char>, PgnError> {
+        let c = self.peek()?;
+        if c.is_some() {
+            self.position += 1;
+        }
+        Ok(c)
+    }
+
+    fn column(&self) -> usize {
+        self.position + 1
+    }
+
+    fn error(&self, column: usize, message: String) -> PgnError {
+        PgnError::Syntax {
+            line: self.line_number,
+            column,
+            message,
+        }
+    }
+
+    fn skip_whitespace(&mut self) -> Result<(), PgnError> {
+        while let Some(c) = self.peek()? {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.position += 1;
+        }
+        Ok(())
+    }
+
+    /// Puts a token back to be returned again by `next_token`.
+    fn push_back(&mut self, token: (Token, usize, usize)) {
+        self.queued.push_front(token);
+    }
+
+    /// The next token with the line and column it starts at, or `None` at
+    /// the end of the input.
+    fn next_token(&mut self) -> Result<Option<(Token, usize, usize)>, PgnError> {
+        loop {
+            if let Some(token) = self.queued.pop_front() {
+                return Ok(Some(token));
+            }
+
+            self.skip_whitespace()?;
+            let (line, column) = (self.line_number, self.column());
+            let c = match self.bump()? {
+                Some(c) => c,
+                None => return Ok(None),
+            };
+
+            let token = match c {
+                '[' => self.tag()?,
+                '{' => self.comment('}')?,
+                ';' => self.comment('\n')?,
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '*' => Token::Result("*".to_string()),
+                '$' => {
+                    let digits = self.take_while(|c| c.is_ascii_digit())?;
+                    let nag = digits
+                        .parse()
+                        .map_err(|_| self.error(column, "invalid NAG".to_string()))?;
+                    Token::Nag(nag)
+                }
+                c if c.is_alphanumeric() || "!?".contains(c) => {
+                    let symbol = c.to_string() + &self.take_while(is_symbol_char)?;
+                    match self.symbol(symbol, line, column) {
+                        Some(token) => token,
+                        None => continue,
+                    }
+                }
+                c => {
+                    return Err(self.error(column, format!("unexpected character '{}'", c)));
+                }
+            };
+
+            return Ok(Some((token, line, column)));
+        }
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> Result<String, PgnError> {
+        let mut text = String::new();
+        while let Some(c) = self.peek()? {
+            if !accept(c) {
+                break;
+            }
+            text.push(c);
+            self.position += 1;
+        }
+        Ok(text)
+    }
+
+    /// A tag pair after its opening `[`.
+    fn tag(&mut self) -> Result<Token, PgnError> {
+        self.skip_whitespace()?;
+        let name = self.take_while(|c| c.is_alphanumeric() || c == '_')?;
+        if name.is_empty() {
+            return Err(self.error(self.column(), "expected a tag name".to_string()));
+        }
+
+        self.skip_whitespace()?;
+        if self.peek()? != Some('"') {
+            return Err(self.error(self.column(), "expected a quoted tag value".to_string()));
+        }
+        self.position += 1;
+
+        let mut value = String::new();
+        loop {
+            match self.bump()? {
+                Some('"') => break,
+                Some('\\') => match self.bump()? {
+                    Some(c @ ('"' | '\\')) => value.push(c),
+                    _ => return Err(self.error(self.column(), "invalid escape".to_string())),
+                },
+                Some('\n') | None => {
+                    return Err(self.error(self.column(), "unterminated tag value".to_string()))
+                }
+                Some(c) => value.push(c),
+            }
+        }
+
+        self.skip_whitespace()?;
+        if self.bump()? != Some(']') {
+            return Err(self.error(self.column(), "expected ']'".to_string()));
+        }
+
+        Ok(Token::Tag(name, value))
+    }
+
+    /// A comment running up to `end`, which may be on a later line.
+    fn comment(&mut self, end: char) -> Result<Token, PgnError> {
+        let (line, column) = (self.line_number, self.column());
+        let mut text = String::new();
+        loop {
+            match self.bump()? {
+                Some(c) if c == end => break,
+                Some(c) => text.push(c),
+                None if end == '\n' => break,
+                None => {
+                    return Err(PgnError::Syntax {
+                        line,
+                        column: column - 1,
+                        message: "unterminated comment".to_string(),
+                    })
+                }
+            }
+        }
+
+        Ok(Token::Comment(
+            text.split_whitespace().collect::<Vec<&str>>().join(" "),
+        ))
+    }
+
+    /// Makes sense of a run of symbol characters: a result, a move with any
+    /// `!`/`?` suffix turned into NAGs, or nothing for a bare move number.
+    fn symbol(&mut self, symbol: String, line: usize, column: usize) -> Option<Token> {
+        if ["1-0", "0-1", "1/2-1/2"].contains(&symbol.as_str()) {
+            return Some(Token::Result(symbol));
+        }
+
+        // Move numbers such as `12.` or `12...`, possibly run into the move.
+        let digits = symbol.chars().take_while(|c| c.is_ascii_digit()).count();
+        let rest = &symbol[digits..];
+        let rest = if digits > 0 && (rest.is_empty() || rest.starts_with('.')) {
+            rest.trim_start_matches('.')
+        } else {
+            &symbol
+        };
+        let offset = symbol.len() - rest.len();
+
+        let san = rest.trim_end_matches(['!', '?']);
+        let suffix = &rest[san.len()..];
+        if let Some(nag) = suffix_nag(suffix) {
+            self.queued
+                .push_back((Token::Nag(nag), line, column + offset + san.len()));
+        }
+
+        if san.is_empty() {
+            self.queued.pop_front().map(|(token, _, _)| token)
+        } else {
+            Some(Token::Move(san.to_string()))
+        }
+    }
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || "_+#=:-/.!?".contains(c)
+}
+
+/// The NAG for a traditional move suffix such as `!?`.
+fn suffix_nag(suffix: &str) -> Option<u8> {
+    match suffix {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    }
+}
+
+/// Reads games one at a time from PGN text, replaying every move so that
+/// each comes back as a `PgnGame` with legal moves only. After a game with
+/// an error the reader skips to the next game, so one bad game in a large
+/// file does not stop the rest from being read.
+///
+/// ```
+/// use chess_engine::pgn::PgnReader;
+///
+/// let pgn = "[White \"Morphy\"]\n\n1. e4 e5 2. Nf3 d6 *\n";
+/// let game = PgnReader::new(pgn.as_bytes()).next().unwrap().unwrap();
+/// assert_eq!(game.tag("White"), Some("Morphy"));
+/// assert_eq!(game.tree.mainline().len(), 4);
+/// ```
+pub struct PgnReader<R> {
+    lexer: Lexer<R>,
+}
+
+impl<R: BufRead> PgnReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lexer: Lexer::new(reader),
+        }
+    }
+
+    fn read_game(&mut self) -> Result<Option<PgnGame>, PgnError> {
+        let mut game: Option<PgnGame> = None;
+        let mut tags = vec![];
+        let mut fen_at = (0, 0);
+        let mut variations: Vec<NodeId> = vec![];
+        // Comments and NAGs between `(` and the first move of a variation
+        // belong to that move, which is not in the tree yet.
+        let mut variation_start = false;
+        let mut pending_comment: Option<String> = None;
+        let mut pending_nags = vec![];
+
+        loop {
+            let (token, line, column) = match self.lexer.next_token()? {
+                Some(token) => token,
+                None if game.is_none() && tags.is_empty() => return Ok(None),
+                None => {
+                    if !variations.is_empty() {
+                        return Err(self
+                            .lexer
+                            .error(self.lexer.column(), "unterminated variation".to_string()));
+                    }
+                    let game = match game {
+                        Some(game) => game,
+                        None => start_game(tags, fen_at)?,
+                    };
+                    return Ok(Some(finish_unterminated(game)));
+                }
+            };
+
+            if let Token::Tag(name, value) = token {
+                if let Some(game) = game {
+                    // A game without a result, followed by the next one.
+                    self.lexer
+                        .push_back((Token::Tag(name, value), line, column));
+                    return Ok(Some(finish_unterminated(game)));
+                }
+                if name == "FEN" {
+                    fen_at = (line, column);
+                }
+                tags.push((name, value));
+                continue;
+            }
+
+            if let Token::Result(result) = token {
+                if !variations.is_empty() {
+                    return Err(PgnError::Syntax {
+                        line,
+                        column,
+                        message: "result inside a variation".to_string(),
+                    });
+                }
+                let mut game = match game.take() {
+                    Some(game) => game,
+                    None => start_game(std::mem::take(&mut tags), fen_at)?,
+                };
+                game.result = result;
+                return Ok(Some(finish(game)));
+            }
+
+            let game = match &mut game {
+                Some(game) => game,
+                None => game.insert(start_game(std::mem::take(&mut tags), fen_at)?),
+            };
+            let tree = &mut game.tree;
+            let syntax = |message: &str| PgnError::Syntax {
+                line,
+                column,
+                message: message.to_string(),
+            };
+
+            match token {
+                Token::Tag(..) | Token::Result(_) => unreachable!(),
+                Token::Move(san) => {
+                    let m = tree
+                        .game()
+                        .parse_san(&san)
+                        .map_err(|error| PgnError::IllegalMove {
+                            line,
+                            column,
+                            san: san.clone(),
+                            error,
+                        })?;
+                    tree.play(m);
+
+                    let id = tree.current();
+                    if let Some(text) = pending_comment.take() {
+                        add_comment(tree, id, text);
+                    }
+                    for nag in pending_nags.drain(..) {
+                        tree.add_nag(id, nag);
+                    }
+                    variation_start = false;
+                }
+                Token::Comment(text) if variation_start => {
+                    pending_comment = Some(match pending_comment.take() {
+                        Some(old) => format!("{} {}", old, text),
+                        None => text,
+                    });
+                }
+                Token::Comment(text) => add_comment(tree, tree.current(), text),
+                Token::Nag(nag) if variation_start => pending_nags.push(nag),
+                Token::Nag(nag) => tree.add_nag(tree.current(), nag),
+                Token::Open => {
+                    let current = tree.current();
+                    tree.undo()
+                        .ok_or_else(|| syntax("variation before the first move"))?;
+                    variations.push(current);
+                    variation_start = true;
+                }
+                Token::Close => {
+                    let current = variations.pop().ok_or_else(|| syntax("unmatched ')'"))?;
+                    tree.go_to(current);
+                    variation_start = false;
+                    pending_comment = None;
+                    pending_nags.clear();
+                }
+            }
+        }
+    }
+
+    /// Skips what is left of a game after an error, up to its result or the
+    /// tags of the next game.
+    fn skip_game(&mut self) {
+        loop {
+            match self.lexer.next_token() {
+                Ok(None) | Ok(Some((Token::Result(_), _, _))) => return,
+                Ok(Some((token @ Token::Tag(..), line, column))) => {
+                    self.lexer.push_back((token, line, column));
+                    return;
+                }
+                Ok(Some(_)) => (),
+                Err(_) if self.lexer.failed => return,
+                Err(_) => (),
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnReader<R> {
+    type Item = Result<PgnGame, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_game() {
+            Ok(game) => game.map(Ok),
+            Err(error) => {
+                self.skip_game();
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Adds `text` to the comment on `id`, after whatever is there already.
+fn add_comment(tree: &mut GameTree, id: NodeId, text: String) {
+    let comment = match &tree.node(id).comment {
+        Some(old) => format!("{} {}", old, text),
+        None => text,
+    };
+    tree.set_comment(id, &comment);
+}
+
+/// A game with `tags`, starting from the position of the `FEN` tag if there
+/// is one.
+fn start_game(tags: Vec<(String, String)>, fen_at: (usize, usize)) -> Result<PgnGame, PgnError> {
+    let fen = tags.iter().find(|(name, _)| name == "FEN");
+    let tree = match fen {
+        Some((_, fen)) => GameTree::from_fen(fen).map_err(|error| PgnError::InvalidFen {
+            line: fen_at.0,
+            column: fen_at.1,
+            error,
+        })?,
+        None => GameTree::new(),
+    };
+
+    let mut game = PgnGame::with_tree(tree);
+    game.tags = tags;
+    Ok(game)
+}
+
+/// Finishes a game whose movetext stopped without a result, taking the
+/// result from its tags instead.
+fn finish_unterminated(mut game: PgnGame) -> PgnGame {
+    if let Some(result) = game.tag("Result") {
+        game.result = result.to_string();
+    }
+    finish(game)
+}
+
+/// Leaves the cursor at the end of the main line, so that `tree.game()` is
+/// the final position.
+fn finish(mut game: PgnGame) -> PgnGame {
+    let tree = &mut game.tree;
+    let mut end = tree.root();
+    while let Some(&child) = tree.node(end).children.first() {
+        end = child;
+    }
+    tree.go_to(end);
+    game
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Outcome;
+
+    fn read_all(pgn: &str) -> Vec<Result<PgnGame, PgnError>> {
+        PgnReader::new(pgn.as_bytes()).collect()
+    }
+
+    fn read_one(pgn: &str) -> PgnGame {
+        let mut games = read_all(pgn);
+        assert_eq!(games.len(), 1);
+        games.remove(0).unwrap()
+    }
+
+    fn mainline(game: &PgnGame) -> Vec<String> {
+        game.tree.mainline().iter().map(|m| m.to_uci()).collect()
+    }
+
+    const IMMORTAL: &str = r#"[Event "London"]
+[Site "London ENG"]
+[Date "1851.06.21"]
+[Round "?"]
+[White "Adolf Anderssen"]
+[Black "Lionel Kieseritzky"]
+[Result "1-0"]
+[ECO "C33"]
+
+1. e4 e5 2. f4 exf4 3. Bc4 Qh4+ 4. Kf1 b5 5. Bxb5 Nf6 6. Nf3 Qh6 7. d3 Nh5 8.
+Nh4 Qg5 9. Nf5 c6 10. g4 Nf6 11. Rg1 cxb5 12. h4 Qg6 13. h5 Qg5 14. Qf3 Ng8 15.
+Bxf4 Qf6 16. Nc3 Bc5 17. Nd5 Qxb2 18. Bd6 Bxg1 19. e5 Qxa1+ 20. Ke2 Na6 21.
+Nxg7+ Kd8 22. Qf6+ Nxf6 23. Be7# 1-0
+"#;
+
+    #[test]
+    fn test_read_game() {
+        let game = read_one(IMMORTAL);
+
+        assert_eq!(game.tag("White"), Some("Adolf Anderssen"));
+        assert_eq!(game.tag("ECO"), Some("C33"));
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.tree.mainline().len(), 45);
+        assert_eq!(
+            game.tree.game().outcome(),
+            Some(Outcome::Checkmate {
+                winner: Color::White
+            })
+        );
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let game = read_one(IMMORTAL);
+        assert_eq!(game.to_string(), IMMORTAL.to_string() + "\n");
+    }
+
+    #[test]
+    fn test_comments_nags_and_variations() {
+        let pgn = "1. e4 {King's pawn} e5 $1 (1... c5!? 2. Nf3 (2. c3) 2... d6) (1... e6) \
+                   2. Nf3?! ; a comment to the end of the line\n Nc6 *";
+        let game = read_one(pgn);
+        let tree = &game.tree;
+
+        assert_eq!(mainline(&game), vec!["e2e4", "e7e5", "g1f3", "b8c6"]);
+        let e4 = tree.node(tree.root()).children[0];
+        assert_eq!(tree.node(e4).comment.as_deref(), Some("King's pawn"));
+        assert_eq!(tree.node(e4).children.len(), 3);
+
+        let e5 = tree.node(e4).children[0];
+        assert_eq!(tree.node(e5).nags, vec![1]);
+        let c5 = tree.node(e4).children[1];
+        assert_eq!(tree.node(c5).nags, vec![5]);
+        assert_eq!(tree.node(c5).children.len(), 2);
+
+        let main_nf3 = tree.node(e5).children[0];
+        assert_eq!(tree.node(main_nf3).nags, vec![6]);
+        assert_eq!(
+            tree.node(main_nf3).comment.as_deref(),
+            Some("a comment to the end of the line")
+        );
+        assert_eq!(game.result, "*");
+    }
+
+    #[test]
+    fn test_write_variations() {
+        let pgn = "1. e4 {King's pawn} e5 $1 (1... c5 2. Nf3 (2. c3) 2... d6) 2. Nf3 *";
+        let game = read_one(pgn);
+        let text = game.to_string();
+        let movetext = text.split("\n\n").nth(1).unwrap();
+
+        assert_eq!(
+            movetext,
+            "1. e4 {King's pawn} 1... e5 $1 (1... c5 2. Nf3 (2. c3) 2... d6) 2. Nf3 *"
+        );
+
+        let reread = read_one(&text);
+        assert_eq!(reread.to_string(), text);
+    }
+
+    #[test]
+    fn test_comment_and_nag_opening_a_variation() {
+        let game = read_one("1. e4 e5 ({Sicilian} $5 1... c5 {sharp}) *");
+        let tree = &game.tree;
+
+        let e4 = tree.node(tree.root()).children[0];
+        assert_eq!(tree.node(e4).comment, None);
+        assert!(tree.node(e4).nags.is_empty());
+        let c5 = tree.node(e4).children[1];
+        assert_eq!(tree.node(c5).comment.as_deref(), Some("Sicilian sharp"));
+        assert_eq!(tree.node(c5).nags, vec![5]);
+    }
+
+    #[test]
+    fn test_closing_brace_in_comment() {
+        let mut game = read_one("1. e4 *");
+        let e4 = game.tree.node(game.tree.root()).children[0];
+        game.tree.set_comment(e4, "a {nested} comment");
+
+        let text = game.to_string();
+        assert!(text.contains("1. e4 {a {nested comment} *"), "{}", text);
+        let reread = read_one(&text);
+        assert_eq!(mainline(&reread), vec!["e2e4"]);
+        assert_eq!(reread.to_string(), text);
+    }
+
+    #[test]
+    fn test_fen_tag() {
+        let pgn = r#"[SetUp "1"]
+[FEN "4k3/8/8/8/8/8/4P3/4K3 b - - 0 12"]
+
+12... Kd7 13. e4 *"#;
+        let game = read_one(pgn);
+
+        assert_eq!(mainline(&game), vec!["e8d7", "e2e4"]);
+        let text = game.to_string();
+        assert!(text.contains("[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 12\"]"));
+        assert!(text.contains("12... Kd7 13. e4 *"), "{}", text);
+    }
+
+    #[test]
+    fn test_en_passant_check_is_written_plainly() {
+        let pgn = "[SetUp \"1\"]\n[FEN \"8/2k5/8/3pP3/8/8/8/4K3 w - d6 0 1\"]\n\n1. exd6+ *";
+        let text = read_one(pgn).to_string();
+        assert!(text.ends_with("\n\n1. exd6+ *\n\n"), "{}", text);
+    }
+
+    #[test]
+    fn test_invalid_fen_tag() {
+        let games = read_all("[Event \"x\"]\n[FEN \"8/8/8 w - - 0 1\"]\n\n1. e4 *\n");
+        match &games[0] {
+            Err(PgnError::InvalidFen { line, column, .. }) => assert_eq!((*line, *column), (2, 1)),
+            _ => panic!("expected an invalid FEN"),
+        }
+    }
+
+    #[test]
+    fn test_illegal_move_position() {
+        let pgn =
+            "[Event \"Bad\"]\n\n1. e4 e5\n2. Nf3 Ke7 3. Ke3 Kd6 *\n\n[Event \"Good\"]\n\n1. d4 *\n";
+        let games = read_all(pgn);
+        assert_eq!(games.len(), 2);
+
+        match &games[0] {
+            Err(
+                error @ PgnError::IllegalMove {
+                    line, column, san, ..
+                },
+            ) => {
+                assert_eq!((*line, *column, san.as_str()), (4, 15, "Ke3"));
+                assert!(error
+                    .to_string()
+                    .starts_with("line 4, column 15: illegal move Ke3"));
+            }
+            _ => panic!("expected an illegal move"),
+        }
+        let good = games[1].as_ref().unwrap();
+        assert_eq!(good.tag("Event"), Some("Good"));
+        assert_eq!(mainline(good), vec!["d2d4"]);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        for pgn in [
+            "1. e4 e5) *",
+            "(1. e4) *",
+            "1. e4 (1. d4 *",
+            "1. e4 {open",
+            "[Event \"x]",
+        ] {
+            let games = read_all(pgn);
+            assert!(matches!(games[0], Err(PgnError::Syntax { .. })), "{}", pgn);
+        }
+    }
+
+    #[test]
+    fn test_games_without_results_or_tags() {
+        let games = read_all("1. e4 e5\n\n[Event \"Second\"]\n[Result \"0-1\"]\n\n1. d4\n");
+        assert_eq!(games.len(), 2);
+
+        let first = games[0].as_ref().unwrap();
+        assert_eq!(mainline(first), vec!["e2e4", "e7e5"]);
+        assert_eq!(first.result, "*");
+
+        let second = games[1].as_ref().unwrap();
+        assert_eq!(second.result, "0-1");
+        assert_eq!(mainline(second), vec!["d2d4"]);
+    }
+
+    #[test]
+    fn test_escape_lines_and_move_numbers_run_together() {
+        let game = read_one("% skipped\n1.e4 1...e5 2.Nf3 *");
+        assert_eq!(mainline(&game), vec!["e2e4", "e7e5", "g1f3"]);
+    }
+
+    #[test]
+    fn test_lines_are_wrapped() {
+        let game = read_one(IMMORTAL);
+        let mut long = PgnGame::with_tree(game.tree);
+        let first = long.tree.node(long.tree.root()).children[0];
+        long.tree.set_comment(first, &"word ".repeat(40));
+
+        for line in long.to_string().lines() {
+            assert!(line.len() <= 79, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_write_defaults_and_escapes() {
+        let mut game = PgnGame::new();
+        game.set_tag("White", "A \"quoted\" name");
+        let text = game.to_string();
+
+        assert!(text.starts_with("[Event \"?\"]\n[Site \"?\"]\n[Date \"????.??.??\"]"));
+        assert!(text.contains("[White \"A \\\"quoted\\\" name\"]"));
+        assert!(text.ends_with("\n\n*\n\n"));
+    }
+}
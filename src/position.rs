@@ -1,24 +1,29 @@
+use crate::game::Game;
 use crate::utils::*;
+use crate::zobrist;
 use bitflags::bitflags;
-use std::collections::VecDeque;
+use std::fmt;
 
 type PiecePosition = u64;
 
+/// The name of the square of a single-bit bitboard, e.g. `"e4"`.
 pub fn bit_to_position(bit: PiecePosition) -> Result<String, String> {
     if bit == 0 {
-        return Err("No piece present!".to_string());
+        Err("No piece present!".to_string())
     } else {
         let onebit_index = bit_scan(bit);
-        return Ok(index_to_position(onebit_index));
+        Ok(index_to_position(onebit_index))
     }
 }
 
+/// The index (a1 = 0, h8 = 63) of a square name such as `"e4"`.
 pub fn position_to_index(position: &str) -> Result<usize, String> {
     let bitboard = position_to_bit(position)?;
 
     Ok(bit_scan(bitboard))
 }
 
+/// The single-bit bitboard of a square name such as `"e4"`.
 pub fn position_to_bit(position: &str) -> Result<PiecePosition, String> {
     if position.len() != 2 {
         return Err(format!(
@@ -30,7 +35,7 @@ pub fn position_to_bit(position: &str) -> Result<PiecePosition, String> {
 
     let bytes = position.as_bytes();
     let byte0 = bytes[0];
-    if byte0 < 97 || byte0 >= 97 + 8 {
+    if !(97..97 + 8).contains(&byte0) {
         return Err(format!(
             "Invalid column character: {}, string: '{}'",
             byte0 as char, position
@@ -44,7 +49,7 @@ pub fn position_to_bit(position: &str) -> Result<PiecePosition, String> {
 
     match (byte1 as char).to_digit(10) {
         Some(number) => {
-            if number < 1 || number > 8 {
+            if !(1..=8).contains(&number) {
                 return Err(format!(
                     "Invalid row character: {}, string: '{}'",
                     byte1 as char, position
@@ -62,17 +67,18 @@ pub fn position_to_bit(position: &str) -> Result<PiecePosition, String> {
     }
 
     let square_number = row * 8 + column;
-    let bit = (1 as u64) << square_number;
+    let bit = 1_u64 << square_number;
 
     Ok(bit)
 }
 
 static COL_MAP: [char; 8] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h'];
 
+/// The name of the square with index `index`, e.g. `"e4"` for 28.
 pub fn index_to_position(index: usize) -> String {
     let column = index % 8;
     let row = index / 8 + 1;
-    return format!("{}{}", COL_MAP[column], row);
+    format!("{}{}", COL_MAP[column], row)
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -101,6 +107,8 @@ pub enum PieceType {
     King,
 }
 
+/// A piece and where it stands. Captured pieces stay in `Position::pieces`
+/// with `alive` cleared so that piece indices remain stable.
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub struct Piece {
     pub position: PiecePosition,
@@ -110,25 +118,31 @@ pub struct Piece {
 }
 
 impl Piece {
-    fn to_string(&self) -> String {
-        let mut result = match self.piece_type {
-            PieceType::Pawn => "p ",
-            PieceType::Rook => "r ",
-            PieceType::Knight => "n ",
-            PieceType::Bishop => "b ",
-            PieceType::Queen => "q ",
-            PieceType::King => "k ",
-        }
-        .to_string();
+    /// The FEN letter for this piece, upper case for White.
+    pub fn fen_char(&self) -> char {
+        let ch = match self.piece_type {
+            PieceType::Pawn => 'p',
+            PieceType::Rook => 'r',
+            PieceType::Knight => 'n',
+            PieceType::Bishop => 'b',
+            PieceType::Queen => 'q',
+            PieceType::King => 'k',
+        };
 
-        if self.color == Color::White {
-            result.make_ascii_uppercase();
+        match self.color {
+            Color::White => ch.to_ascii_uppercase(),
+            Color::Black => ch,
         }
+    }
+}
 
-        result
+impl fmt::Display for Piece {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ", self.fen_char())
     }
 }
 
+/// The contents of a square: nothing, or an index into `Position::pieces`.
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Square {
     Empty,
@@ -137,6 +151,8 @@ pub enum Square {
 use Square::*;
 
 bitflags! {
+    /// The castling moves each side may still make, as in the FEN castling
+    /// field.
     pub struct CastlingRights: u8 {
         const NONE = 0;
         const WHITEKINGSIDE = 1 << 0;
@@ -150,6 +166,286 @@ bitflags! {
             | Self::BLACKQUEENSIDE.bits;
     }
 }
+
+bitflags! {
+    /// What kind of move a `Move` is, beyond its squares and promotion.
+    pub struct MoveFlags: u8 {
+        const QUIET = 0;
+        const CAPTURE = 1 << 0;
+        const EN_PASSANT = 1 << 1;
+        const CASTLE = 1 << 2;
+        const DOUBLE_PUSH = 1 << 3;
+    }
+}
+
+/// A single move, small enough to be copied around freely by the move
+/// generators and the search.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Move {
+    from: u8,
+    to: u8,
+    promotion: Option<PieceType>,
+    flags: MoveFlags,
+}
+
+impl Move {
+    pub fn new(from: usize, to: usize, flags: MoveFlags) -> Self {
+        Self {
+            from: from as u8,
+            to: to as u8,
+            promotion: None,
+            flags,
+        }
+    }
+
+    pub fn with_promotion(from: usize, to: usize, promotion: PieceType, flags: MoveFlags) -> Self {
+        Self {
+            from: from as u8,
+            to: to as u8,
+            promotion: Some(promotion),
+            flags,
+        }
+    }
+
+    pub fn from(self) -> usize {
+        self.from as usize
+    }
+
+    pub fn to(self) -> usize {
+        self.to as usize
+    }
+
+    pub fn promotion(self) -> Option<PieceType> {
+        self.promotion
+    }
+
+    pub fn flags(self) -> MoveFlags {
+        self.flags
+    }
+
+    pub fn is_capture(self) -> bool {
+        self.flags.contains(MoveFlags::CAPTURE)
+    }
+
+    pub fn is_en_passant(self) -> bool {
+        self.flags.contains(MoveFlags::EN_PASSANT)
+    }
+
+    pub fn is_castle(self) -> bool {
+        self.flags.contains(MoveFlags::CASTLE)
+    }
+
+    pub fn is_double_push(self) -> bool {
+        self.flags.contains(MoveFlags::DOUBLE_PUSH)
+    }
+
+    /// The move in the long algebraic notation of the UCI protocol, e.g.
+    /// `e2e4`, `e7e8q`, or `e1g1` for castling; the same as `to_string`.
+    pub fn to_uci(self) -> String {
+        self.to_string()
+    }
+}
+
+/// Why a UCI move string was rejected by `Position::parse_uci_move`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum UciMoveError {
+    InvalidLength(String),
+    InvalidSquare(String),
+    InvalidPromotion(char),
+    IllegalMove(String),
+}
+
+impl fmt::Display for UciMoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UciMoveError::InvalidLength(uci) => {
+                write!(f, "'{}' is not 4 or 5 characters long", uci)
+            }
+            UciMoveError::InvalidSquare(square) => write!(f, "invalid square '{}'", square),
+            UciMoveError::InvalidPromotion(piece) => {
+                write!(f, "invalid promotion piece '{}'", piece)
+            }
+            UciMoveError::IllegalMove(uci) => write!(f, "'{}' is not a legal move", uci),
+        }
+    }
+}
+
+impl std::error::Error for UciMoveError {}
+
+/// The move among `legal_moves` that `uci` names, see
+/// `Position::parse_uci_move`.
+pub fn match_uci_move(uci: &str, legal_moves: &[Move]) -> Result<Move, UciMoveError> {
+    if !uci.is_ascii() || !(4..=5).contains(&uci.len()) {
+        return Err(UciMoveError::InvalidLength(uci.to_string()));
+    }
+
+    let square = |name: &str| {
+        position_to_index(name).map_err(|_| UciMoveError::InvalidSquare(name.to_string()))
+    };
+    let from = square(&uci[0..2])?;
+    let to = square(&uci[2..4])?;
+    let promotion = match uci[4..].chars().next() {
+        None => None,
+        Some('q') => Some(PieceType::Queen),
+        Some('r') => Some(PieceType::Rook),
+        Some('b') => Some(PieceType::Bishop),
+        Some('n') => Some(PieceType::Knight),
+        Some(piece) => return Err(UciMoveError::InvalidPromotion(piece)),
+    };
+
+    legal_moves
+        .iter()
+        .copied()
+        .find(|m| m.from() == from && m.to() == to && m.promotion() == promotion)
+        .ok_or_else(|| UciMoveError::IllegalMove(uci.to_string()))
+}
+
+/// Long algebraic notation, e.g. `e2e4` or `e7e8q`.
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}",
+            index_to_position(self.from()),
+            index_to_position(self.to())
+        )?;
+        match self.promotion {
+            Some(PieceType::Queen) => write!(f, "q"),
+            Some(PieceType::Rook) => write!(f, "r"),
+            Some(PieceType::Bishop) => write!(f, "b"),
+            Some(PieceType::Knight) => write!(f, "n"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Everything `unmake_move` needs to restore that cannot be derived from the
+/// move itself.
+#[derive(Debug, Copy, Clone)]
+pub struct Undo {
+    captured: Option<usize>,
+    en_passant: Option<PiecePosition>,
+    castling_rights: CastlingRights,
+    halfmove_clock: usize,
+}
+
+/// The fields of a FEN record, in order.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FenField {
+    Placement,
+    ActiveColor,
+    Castling,
+    EnPassant,
+    HalfmoveClock,
+    FullmoveNumber,
+}
+
+impl fmt::Display for FenField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FenField::Placement => "piece placement",
+            FenField::ActiveColor => "active color",
+            FenField::Castling => "castling rights",
+            FenField::EnPassant => "en passant square",
+            FenField::HalfmoveClock => "halfmove clock",
+            FenField::FullmoveNumber => "fullmove number",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Why a FEN string was rejected. Offsets count characters from the start of
+/// the string.
+#[derive(Debug, PartialEq, Clone)]
+pub enum FenError {
+    MissingField(FenField),
+    InvalidCharacter {
+        field: FenField,
+        offset: usize,
+        character: char,
+    },
+    WrongRankCount {
+        count: usize,
+    },
+    WrongRankLength {
+        rank: usize,
+        offset: usize,
+        length: usize,
+    },
+    InvalidSquare {
+        offset: usize,
+    },
+    InvalidNumber {
+        field: FenField,
+        offset: usize,
+    },
+    TrailingCharacters {
+        offset: usize,
+    },
+    WrongKingCount {
+        color: Color,
+        count: usize,
+    },
+    PawnOnBackRank {
+        square: usize,
+    },
+    InvalidEnPassant {
+        square: usize,
+    },
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing {}", field),
+            FenError::InvalidCharacter {
+                field,
+                offset,
+                character,
+            } => write!(
+                f,
+                "invalid character '{}' in {} at offset {}",
+                character, field, offset
+            ),
+            FenError::WrongRankCount { count } => {
+                write!(f, "expected 8 ranks in piece placement, found {}", count)
+            }
+            FenError::WrongRankLength {
+                rank,
+                offset,
+                length,
+            } => write!(
+                f,
+                "rank {} at offset {} describes {} squares instead of 8",
+                rank, offset, length
+            ),
+            FenError::InvalidSquare { offset } => {
+                write!(f, "invalid en passant square at offset {}", offset)
+            }
+            FenError::InvalidNumber { field, offset } => {
+                write!(f, "invalid {} at offset {}", field, offset)
+            }
+            FenError::TrailingCharacters { offset } => {
+                write!(f, "unexpected text after the FEN at offset {}", offset)
+            }
+            FenError::WrongKingCount { color, count } => {
+                write!(f, "expected one {:?} king, found {}", color, count)
+            }
+            FenError::PawnOnBackRank { square } => {
+                write!(f, "pawn on {}", index_to_position(*square))
+            }
+            FenError::InvalidEnPassant { square } => write!(
+                f,
+                "en passant square {} does not follow a double pawn push",
+                index_to_position(*square)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+/// The board and the rest of the state a FEN record describes.
 #[derive(Clone)]
 pub struct Position {
     pub pieces: Vec<Piece>,
@@ -162,6 +458,70 @@ pub struct Position {
 
     pub white_occupancy: Bitboard,
     pub black_occupancy: Bitboard,
+    /// One bitboard per color and piece type, indexed by `Color as usize`
+    /// and `PieceType as usize`; see `bitboard`.
+    pub piece_bitboards: [[Bitboard; 6]; 2],
+
+    /// Zobrist hash of the pieces, side to move, castling rights and en
+    /// passant file, kept up to date by every change to the position.
+    pub hash: u64,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Two positions are equal when they would be written as the same FEN, no
+/// matter how their piece lists are laid out.
+impl PartialEq for Position {
+    fn eq(&self, other: &Self) -> bool {
+        let same_board = (0..64).all(|square| {
+            let kind = |position: &Position| {
+                position
+                    .piece_at(square)
+                    .map(|piece| (piece.color, piece.piece_type))
+            };
+            kind(self) == kind(other)
+        });
+
+        same_board
+            && self.active_color == other.active_color
+            && self.castling_rights == other.castling_rights
+            && self.en_passant == other.en_passant
+            && self.halfmove_clock == other.halfmove_clock
+            && self.fullmove_number == other.fullmove_number
+    }
+}
+
+impl fmt::Debug for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Position(\"{}\")", self.to_fen())
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut board = "".to_owned();
+        let mut temp = "".to_owned();
+
+        for (i, square) in self.squares.iter().enumerate() {
+            match square {
+                Square::Empty => temp.push_str(". "), //temp.push_str(&index_to_position(i)),
+                Square::Occupied(idx) => temp.push_str(&self.pieces[*idx].to_string()),
+            }
+
+            if (i + 1) % 8 == 0 {
+                temp.push('\n');
+                board.insert_str(0, &temp);
+                temp.clear();
+            }
+        }
+        board.insert_str(0, &temp);
+
+        write!(f, "{}", board)
+    }
 }
 
 impl Position {
@@ -173,9 +533,9 @@ impl Position {
         index: &mut usize,
     ) {
         self.pieces.push(Piece {
-            position: (1 as u64) << position,
-            color: color,
-            piece_type: piece_type,
+            position: 1_u64 << position,
+            color,
+            piece_type,
             alive: true,
         });
         self.squares.push(Square::Occupied(*index));
@@ -185,6 +545,8 @@ impl Position {
             Black => self.black_occupancy |= bitboard,
             White => self.white_occupancy |= bitboard,
         }
+        self.piece_bitboards[color as usize][piece_type as usize] |= bitboard;
+        self.hash ^= zobrist::piece_key(color, piece_type, position);
 
         *index += 1;
     }
@@ -193,166 +555,342 @@ impl Position {
         self.squares.push(Square::Empty);
     }
 
+    /// The standard starting position.
     pub fn new() -> Position {
         Position::read_FEN("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
     }
 
-    pub fn to_string(&self) -> String {
-        let mut board = "".to_owned();
-        let mut temp = "".to_owned();
+    /// The legal move named by `uci` in the long algebraic notation of the
+    /// UCI protocol, e.g. `e2e4` or `e7e8q`. This builds the attack tables
+    /// the move generator needs each time; with a `Game` at hand, use
+    /// `Game::parse_uci_move` instead.
+    pub fn parse_uci_move(&self, uci: &str) -> Result<Move, UciMoveError> {
+        Game::with_position(self.clone()).parse_uci_move(uci)
+    }
 
-        for (i, square) in self.squares.iter().enumerate() {
-            match square {
-                Square::Empty => temp.push_str(". "), //temp.push_str(&index_to_position(i)),
-                Square::Occupied(idx) => temp.push_str(&self.pieces[*idx].to_string()),
+    /// Writes the position in Forsyth-Edwards Notation; `read_FEN` reads it
+    /// back into an equal position.
+    pub fn to_fen(&self) -> String {
+        let mut placement = String::new();
+        for row in (0..8).rev() {
+            let mut empty_squares = 0;
+            for col in 0..8 {
+                match self.piece_at(row * 8 + col) {
+                    None => empty_squares += 1,
+                    Some(piece) => {
+                        if empty_squares > 0 {
+                            placement.push_str(&empty_squares.to_string());
+                            empty_squares = 0;
+                        }
+                        placement.push(piece.fen_char());
+                    }
+                }
             }
+            if empty_squares > 0 {
+                placement.push_str(&empty_squares.to_string());
+            }
+            if row > 0 {
+                placement.push('/');
+            }
+        }
 
-            if (i + 1) % 8 == 0 {
-                temp.push_str("\n");
-                board.insert_str(0, &temp);
-                temp.clear();
+        let active_color = match self.active_color {
+            White => "w",
+            Black => "b",
+        };
+
+        let mut castling = String::new();
+        for (right, ch) in [
+            (CastlingRights::WHITEKINGSIDE, 'K'),
+            (CastlingRights::WHITEQUEENSIDE, 'Q'),
+            (CastlingRights::BLACKKINGSIDE, 'k'),
+            (CastlingRights::BLACKQUEENSIDE, 'q'),
+        ] {
+            if self.castling_rights.contains(right) {
+                castling.push(ch);
             }
         }
-        board.insert_str(0, &temp);
+        if castling.is_empty() {
+            castling.push('-');
+        }
+
+        let en_passant = match self.en_passant {
+            Some(bit) => index_to_position(bit_scan(bit)),
+            None => "-".to_string(),
+        };
 
-        board
+        format!(
+            "{} {} {} {} {} {}",
+            placement,
+            active_color,
+            castling,
+            en_passant,
+            self.halfmove_clock,
+            self.fullmove_number
+        )
     }
 
+    /// An empty board with White to move.
     pub fn empty() -> Self {
         Position::read_FEN("8/8/8/8/8/8/8/8 w - - 0 1")
     }
 
+    /// Parses a FEN string, rejecting malformed fields as well as positions
+    /// that cannot arise in a game (see `validate`).
+    pub fn from_fen(fen: &str) -> Result<Position, FenError> {
+        let position = Position::parse_fen(fen)?;
+        position.validate()?;
+
+        Ok(position)
+    }
+
+    /// Reads a FEN string without the checks of `validate`, so partial setups
+    /// such as a lone knight can be loaded. Panics on malformed input.
     #[allow(non_snake_case)]
     pub fn read_FEN(fen: &str) -> Position {
+        match Position::parse_fen(fen) {
+            Ok(position) => position,
+            Err(err) => panic!("Invalid FEN '{}': {}", fen, err),
+        }
+    }
+
+    fn parse_fen(fen: &str) -> Result<Position, FenError> {
         let mut game = Position {
             pieces: vec![],
-            squares: vec![],
+            squares: vec![Empty; 64],
             active_color: Color::White,
-            castling_rights: CastlingRights::ALL,
+            castling_rights: CastlingRights::NONE,
             en_passant: None,
             halfmove_clock: 0,
             fullmove_number: 1,
             white_occupancy: 0,
             black_occupancy: 0,
+            piece_bitboards: [[0; 6]; 2],
+            hash: 0,
         };
 
-        let (position, rest) = split_on(fen, ' ');
+        let mut fields = fen_fields(fen).into_iter();
+        let mut next_field = |field: FenField| fields.next().ok_or(FenError::MissingField(field));
 
-        let mut deque_squares = VecDeque::new();
-        let mut piece_index = 0;
-        let mut piece_position = 64;
+        let (offset, placement) = next_field(FenField::Placement)?;
+        game.parse_placement(placement, offset)?;
 
-        for row in position.splitn(8, |ch| ch == '/') {
-            piece_position -= 8;
-            let (pieces, squares) = parse_row(&row, piece_index, piece_position);
+        let (offset, color_to_move) = next_field(FenField::ActiveColor)?;
+        game.active_color = match color_to_move {
+            "w" => Color::White,
+            "b" => Color::Black,
+            other => {
+                let (i, character) = match other.chars().next() {
+                    Some('w') | Some('b') => other.chars().enumerate().nth(1).unwrap(),
+                    _ => (0, other.chars().next().unwrap()),
+                };
+                return Err(FenError::InvalidCharacter {
+                    field: FenField::ActiveColor,
+                    offset: offset + i,
+                    character,
+                });
+            }
+        };
 
-            for p in pieces {
-                let position = p.position;
-                match p.color {
-                    Black => game.black_occupancy |= position,
-                    White => game.white_occupancy |= position,
+        let (offset, castling_rights) = next_field(FenField::Castling)?;
+        if castling_rights != "-" {
+            for (i, ch) in castling_rights.chars().enumerate() {
+                let right = match ch {
+                    'K' => CastlingRights::WHITEKINGSIDE,
+                    'Q' => CastlingRights::WHITEQUEENSIDE,
+                    'k' => CastlingRights::BLACKKINGSIDE,
+                    'q' => CastlingRights::BLACKQUEENSIDE,
+                    _ => CastlingRights::NONE,
+                };
+                if right.is_empty() || game.castling_rights.contains(right) {
+                    return Err(FenError::InvalidCharacter {
+                        field: FenField::Castling,
+                        offset: offset + i,
+                        character: ch,
+                    });
                 }
-                game.pieces.push(p);
-                piece_index += 1;
+                game.castling_rights |= right;
             }
-            for s in squares {
-                deque_squares.push_front(s);
+        }
+
+        let (offset, en_passant) = next_field(FenField::EnPassant)?;
+        if en_passant != "-" {
+            match position_to_bit(en_passant) {
+                Ok(bit) => game.en_passant = Some(bit),
+                Err(_) => return Err(FenError::InvalidSquare { offset }),
             }
         }
 
-        game.squares = Vec::from(deque_squares);
+        // The clocks are often left out, e.g. in EPD records.
+        if let Ok((offset, halfmove_clock)) = next_field(FenField::HalfmoveClock) {
+            game.halfmove_clock = halfmove_clock
+                .parse()
+                .map_err(|_| FenError::InvalidNumber {
+                    field: FenField::HalfmoveClock,
+                    offset,
+                })?;
+        }
+        if let Ok((offset, fullmove_number)) = next_field(FenField::FullmoveNumber) {
+            game.fullmove_number =
+                fullmove_number
+                    .parse()
+                    .map_err(|_| FenError::InvalidNumber {
+                        field: FenField::FullmoveNumber,
+                        offset,
+                    })?;
+        }
 
-        let (color_to_move, rest) = split_on(rest, ' ');
-        game.active_color = match color_to_move {
-            "w" => Color::White,
-            "b" => Color::Black,
-            _ => panic!("Unknown color designator: '{}'", color_to_move),
-        };
+        if let Some((offset, _)) = fields.next() {
+            return Err(FenError::TrailingCharacters { offset });
+        }
 
-        let (castling_rights, rest) = split_on(rest, ' ');
-        let mut castling = CastlingRights::NONE;
-        for ch in castling_rights.chars() {
-            match ch {
-                'K' => castling |= CastlingRights::WHITEKINGSIDE,
-                'Q' => castling |= CastlingRights::WHITEQUEENSIDE,
-                'k' => castling |= CastlingRights::BLACKKINGSIDE,
-                'q' => castling |= CastlingRights::BLACKQUEENSIDE,
-                '-' => (),
-                other => panic!("Invalid character in castling rights: '{}'", other),
-            }
-        }
-        game.castling_rights = castling;
-
-        let (en_passant, rest) = split_on(rest, ' ');
-        match en_passant {
-            "-" => game.en_passant = None,
-            s => match position_to_bit(s) {
-                Err(msg) => panic!("{}", msg),
-                Ok(bit) => game.en_passant = Some(bit),
-            },
-        };
+        game.hash = game.compute_hash();
 
-        let (halfmove_clock, rest) = split_on(rest, ' ');
-        match halfmove_clock.parse() {
-            Ok(number) => game.halfmove_clock = number,
-            Err(_) => panic!("Invalid halfmove: {}", halfmove_clock),
+        Ok(game)
+    }
+
+    fn parse_placement(&mut self, placement: &str, offset: usize) -> Result<(), FenError> {
+        let ranks = placement.split('/').collect::<Vec<&str>>();
+        if ranks.len() != 8 {
+            return Err(FenError::WrongRankCount { count: ranks.len() });
         }
 
-        let (fullmove_number, rest) = split_on(rest, ' ');
-        match fullmove_number.parse() {
-            Ok(number) => game.fullmove_number = number,
-            Err(_) => panic!("Invalid halfmove: {}", fullmove_number),
+        let mut rank_offset = offset;
+        for (i, rank) in ranks.iter().enumerate() {
+            let row = 7 - i;
+            let mut col = 0;
+
+            for (j, ch) in rank.chars().enumerate() {
+                let invalid_character = FenError::InvalidCharacter {
+                    field: FenField::Placement,
+                    offset: rank_offset + j,
+                    character: ch,
+                };
+
+                if let Some(number) = ch.to_digit(10) {
+                    if !(1..=8).contains(&number) {
+                        return Err(invalid_character);
+                    }
+                    col += number as usize;
+                    continue;
+                }
+
+                let piece_type = match ch.to_ascii_lowercase() {
+                    'p' => PieceType::Pawn,
+                    'r' => PieceType::Rook,
+                    'n' => PieceType::Knight,
+                    'b' => PieceType::Bishop,
+                    'q' => PieceType::Queen,
+                    'k' => PieceType::King,
+                    _ => return Err(invalid_character),
+                };
+                if col < 8 {
+                    let color = if ch.is_ascii_uppercase() {
+                        White
+                    } else {
+                        Black
+                    };
+                    let square = row * 8 + col;
+                    self.pieces.push(Piece {
+                        position: 1 << square,
+                        color,
+                        piece_type,
+                        alive: true,
+                    });
+                    self.place_piece(self.pieces.len() - 1, square);
+                }
+                col += 1;
+            }
+
+            if col != 8 {
+                return Err(FenError::WrongRankLength {
+                    rank: row + 1,
+                    offset: rank_offset,
+                    length: col,
+                });
+            }
+            rank_offset += rank.chars().count() + 1;
         }
 
-        game
+        Ok(())
+    }
+
+    /// Checks the rules every reachable position obeys: one king per side,
+    /// no pawns on the first or last rank, and an en passant square that lies
+    /// behind a pawn which just made a double push.
+    pub fn validate(&self) -> Result<(), FenError> {
+        for color in [White, Black] {
+            let count = self
+                .pieces
+                .iter()
+                .filter(|p| p.alive && p.color == color && p.piece_type == PieceType::King)
+                .count();
+            if count != 1 {
+                return Err(FenError::WrongKingCount { color, count });
+            }
+        }
+
+        for square in (0..8).chain(56..64) {
+            if let Some(piece) = self.piece_at(square) {
+                if piece.piece_type == PieceType::Pawn {
+                    return Err(FenError::PawnOnBackRank { square });
+                }
+            }
+        }
+
+        if let Some(bit) = self.en_passant {
+            let square = bit_scan(bit);
+            let (row, _) = rowcol(square);
+            let (expected_row, pawn_square, origin_square) = match self.active_color {
+                White => (6, square.wrapping_sub(8), square + 8),
+                Black => (3, square + 8, square.wrapping_sub(8)),
+            };
+
+            let consistent = row == expected_row
+                && self.piece_at(square).is_none()
+                && self.piece_at(origin_square).is_none()
+                && matches!(self.piece_at(pawn_square),
+                            Some(p) if p.piece_type == PieceType::Pawn
+                                && p.color != self.active_color);
+            if !consistent {
+                return Err(FenError::InvalidEnPassant { square });
+            }
+        }
+
+        Ok(())
     }
 
+    /// The number of pieces still on the board.
     pub fn count_pieces(&self) -> usize {
         self.pieces.iter().filter(|p| p.alive).count()
     }
 
-    pub fn move_piece(self: &mut Self, piece_position: PiecePosition, new_position: usize) {
-        let square_index = bit_scan(piece_position);
-        let square = self.squares[square_index];
-        let piece_index = match square {
-            Empty => panic!("Tried to move a piece from an empty square"),
-            Occupied(idx) => idx,
+    /// Moves the piece on `piece_position` to `new_position`, capturing
+    /// whatever stands there. Double pawn pushes and castling are recognised
+    /// from the distance moved.
+    pub fn move_piece(&mut self, piece_position: PiecePosition, new_position: usize) {
+        let from = bit_scan(piece_position);
+        let piece = match self.piece_at(from) {
+            None => panic!("Tried to move a piece from an empty square"),
+            Some(piece) => piece,
         };
-        self.pieces[piece_index].position = 1 << new_position;
-        self.squares[square_index] = Empty;
-
-        match self.squares[new_position] {
-            Empty => self.squares[new_position] = Occupied(piece_index),
-            Occupied(other_idx) => {
-                let other_piece = &self.pieces[other_idx];
-                if self.pieces[piece_index].color == other_piece.color {
-                    panic!("Cannot move a piece onto a square occupied by one of it's own color");
-                }
 
-                self.pieces[other_idx].alive = false;
-                self.squares[new_position] = Occupied(piece_index);
+        let mut flags = MoveFlags::QUIET;
+        if let Some(other_piece) = self.piece_at(new_position) {
+            if piece.color == other_piece.color {
+                panic!("Cannot move a piece onto a square occupied by one of it's own color");
             }
+            flags |= MoveFlags::CAPTURE;
         }
 
-        match self.pieces[piece_index].piece_type {
-            PieceType::Pawn => {
-                let (old_row, col) = rowcol(square_index);
-                let (new_row, _) = rowcol(new_position);
-                if (old_row - new_row).abs() == 2 {
-                    let direction = (new_row - old_row).signum();
-                    let pawn_left = self.has_pawn(old_row + 2 * direction, col + 1);
-                    let pawn_right = self.has_pawn(old_row + 2 * direction, col - 1);
-
-                    if pawn_left || pawn_right {
-                        let idx = index(old_row + 1 * direction, col).unwrap() as usize;
-                        self.en_passant = Some(1 << idx);
-                    } else {
-                        self.en_passant = None;
-                    }
-                }
-            }
-            _ => self.en_passant = None,
+        let distance = (from as i32 - new_position as i32).abs();
+        match piece.piece_type {
+            PieceType::Pawn if distance == 16 => flags |= MoveFlags::DOUBLE_PUSH,
+            PieceType::King if distance == 2 => flags |= MoveFlags::CASTLE,
+            _ => (),
         }
+
+        self.make_move(Move::new(from, new_position, flags));
     }
 
     fn has_pawn(&self, row: i32, col: i32) -> bool {
@@ -367,39 +905,24 @@ impl Position {
         }
     }
 
-    pub fn take_en_passant(
-        self: &mut Self,
-        piece_position: PiecePosition,
-        new_position: PiecePosition,
-    ) {
-        let square_index = bit_scan(piece_position);
-        let square = self.squares[square_index];
-        let piece_index = match square {
-            Empty => panic!("Tried to move a piece from an empty square"),
-            Occupied(idx) => idx,
+    pub fn take_en_passant(&mut self, piece_position: PiecePosition, new_position: PiecePosition) {
+        let from = bit_scan(piece_position);
+        let to = bit_scan(new_position);
+        let color = match self.piece_at(from) {
+            None => panic!("Tried to move a piece from an empty square"),
+            Some(piece) => piece.color,
         };
-        self.pieces[piece_index].position = new_position;
-        self.squares[square_index] = Empty;
-
-        if let Occupied(_) = self.squares[bit_scan(new_position)] {
+        if let Occupied(_) = self.squares[to] {
             panic!("Tried to take en passant onto an occupied square");
         }
 
-        let (old_row, old_col) = rowcol(square_index);
-        let (new_row, new_col) = rowcol(bit_scan(new_position));
-        let direction = (new_row - old_row).signum();
-
-        let taken_square = (new_row - direction, new_col);
-        let taken_index = index(taken_square.0, taken_square.1).unwrap();
-        match self.squares[taken_index] {
-            Occupied(pidx) => {
-                self.pieces[pidx].alive = false;
-                self.squares[taken_index] = Empty;
-            }
-            Empty => panic!("Tried to en passant but there was no pawn in expected square"),
+        let m = Move::new(from, to, MoveFlags::CAPTURE | MoveFlags::EN_PASSANT);
+        match self.piece_at(captured_square(m, color)) {
+            Some(piece) if piece.piece_type == PieceType::Pawn => (),
+            _ => panic!("Tried to en passant but there was no pawn in expected square"),
         }
 
-        self.en_passant = None;
+        self.make_move(m);
     }
 
     pub fn perform_promotion(
@@ -408,127 +931,457 @@ impl Position {
         new_index: usize,
         promotion_type: PieceType,
     ) {
-        let square_index = bit_scan(piece_position);
-        let square = self.squares[square_index];
-        let piece_index = match square {
+        let from = bit_scan(piece_position);
+        if let Empty = self.squares[from] {
+            panic!("Tried to move a piece from an empty square");
+        }
+        if let Occupied(_) = self.squares[new_index] {
+            panic!("Tried to promote onto an occupied square");
+        }
+
+        match promotion_type {
+            PieceType::Queen | PieceType::Bishop | PieceType::Knight | PieceType::Rook => {
+                self.make_move(Move::with_promotion(
+                    from,
+                    new_index,
+                    promotion_type,
+                    MoveFlags::QUIET,
+                ));
+            }
+            other => panic!("{}", format!("Cannot promote to {other:?}!")),
+        }
+    }
+
+    /// Applies `m` in place and returns the record needed to take it back.
+    ///
+    /// This is the one place where a move changes the position: besides the
+    /// pieces it updates the side to move, castling rights, en passant
+    /// square, halfmove clock and fullmove number as FEN describes them.
+    pub fn make_move(&mut self, m: Move) -> Undo {
+        let from = m.from();
+        let to = m.to();
+        let piece_index = match self.squares[from] {
             Empty => panic!("Tried to move a piece from an empty square"),
             Occupied(idx) => idx,
         };
-        let current_piece = self.pieces[piece_index];
+        let color = self.pieces[piece_index].color;
+        let is_pawn_move = self.pieces[piece_index].piece_type == PieceType::Pawn;
+
+        let mut undo = Undo {
+            captured: None,
+            en_passant: self.en_passant,
+            castling_rights: self.castling_rights,
+            halfmove_clock: self.halfmove_clock,
+        };
+        self.hash ^= self.state_hash();
 
-        let new_position = 1 << new_index as u64;
-        self.pieces[piece_index].alive = false;
-        self.squares[square_index] = Empty;
-        if let Occupied(_) = self.squares[new_index] {
-            panic!("Tried to promote onto an occupied square");
+        let captured_square = captured_square(m, color);
+        if let Occupied(other_idx) = self.squares[captured_square] {
+            if self.pieces[other_idx].color == color {
+                panic!("Cannot move a piece onto a square occupied by one of it's own color");
+            }
+            self.remove_piece(captured_square);
+            self.pieces[other_idx].alive = false;
+            undo.captured = Some(other_idx);
         }
 
-        macro_rules! promote {
-            ($type:ident) => {{
-                let promoted_piece = Piece {
-                    position: new_position,
-                    color: current_piece.color,
-                    piece_type: PieceType::$type,
-                    alive: true,
-                };
-                self.pieces.push(promoted_piece);
-                self.squares[new_index] = Occupied(self.pieces.len() - 1);
-            }};
+        self.remove_piece(from);
+        if let Some(promotion_type) = m.promotion() {
+            self.pieces[piece_index].piece_type = promotion_type;
         }
+        self.place_piece(piece_index, to);
 
-        match promotion_type {
-            PieceType::Queen => promote!(Queen),
-            PieceType::Bishop => promote!(Bishop),
-            PieceType::Knight => promote!(Knight),
-            PieceType::Rook => promote!(Rook),
-            other => panic!("{}", format!("Cannot promote to {other:?}!")),
+        if m.is_castle() {
+            let (rook_from, rook_to) = castling_rook_squares(to);
+            let rook_index = match self.squares[rook_from] {
+                Empty => panic!("Tried to castle without a rook in the corner"),
+                Occupied(idx) => idx,
+            };
+            self.remove_piece(rook_from);
+            self.place_piece(rook_index, rook_to);
         }
-    }
 
-    pub fn add(self: &mut Self, piece_color: Color, piece_type: PieceType, square: &str) {
-        let index = square_to_index(square);
+        self.castling_rights
+            .remove(castling_rights_lost(from) | castling_rights_lost(to));
 
-        let piece = Piece {
-            position: 1 << index,
-            color: piece_color,
-            piece_type: piece_type,
-            alive: true,
+        // The square behind a double-pushed pawn is recorded whether or not
+        // an enemy pawn is in place to capture it.
+        self.en_passant = if m.is_double_push() {
+            Some(1 << ((from + to) / 2))
+        } else {
+            None
         };
 
-        self.pieces.push(piece);
-        match self.squares[index] {
-            Empty => self.squares[index] = Occupied(self.pieces.len() - 1),
-            _ => panic!("Square {} is already occupied", index),
+        if is_pawn_move || undo.captured.is_some() {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
         }
-
-        match piece_color {
-            White => self.white_occupancy |= 1 << index,
-            Black => self.black_occupancy |= 1 << index,
+        if color == Black {
+            self.fullmove_number += 1;
         }
+        self.active_color = color.opposite();
+        self.hash ^= self.state_hash();
+
+        self.debug_assert_consistent();
+
+        undo
     }
-}
 
-pub fn square_to_index(square: &str) -> usize {
-    assert_eq!(square.as_bytes().len(), 2);
+    /// Takes back `m`, which must be the last move applied with `make_move`.
+    pub fn unmake_move(&mut self, m: Move, undo: Undo) {
+        let from = m.from();
+        let to = m.to();
+        let piece_index = match self.squares[to] {
+            Empty => panic!("Tried to unmake a move but the target square is empty"),
+            Occupied(idx) => idx,
+        };
+        let color = self.pieces[piece_index].color;
+        self.hash ^= self.state_hash();
 
-    let col: u8 = square.as_bytes()[0] - ('a' as u8);
-    let row: u8 = square.as_bytes()[1] - ('1' as u8);
+        self.remove_piece(to);
+        if m.promotion().is_some() {
+            self.pieces[piece_index].piece_type = PieceType::Pawn;
+        }
+        self.place_piece(piece_index, from);
 
-    (row * 8 + col) as usize
-}
+        if m.is_castle() {
+            let (rook_from, rook_to) = castling_rook_squares(to);
+            let rook_index = match self.squares[rook_to] {
+                Empty => panic!("Tried to uncastle without a rook next to the king"),
+                Occupied(idx) => idx,
+            };
+            self.remove_piece(rook_to);
+            self.place_piece(rook_index, rook_from);
+        }
+
+        if let Some(other_idx) = undo.captured {
+            self.pieces[other_idx].alive = true;
+            self.place_piece(other_idx, captured_square(m, color));
+        }
 
-fn parse_row(
-    row: &str,
-    mut piece_index: usize,
-    mut piece_position: usize,
-) -> (Vec<Piece>, VecDeque<Square>) {
-    let mut pieces = Vec::new();
-    let mut squares = VecDeque::new();
+        self.en_passant = undo.en_passant;
+        self.castling_rights = undo.castling_rights;
+        self.halfmove_clock = undo.halfmove_clock;
+        if color == Black {
+            self.fullmove_number -= 1;
+        }
+        self.active_color = color;
+        self.hash ^= self.state_hash();
 
-    let mut color;
+        self.debug_assert_consistent();
+    }
 
-    macro_rules! add_piece {
-        ($piece_type:ident) => {{
-            let piece = Piece {
-                color: color,
-                position: (1 as u64) << piece_position,
-                piece_type: PieceType::$piece_type,
-                alive: true,
-            };
-            let square = Square::Occupied(piece_index);
-            pieces.push(piece);
-            squares.push_front(square);
-            piece_position += 1;
-            piece_index += 1;
-        }};
-    }
-
-    for ch in row.chars() {
-        let is_upper = ch.is_ascii_uppercase();
-        color = if is_upper { Color::White } else { Color::Black };
-        match ch.to_ascii_lowercase() {
-            'r' => add_piece!(Rook),
-            'n' => add_piece!(Knight),
-            'b' => add_piece!(Bishop),
-            'q' => add_piece!(Queen),
-            'k' => add_piece!(King),
-            'p' => add_piece!(Pawn),
-            num => match num.to_digit(10) {
-                None => panic!("Invalid input: {}", num),
-                Some(number) => {
-                    for i in 0..number {
-                        squares.push_front(Square::Empty);
-                        piece_position += 1;
-                    }
-                }
-            },
+    /// The piece on `square`, if any.
+    pub fn piece_at(&self, square: usize) -> Option<&Piece> {
+        match self.squares[square] {
+            Empty => None,
+            Occupied(idx) => Some(&self.pieces[idx]),
         }
     }
 
-    (pieces, squares)
-}
+    /// The squares of all pieces of `color` and `piece_type`.
+    pub fn bitboard(&self, color: Color, piece_type: PieceType) -> Bitboard {
+        self.piece_bitboards[color as usize][piece_type as usize]
+    }
 
-#[cfg(test)]
+    /// The squares of all pieces of `color`.
+    pub fn occupancy(&self, color: Color) -> Bitboard {
+        match color {
+            White => self.white_occupancy,
+            Black => self.black_occupancy,
+        }
+    }
+
+    /// The Zobrist hash of the position computed from scratch; `hash` holds
+    /// the same value, maintained incrementally.
+    pub fn compute_hash(&self) -> u64 {
+        let mut hash = self.state_hash();
+        for (square, content) in self.squares.iter().enumerate() {
+            if let Occupied(idx) = content {
+                let piece = &self.pieces[*idx];
+                hash ^= zobrist::piece_key(piece.color, piece.piece_type, square);
+            }
+        }
+
+        hash
+    }
+
+    /// The part of the hash that does not depend on where the pieces are.
+    /// `make_move` and `unmake_move` xor it out before changing anything and
+    /// back in once they are done.
+    fn state_hash(&self) -> u64 {
+        let mut hash = zobrist::castling_key(self.castling_rights) ^ self.en_passant_hash();
+        if self.active_color == Black {
+            hash ^= zobrist::black_to_move_key();
+        }
+
+        hash
+    }
+
+    /// The en passant file only counts when a pawn of the side to move stands
+    /// next to the pawn that just made a double push, so that positions with
+    /// the same possible moves hash the same.
+    fn en_passant_hash(&self) -> u64 {
+        let square = match self.en_passant {
+            Some(bit) => bit_scan(bit),
+            None => return 0,
+        };
+        let pushed_square = match self.active_color {
+            White => square.checked_sub(8),
+            Black => Some(square + 8).filter(|&s| s < 64),
+        };
+        let pushed_square = match pushed_square {
+            Some(pushed_square) => pushed_square,
+            None => return 0,
+        };
+
+        let file = pushed_square % 8;
+        let neighbours = [
+            Some(pushed_square.wrapping_sub(1)).filter(|_| file > 0),
+            Some(pushed_square + 1).filter(|_| file < 7),
+        ];
+        let can_capture = neighbours.into_iter().flatten().any(|s| {
+            matches!(self.piece_at(s),
+                     Some(p) if p.piece_type == PieceType::Pawn && p.color == self.active_color)
+        });
+
+        if can_capture {
+            zobrist::en_passant_key(file)
+        } else {
+            0
+        }
+    }
+
+    // Every change to the board goes through `place_piece` and `remove_piece`,
+    // which keep `squares`, the pieces' positions, the bitboards and the hash
+    // in step.
+    fn place_piece(&mut self, piece_index: usize, square: usize) {
+        let bit = 1 << square;
+        let piece = &mut self.pieces[piece_index];
+        piece.position = bit;
+        match piece.color {
+            White => self.white_occupancy |= bit,
+            Black => self.black_occupancy |= bit,
+        }
+        self.piece_bitboards[piece.color as usize][piece.piece_type as usize] |= bit;
+        self.hash ^= zobrist::piece_key(piece.color, piece.piece_type, square);
+        self.squares[square] = Occupied(piece_index);
+    }
+
+    fn remove_piece(&mut self, square: usize) {
+        let bit = 1 << square;
+        if let Occupied(idx) = self.squares[square] {
+            let piece = &self.pieces[idx];
+            self.piece_bitboards[piece.color as usize][piece.piece_type as usize] &= !bit;
+            self.hash ^= zobrist::piece_key(piece.color, piece.piece_type, square);
+        }
+        self.white_occupancy &= !bit;
+        self.black_occupancy &= !bit;
+        self.squares[square] = Empty;
+    }
+
+    /// Puts a new piece on the empty square named `square`.
+    pub fn add(&mut self, piece_color: Color, piece_type: PieceType, square: &str) {
+        let index = square_to_index(square);
+
+        let piece = Piece {
+            position: 1 << index,
+            color: piece_color,
+            piece_type,
+            alive: true,
+        };
+
+        if let Occupied(_) = self.squares[index] {
+            panic!("Square {} is already occupied", index);
+        }
+        self.pieces.push(piece);
+        self.place_piece(self.pieces.len() - 1, index);
+
+        self.debug_assert_consistent();
+    }
+
+    /// Verifies that `squares`, `pieces`, the bitboards and the hash all
+    /// describe the same position, returning a description of the first
+    /// disagreement found.
+    pub fn check_consistency(&self) -> Result<(), String> {
+        if self.squares.len() != 64 {
+            return Err(format!("Expected 64 squares, found {}", self.squares.len()));
+        }
+        if self.white_occupancy & self.black_occupancy != 0 {
+            return Err(format!(
+                "White and black occupancy overlap on {}",
+                index_to_position(bit_scan(self.white_occupancy & self.black_occupancy))
+            ));
+        }
+
+        let mut white_occupancy = 0;
+        let mut black_occupancy = 0;
+        let mut piece_bitboards = [[0; 6]; 2];
+        for (square, content) in self.squares.iter().enumerate() {
+            if let Occupied(idx) = content {
+                let piece = &self.pieces[*idx];
+                if !piece.alive {
+                    return Err(format!(
+                        "Captured piece {} still on {}",
+                        idx,
+                        index_to_position(square)
+                    ));
+                }
+                if piece.position != 1 << square {
+                    return Err(format!(
+                        "Piece {} is on {} but thinks it is on {:?}",
+                        idx,
+                        index_to_position(square),
+                        bit_to_position(piece.position)
+                    ));
+                }
+                match piece.color {
+                    White => white_occupancy |= 1 << square,
+                    Black => black_occupancy |= 1 << square,
+                }
+                piece_bitboards[piece.color as usize][piece.piece_type as usize] |= 1 << square;
+            }
+        }
+
+        for (idx, piece) in self.pieces.iter().enumerate() {
+            if piece.alive && self.squares[bit_scan(piece.position)] != Occupied(idx) {
+                return Err(format!(
+                    "Piece {} on {:?} is missing from its square",
+                    idx,
+                    bit_to_position(piece.position)
+                ));
+            }
+        }
+
+        if white_occupancy != self.white_occupancy {
+            return Err(format!(
+                "White occupancy is {:#x}, squares say {:#x}",
+                self.white_occupancy, white_occupancy
+            ));
+        }
+        if black_occupancy != self.black_occupancy {
+            return Err(format!(
+                "Black occupancy is {:#x}, squares say {:#x}",
+                self.black_occupancy, black_occupancy
+            ));
+        }
+
+        for color in [White, Black] {
+            for piece_type in [
+                PieceType::Pawn,
+                PieceType::Rook,
+                PieceType::Knight,
+                PieceType::Bishop,
+                PieceType::Queen,
+                PieceType::King,
+            ] {
+                let expected = piece_bitboards[color as usize][piece_type as usize];
+                if self.bitboard(color, piece_type) != expected {
+                    return Err(format!(
+                        "{:?} {:?} bitboard is {:#x}, squares say {:#x}",
+                        color,
+                        piece_type,
+                        self.bitboard(color, piece_type),
+                        expected
+                    ));
+                }
+            }
+        }
+
+        let hash = self.compute_hash();
+        if hash != self.hash {
+            return Err(format!(
+                "Hash is {:#x}, recomputing it gives {:#x}",
+                self.hash, hash
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn debug_assert_consistent(&self) {
+        if cfg!(debug_assertions) {
+            if let Err(msg) = self.check_consistency() {
+                panic!("Inconsistent position: {}\n{}", msg, self);
+            }
+        }
+    }
+}
+
+/// Splits a FEN into its whitespace separated fields, each paired with the
+/// character offset at which it starts.
+fn fen_fields(fen: &str) -> Vec<(usize, &str)> {
+    let mut fields = vec![];
+    let mut start = None;
+
+    for (char_offset, (byte_offset, ch)) in fen.char_indices().enumerate() {
+        if ch.is_whitespace() {
+            if let Some((char_start, byte_start)) = start.take() {
+                fields.push((char_start, &fen[byte_start..byte_offset]));
+            }
+        } else if start.is_none() {
+            start = Some((char_offset, byte_offset));
+        }
+    }
+    if let Some((char_start, byte_start)) = start {
+        fields.push((char_start, &fen[byte_start..]));
+    }
+
+    fields
+}
+
+/// The castling rights that are gone once a piece moves from or to `square`,
+/// either because the king or rook left it or because the rook was captured.
+fn castling_rights_lost(square: usize) -> CastlingRights {
+    match square {
+        0 => CastlingRights::WHITEQUEENSIDE,
+        4 => CastlingRights::WHITEKINGSIDE | CastlingRights::WHITEQUEENSIDE,
+        7 => CastlingRights::WHITEKINGSIDE,
+        56 => CastlingRights::BLACKQUEENSIDE,
+        60 => CastlingRights::BLACKKINGSIDE | CastlingRights::BLACKQUEENSIDE,
+        63 => CastlingRights::BLACKKINGSIDE,
+        _ => CastlingRights::NONE,
+    }
+}
+
+/// The rook's origin and destination for a castling move whose king lands on
+/// `king_to`.
+fn castling_rook_squares(king_to: usize) -> (usize, usize) {
+    match king_to {
+        6 => (7, 5),
+        2 => (0, 3),
+        62 => (63, 61),
+        58 => (56, 59),
+        other => panic!("{} is not a castling destination", other),
+    }
+}
+
+/// The square of the piece taken by `m`, which differs from the target square
+/// only for en passant.
+fn captured_square(m: Move, color: Color) -> usize {
+    let to = m.to();
+    if m.is_en_passant() {
+        match color {
+            White => to - 8,
+            Black => to + 8,
+        }
+    } else {
+        to
+    }
+}
+
+/// Like `position_to_index`, but panics on malformed square names.
+pub fn square_to_index(square: &str) -> usize {
+    assert_eq!(square.len(), 2);
+
+    let col: u8 = square.as_bytes()[0] - b'a';
+    let row: u8 = square.as_bytes()[1] - b'1';
+
+    (row * 8 + col) as usize
+}
+
+#[cfg(test)]
 mod tests {
     use super::PieceType::*;
     use super::*;
@@ -544,6 +1397,8 @@ mod tests {
             fullmove_number: 1,
             white_occupancy: 0,
             black_occupancy: 0,
+            piece_bitboards: [[0; 6]; 2],
+            hash: 0,
         };
         let mut piece_index = 0;
 
@@ -572,7 +1427,7 @@ mod tests {
         }
 
         let offset = 56;
-        game.push_piece_and_square(0 + offset, color, PieceType::Rook, &mut piece_index);
+        game.push_piece_and_square(offset, color, PieceType::Rook, &mut piece_index);
         game.push_piece_and_square(1 + offset, color, PieceType::Knight, &mut piece_index);
         game.push_piece_and_square(2 + offset, color, PieceType::Bishop, &mut piece_index);
         game.push_piece_and_square(3 + offset, color, PieceType::Queen, &mut piece_index);
@@ -645,11 +1500,14 @@ mod tests {
         let right_chars = ["K", "Q", "k", "q"];
         for i in 0..(2 ^ 4) {
             let bitflag_rights = CastlingRights::from_bits(i).unwrap();
-            for j in 0..4 {
+            for (j, right_char) in right_chars.iter().enumerate() {
                 if (i >> j) & 1 != 0 {
-                    rights.push_str(right_chars[j]);
+                    rights.push_str(right_char);
                 }
             }
+            if rights.is_empty() {
+                rights.push('-');
+            }
             let fen = format!(
                 "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w {} - 0 1",
                 rights
@@ -664,6 +1522,197 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_from_fen_accepts_valid_positions() {
+        for fen in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2",
+        ] {
+            assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn test_from_fen_defaults_missing_clocks() {
+        let game = Position::from_fen("4k3/8/8/8/8/8/8/4K3 b -  - ").unwrap();
+        assert_eq!(game.active_color, Color::Black);
+        assert_eq!(game.halfmove_clock, 0);
+        assert_eq!(game.fullmove_number, 1);
+    }
+
+    fn fen_error(fen: &str) -> FenError {
+        Position::from_fen(fen).unwrap_err()
+    }
+
+    #[test]
+    fn test_fen_error_missing_field() {
+        assert_eq!(fen_error(""), FenError::MissingField(FenField::Placement));
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w"),
+            FenError::MissingField(FenField::Castling)
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w -"),
+            FenError::MissingField(FenField::EnPassant)
+        );
+    }
+
+    #[test]
+    fn test_fen_error_invalid_character() {
+        assert_eq!(
+            fen_error("4k3/8/8/3x4/8/8/8/4K3 w - - 0 1"),
+            FenError::InvalidCharacter {
+                field: FenField::Placement,
+                offset: 9,
+                character: 'x'
+            }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 W - - 0 1"),
+            FenError::InvalidCharacter {
+                field: FenField::ActiveColor,
+                offset: 20,
+                character: 'W'
+            }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w KQkK - 0 1"),
+            FenError::InvalidCharacter {
+                field: FenField::Castling,
+                offset: 25,
+                character: 'K'
+            }
+        );
+    }
+
+    #[test]
+    fn test_fen_error_rank_count_and_length() {
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/4K3 w - - 0 1"),
+            FenError::WrongRankCount { count: 7 }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/44p/8/8/4K3 w - - 0 1"),
+            FenError::WrongRankLength {
+                rank: 4,
+                offset: 10,
+                length: 9
+            }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/7/4K3 w - - 0 1"),
+            FenError::WrongRankLength {
+                rank: 2,
+                offset: 14,
+                length: 7
+            }
+        );
+    }
+
+    #[test]
+    fn test_fen_error_bad_square_and_numbers() {
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w - e9 0 1"),
+            FenError::InvalidSquare { offset: 24 }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w - - x 1"),
+            FenError::InvalidNumber {
+                field: FenField::HalfmoveClock,
+                offset: 26
+            }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w - - 0 -1"),
+            FenError::InvalidNumber {
+                field: FenField::FullmoveNumber,
+                offset: 28
+            }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w - - 0 1 extra"),
+            FenError::TrailingCharacters { offset: 30 }
+        );
+    }
+
+    #[test]
+    fn test_fen_error_king_count() {
+        assert_eq!(
+            fen_error("8/8/8/8/8/8/8/4K3 w - - 0 1"),
+            FenError::WrongKingCount {
+                color: Color::Black,
+                count: 0
+            }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/3KK3 w - - 0 1"),
+            FenError::WrongKingCount {
+                color: Color::White,
+                count: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_fen_error_pawn_on_back_rank() {
+        assert_eq!(
+            fen_error("4k2P/8/8/8/8/8/8/4K3 w - - 0 1"),
+            FenError::PawnOnBackRank { square: 63 }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/p3K3 w - - 0 1"),
+            FenError::PawnOnBackRank { square: 0 }
+        );
+    }
+
+    #[test]
+    fn test_fen_error_en_passant_mismatch() {
+        // Wrong rank for the side to move.
+        assert_eq!(
+            fen_error("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e3 0 1"),
+            FenError::InvalidEnPassant { square: 20 }
+        );
+        // No pawn in front of the square.
+        assert_eq!(
+            fen_error("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq e3 0 1"),
+            FenError::InvalidEnPassant { square: 20 }
+        );
+        // The square the pawn came from is occupied.
+        assert_eq!(
+            fen_error("rnbqkbnr/pppppppp/8/8/4P3/8/PPPPPPPP/RNBQKBNR b KQkq e3 0 1"),
+            FenError::InvalidEnPassant { square: 20 }
+        );
+    }
+
+    #[test]
+    fn test_fen_error_offsets_count_characters() {
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w - - é 1"),
+            FenError::InvalidNumber {
+                field: FenField::HalfmoveClock,
+                offset: 26
+            }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w - - é x"),
+            FenError::InvalidNumber {
+                field: FenField::HalfmoveClock,
+                offset: 26
+            }
+        );
+        assert_eq!(
+            fen_error("4k3/8/8/8/8/8/8/4K3 w - - 0 1 é x"),
+            FenError::TrailingCharacters { offset: 30 }
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_read_fen_panics_on_malformed_input() {
+        Position::read_FEN("4k3/8/8 w - - 0 1");
+    }
+
     #[test]
     fn test_occupancy_start_position() {
         let start = Position::new();
@@ -765,4 +1814,592 @@ mod tests {
         let b3_index = position_to_index("b3").unwrap();
         game.take_en_passant(1 << c4_index as u64, 1 << b3_index as u64);
     }
+
+    fn assert_same_board(a: &Position, b: &Position) {
+        assert_eq!(a.to_string(), b.to_string());
+        assert_eq!(a.white_occupancy, b.white_occupancy);
+        assert_eq!(a.black_occupancy, b.black_occupancy);
+        assert_eq!(a.en_passant, b.en_passant);
+        assert_eq!(a.castling_rights, b.castling_rights);
+        assert_eq!(a.halfmove_clock, b.halfmove_clock);
+        assert_eq!(a.fullmove_number, b.fullmove_number);
+        assert_eq!(a.active_color, b.active_color);
+    }
+
+    #[test]
+    fn test_make_move() {
+        let mut game = Position::new();
+        let e2 = square_to_index("e2");
+        let e4 = square_to_index("e4");
+        let pawn = game.squares[e2];
+        game.make_move(Move::new(e2, e4, MoveFlags::DOUBLE_PUSH));
+
+        assert_eq!(game.squares[e2], Empty);
+        assert_eq!(game.squares[e4], pawn);
+        assert_eq!(game.white_occupancy & (1 << e2), 0);
+        assert_ne!(game.white_occupancy & (1 << e4), 0);
+        assert_eq!(game.en_passant, Some(position_to_bit("e3").unwrap()));
+    }
+
+    #[test]
+    fn test_make_unmake_restores_position() {
+        let mut game = Position::new();
+        let before = game.clone();
+        let m = Move::new(
+            square_to_index("g1"),
+            square_to_index("f3"),
+            MoveFlags::QUIET,
+        );
+
+        let undo = game.make_move(m);
+        game.unmake_move(m, undo);
+
+        assert_same_board(&game, &before);
+    }
+
+    #[test]
+    fn test_make_unmake_capture() {
+        let mut game = Position::empty();
+        game.add(White, Rook, "e3");
+        game.add(Black, Knight, "e6");
+        let before = game.clone();
+        let m = Move::new(
+            square_to_index("e3"),
+            square_to_index("e6"),
+            MoveFlags::CAPTURE,
+        );
+
+        let undo = game.make_move(m);
+        assert_eq!(game.count_pieces(), 1);
+        assert_eq!(game.black_occupancy, 0);
+
+        game.unmake_move(m, undo);
+        assert_eq!(game.count_pieces(), 2);
+        assert_same_board(&game, &before);
+    }
+
+    #[test]
+    fn test_make_unmake_en_passant() {
+        let mut game = Position::empty();
+        game.add(White, Pawn, "d2");
+        game.add(Black, Pawn, "c4");
+        game.make_move(Move::new(
+            square_to_index("d2"),
+            square_to_index("d4"),
+            MoveFlags::DOUBLE_PUSH,
+        ));
+        assert_eq!(game.en_passant, Some(position_to_bit("d3").unwrap()));
+        let before = game.clone();
+
+        let m = Move::new(
+            square_to_index("c4"),
+            square_to_index("d3"),
+            MoveFlags::CAPTURE | MoveFlags::EN_PASSANT,
+        );
+        let undo = game.make_move(m);
+        assert_eq!(game.count_pieces(), 1);
+        assert_eq!(game.squares[square_to_index("d4")], Empty);
+        assert_eq!(game.en_passant, None);
+
+        game.unmake_move(m, undo);
+        assert_same_board(&game, &before);
+    }
+
+    #[test]
+    fn test_make_unmake_promotion() {
+        let mut game = Position::empty();
+        game.add(White, Pawn, "e7");
+        let before = game.clone();
+
+        let m = Move::with_promotion(
+            square_to_index("e7"),
+            square_to_index("e8"),
+            Knight,
+            MoveFlags::QUIET,
+        );
+        let undo = game.make_move(m);
+        assert_eq!(game.pieces[0].piece_type, Knight);
+        assert_eq!(game.pieces.len(), 1);
+
+        game.unmake_move(m, undo);
+        assert_eq!(game.pieces[0].piece_type, Pawn);
+        assert_same_board(&game, &before);
+    }
+    #[test]
+    fn test_make_unmake_castling() {
+        let mut game = Position::read_FEN("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let before = game.clone();
+
+        let m = Move::new(
+            square_to_index("e1"),
+            square_to_index("g1"),
+            MoveFlags::CASTLE,
+        );
+        let undo = game.make_move(m);
+        assert_eq!(
+            game.piece_at(square_to_index("g1")).unwrap().piece_type,
+            King
+        );
+        assert_eq!(
+            game.piece_at(square_to_index("f1")).unwrap().piece_type,
+            Rook
+        );
+        assert!(game.piece_at(square_to_index("h1")).is_none());
+        assert_eq!(
+            game.castling_rights,
+            CastlingRights::BLACKKINGSIDE | CastlingRights::BLACKQUEENSIDE
+        );
+
+        game.unmake_move(m, undo);
+        assert_same_board(&game, &before);
+
+        let m = Move::new(
+            square_to_index("e8"),
+            square_to_index("c8"),
+            MoveFlags::CASTLE,
+        );
+        game.make_move(m);
+        assert_eq!(
+            game.piece_at(square_to_index("c8")).unwrap().piece_type,
+            King
+        );
+        assert_eq!(
+            game.piece_at(square_to_index("d8")).unwrap().piece_type,
+            Rook
+        );
+        assert!(game.piece_at(square_to_index("a8")).is_none());
+    }
+
+    #[test]
+    fn test_rook_move_clears_castling_right() {
+        let mut game = Position::read_FEN("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        game.make_move(Move::new(
+            square_to_index("a1"),
+            square_to_index("a5"),
+            MoveFlags::QUIET,
+        ));
+        assert_eq!(
+            game.castling_rights,
+            CastlingRights::ALL - CastlingRights::WHITEQUEENSIDE
+        );
+    }
+
+    #[test]
+    fn test_rook_capture_clears_castling_right() {
+        let mut game = Position::read_FEN("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        game.make_move(Move::new(
+            square_to_index("h1"),
+            square_to_index("h8"),
+            MoveFlags::CAPTURE,
+        ));
+        assert_eq!(
+            game.castling_rights,
+            CastlingRights::WHITEQUEENSIDE | CastlingRights::BLACKQUEENSIDE
+        );
+    }
+
+    #[test]
+    fn test_move_piece_clears_castling_rights() {
+        let mut game = Position::read_FEN("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        game.move_piece(game.pieces[1].position, square_to_index("e7"));
+        assert_eq!(
+            game.castling_rights,
+            CastlingRights::WHITEKINGSIDE | CastlingRights::WHITEQUEENSIDE
+        );
+    }
+    #[test]
+    fn test_move_piece_updates_occupancy() {
+        let mut game = Position::empty();
+        game.add(White, Rook, "e3");
+        game.add(Black, Knight, "e6");
+
+        game.move_piece(1 << square_to_index("e3"), square_to_index("e6"));
+
+        assert_eq!(game.white_occupancy, 1 << square_to_index("e6"));
+        assert_eq!(game.black_occupancy, 0);
+        assert!(game.check_consistency().is_ok());
+    }
+
+    #[test]
+    fn test_take_en_passant_updates_occupancy() {
+        let mut game = Position::empty();
+        game.add(White, Pawn, "d2");
+        game.add(Black, Pawn, "c4");
+        game.move_piece(game.pieces[0].position, position_to_index("d4").unwrap());
+
+        game.take_en_passant(
+            position_to_bit("c4").unwrap(),
+            position_to_bit("d3").unwrap(),
+        );
+
+        assert_eq!(game.white_occupancy, 0);
+        assert_eq!(game.black_occupancy, position_to_bit("d3").unwrap());
+        assert!(game.check_consistency().is_ok());
+    }
+
+    #[test]
+    fn test_perform_promotion_updates_occupancy() {
+        let mut game = Position::empty();
+        game.add(White, Pawn, "e7");
+
+        game.perform_promotion(position_to_bit("e7").unwrap(), square_to_index("e8"), Queen);
+
+        assert_eq!(game.white_occupancy, position_to_bit("e8").unwrap());
+        assert_eq!(
+            game.piece_at(square_to_index("e8")).unwrap().piece_type,
+            Queen
+        );
+        assert!(game.check_consistency().is_ok());
+    }
+
+    #[test]
+    fn test_check_consistency_detects_stale_occupancy() {
+        let mut game = Position::new();
+        assert!(game.check_consistency().is_ok());
+
+        game.white_occupancy ^= 1 << square_to_index("e2");
+        assert!(game.check_consistency().is_err());
+    }
+
+    #[test]
+    fn test_check_consistency_detects_misplaced_piece() {
+        let mut game = Position::new();
+        game.squares
+            .swap(square_to_index("e2"), square_to_index("e4"));
+        assert!(game.check_consistency().is_err());
+    }
+    #[test]
+    fn test_make_move_updates_side_and_clocks() {
+        let mut game = Position::read_FEN("4k3/8/8/8/8/8/4P3/4K1N1 w - - 7 12");
+
+        game.make_move(Move::new(
+            square_to_index("g1"),
+            square_to_index("f3"),
+            MoveFlags::QUIET,
+        ));
+        assert_eq!(game.active_color, Black);
+        assert_eq!(game.halfmove_clock, 8);
+        assert_eq!(game.fullmove_number, 12);
+
+        game.make_move(Move::new(
+            square_to_index("e8"),
+            square_to_index("d8"),
+            MoveFlags::QUIET,
+        ));
+        assert_eq!(game.active_color, White);
+        assert_eq!(game.halfmove_clock, 9);
+        assert_eq!(game.fullmove_number, 13);
+
+        game.make_move(Move::new(
+            square_to_index("e2"),
+            square_to_index("e3"),
+            MoveFlags::QUIET,
+        ));
+        assert_eq!(game.halfmove_clock, 0);
+        assert_eq!(game.fullmove_number, 13);
+    }
+
+    #[test]
+    fn test_capture_resets_halfmove_clock() {
+        let mut game = Position::read_FEN("4k3/8/8/8/8/4n3/8/4KN2 b - - 5 30");
+        let before = game.clone();
+
+        let m = Move::new(
+            square_to_index("e3"),
+            square_to_index("g2"),
+            MoveFlags::QUIET,
+        );
+        let undo = game.make_move(m);
+        assert_eq!(game.halfmove_clock, 6);
+        assert_eq!(game.fullmove_number, 31);
+        game.unmake_move(m, undo);
+        assert_same_board(&game, &before);
+
+        let m = Move::new(
+            square_to_index("e3"),
+            square_to_index("f1"),
+            MoveFlags::CAPTURE,
+        );
+        let undo = game.make_move(m);
+        assert_eq!(game.halfmove_clock, 0);
+        assert_eq!(game.fullmove_number, 31);
+        game.unmake_move(m, undo);
+        assert_same_board(&game, &before);
+    }
+
+    #[test]
+    fn test_en_passant_square_only_after_double_push() {
+        let mut game = Position::new();
+        game.make_move(Move::new(
+            square_to_index("d2"),
+            square_to_index("d4"),
+            MoveFlags::DOUBLE_PUSH,
+        ));
+        assert_eq!(game.en_passant, Some(position_to_bit("d3").unwrap()));
+
+        game.make_move(Move::new(
+            square_to_index("g8"),
+            square_to_index("f6"),
+            MoveFlags::QUIET,
+        ));
+        assert_eq!(game.en_passant, None);
+    }
+    #[test]
+    fn test_to_fen_start_position() {
+        assert_eq!(
+            Position::new().to_fen(),
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
+        );
+    }
+
+    #[test]
+    fn test_to_fen_round_trips_strings() {
+        for fen in [
+            "8/8/8/8/8/8/8/8 w - - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b Kq - 45 112",
+        ] {
+            assert_eq!(Position::read_FEN(fen).to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn test_to_fen_after_moves() {
+        let mut game = Position::new();
+        game.make_move(Move::new(
+            square_to_index("e2"),
+            square_to_index("e4"),
+            MoveFlags::DOUBLE_PUSH,
+        ));
+        assert_eq!(
+            game.to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+        );
+
+        game.make_move(Move::new(
+            square_to_index("g8"),
+            square_to_index("f6"),
+            MoveFlags::QUIET,
+        ));
+        assert_eq!(
+            game.to_fen(),
+            "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1 2"
+        );
+    }
+
+    #[test]
+    fn test_equality_ignores_piece_list_layout() {
+        let mut played = Position::new();
+        let m = Move::new(
+            square_to_index("g1"),
+            square_to_index("f3"),
+            MoveFlags::QUIET,
+        );
+        played.make_move(m);
+        let read = Position::read_FEN(&played.to_fen());
+
+        assert_eq!(played, read);
+        assert_ne!(played, Position::new());
+    }
+
+    fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_piece_bitboards_start_position() {
+        let game = Position::new();
+        assert_eq!(game.bitboard(Color::White, Pawn), 0xff00);
+        assert_eq!(game.bitboard(Color::Black, Pawn), 0x00ff_0000_0000_0000);
+        assert_eq!(game.bitboard(Color::White, Rook), 0x81);
+        assert_eq!(game.bitboard(Color::Black, King), 1 << 60);
+        assert_eq!(game.bitboard(Color::White, Bishop), 0x24);
+    }
+
+    #[test]
+    fn test_piece_bitboards_follow_captures_and_promotions() {
+        let mut game = Position::read_FEN("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1");
+        let m = Move::with_promotion(48, 57, Queen, MoveFlags::CAPTURE);
+
+        let undo = game.make_move(m);
+        assert_eq!(game.bitboard(Color::White, Pawn), 0);
+        assert_eq!(game.bitboard(Color::White, Queen), 1 << 57);
+        assert_eq!(game.bitboard(Color::Black, Knight), 0);
+
+        game.unmake_move(m, undo);
+        assert_eq!(game.bitboard(Color::White, Pawn), 1 << 48);
+        assert_eq!(game.bitboard(Color::White, Queen), 0);
+        assert_eq!(game.bitboard(Color::Black, Knight), 1 << 57);
+    }
+
+    #[test]
+    fn test_check_consistency_detects_stale_piece_bitboard() {
+        let mut game = Position::new();
+        game.piece_bitboards[Color::White as usize][Knight as usize] = 0;
+        assert!(game.check_consistency().is_err());
+    }
+
+    #[test]
+    fn test_hash_ignores_move_order() {
+        let mut a = Position::new();
+        for (from, to) in [("e2", "e4"), ("e7", "e5"), ("g1", "f3")] {
+            a.move_piece(position_to_bit(from).unwrap(), square_to_index(to));
+        }
+        let mut b = Position::new();
+        for (from, to) in [("g1", "f3"), ("e7", "e5"), ("e2", "e4")] {
+            b.move_piece(position_to_bit(from).unwrap(), square_to_index(to));
+        }
+
+        // b still records e3 as en passant square, but no black pawn can
+        // take there.
+        assert_eq!(b.en_passant, position_to_bit("e3").ok());
+        assert_eq!(a.hash, b.hash);
+        assert_eq!(a.hash, a.compute_hash());
+    }
+
+    #[test]
+    fn test_hash_depends_on_side_castling_and_en_passant() {
+        let hash = |fen: &str| Position::read_FEN(fen).hash;
+        let base = hash("r3k2r/8/8/8/3pP3/8/8/R3K2R b - - 0 1");
+
+        assert_ne!(base, hash("r3k2r/8/8/8/3pP3/8/8/R3K2R w - - 0 1"));
+        assert_ne!(base, hash("r3k2r/8/8/8/3pP3/8/8/R3K2R b K - 0 1"));
+        assert_ne!(base, hash("r3k2r/8/8/8/3pP3/8/8/R3K2R b Kk - 0 1"));
+        assert_ne!(base, hash("r3k2r/8/8/8/3pP3/8/8/R3K2R b - e3 0 1"));
+        assert_eq!(base, hash("r3k2r/8/8/8/3pP3/8/8/R3K2R b - - 12 40"));
+        assert_eq!(
+            hash("r3k2r/8/8/8/4P3/8/8/R3K2R b - e3 0 1"),
+            hash("r3k2r/8/8/8/4P3/8/8/R3K2R b - - 0 1")
+        );
+    }
+
+    #[test]
+    fn test_make_unmake_restores_hash() {
+        let mut game = Position::read_FEN("r3k2r/1P6/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1");
+        let start = game.hash;
+        for m in [
+            Move::new(36, 43, MoveFlags::CAPTURE | MoveFlags::EN_PASSANT),
+            Move::with_promotion(49, 56, Queen, MoveFlags::CAPTURE),
+            Move::new(4, 2, MoveFlags::CASTLE),
+        ] {
+            let undo = game.make_move(m);
+            assert_ne!(game.hash, start);
+            assert_eq!(game.hash, game.compute_hash());
+            game.unmake_move(m, undo);
+            assert_eq!(game.hash, start);
+        }
+    }
+
+    #[test]
+    fn test_check_consistency_detects_stale_hash() {
+        let mut game = Position::new();
+        game.hash ^= 1;
+        assert!(game.check_consistency().is_err());
+    }
+
+    #[test]
+    fn test_fen_round_trip_over_random_games() {
+        use crate::movegeneration::generate_moves;
+        use crate::Game;
+
+        let mut seed = 0x9E37_79B9_7F4A_7C15;
+        for start in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ] {
+            for _ in 0..20 {
+                let mut game = Game::read_FEN(start);
+                for _ in 0..100 {
+                    let fen = game.position.to_fen();
+                    let reread = Position::from_fen(&fen).unwrap();
+                    assert_eq!(reread, game.position, "FEN: {}", fen);
+                    assert_eq!(reread.to_fen(), fen);
+
+                    let moves = generate_moves(&game);
+                    if moves.is_empty() {
+                        break;
+                    }
+                    let m = moves[xorshift(&mut seed) as usize % moves.len()];
+                    game.position.make_move(m);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_uci() {
+        let e4 = Move::new(
+            square_to_index("e2"),
+            square_to_index("e4"),
+            MoveFlags::DOUBLE_PUSH,
+        );
+        let castle = Move::new(
+            square_to_index("e1"),
+            square_to_index("g1"),
+            MoveFlags::CASTLE,
+        );
+        let promotion = Move::with_promotion(
+            square_to_index("e7"),
+            square_to_index("e8"),
+            Queen,
+            MoveFlags::QUIET,
+        );
+
+        assert_eq!(e4.to_uci(), "e2e4");
+        assert_eq!(castle.to_uci(), "e1g1");
+        assert_eq!(promotion.to_uci(), "e7e8q");
+    }
+
+    #[test]
+    fn test_parse_uci_move() {
+        let position = Position::from_fen("r3k3/1P6/8/8/8/8/8/R3K2R w KQq - 0 1").unwrap();
+
+        let castle = position.parse_uci_move("e1g1").unwrap();
+        assert!(castle.is_castle());
+        let promotion = position.parse_uci_move("b7a8n").unwrap();
+        assert_eq!(promotion.promotion(), Some(Knight));
+        assert!(promotion.is_capture());
+        assert_eq!(position.parse_uci_move("a1a8").unwrap().to_uci(), "a1a8");
+    }
+
+    #[test]
+    fn test_parse_uci_move_errors() {
+        let position = Position::new();
+
+        assert_eq!(
+            position.parse_uci_move("e2e"),
+            Err(UciMoveError::InvalidLength("e2e".to_string()))
+        );
+        assert_eq!(
+            position.parse_uci_move("e9e4"),
+            Err(UciMoveError::InvalidSquare("e9".to_string()))
+        );
+        assert_eq!(
+            position.parse_uci_move("e2e4k"),
+            Err(UciMoveError::InvalidPromotion('k'))
+        );
+        assert_eq!(
+            position.parse_uci_move("e2e5"),
+            Err(UciMoveError::IllegalMove("e2e5".to_string()))
+        );
+        // A promotion has to name its piece, and other moves must not.
+        let promotion = Position::from_fen("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert!(promotion.parse_uci_move("b7b8").is_err());
+        assert!(position.parse_uci_move("e2e4q").is_err());
+    }
+
+    #[test]
+    fn test_uci_round_trip() {
+        let game =
+            Game::read_FEN("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        for m in game.legal_moves() {
+            assert_eq!(game.parse_uci_move(&m.to_uci()), Ok(m));
+        }
+    }
 }
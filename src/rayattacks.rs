@@ -2,6 +2,9 @@ use crate::utils::*;
 
 type Bitboard = u64;
 
+/// For each direction, the squares a slider sees from each square on an
+/// empty board.
+#[derive(Clone)]
 pub struct Rays {
     pub n_rays: Vec<Bitboard>,
     pub e_rays: Vec<Bitboard>,
@@ -38,16 +41,77 @@ impl Rays {
         let se_rays = make_rays!(se_ray);
 
         Self {
-            n_rays: n_rays,
-            e_rays: e_rays,
-            nw_rays: nw_rays,
-            ne_rays: ne_rays,
-            w_rays: w_rays,
-            s_rays: s_rays,
-            sw_rays: sw_rays,
-            se_rays: se_rays,
+            n_rays,
+            e_rays,
+            nw_rays,
+            ne_rays,
+            w_rays,
+            s_rays,
+            sw_rays,
+            se_rays,
         }
     }
+
+    /// Rook attacks from `square`, worked out one direction at a time. The
+    /// magic tables in `magicattacks` are filled from this.
+    pub fn rook_attacks(&self, square: usize, occupancy: Bitboard) -> Bitboard {
+        ray_attack(&self.n_rays, true, square, occupancy)
+            | ray_attack(&self.e_rays, true, square, occupancy)
+            | ray_attack(&self.s_rays, false, square, occupancy)
+            | ray_attack(&self.w_rays, false, square, occupancy)
+    }
+
+    /// Each direction paired with its opposite.
+    fn directions(&self) -> [(&[Bitboard], &[Bitboard]); 8] {
+        [
+            (&self.n_rays, &self.s_rays),
+            (&self.s_rays, &self.n_rays),
+            (&self.e_rays, &self.w_rays),
+            (&self.w_rays, &self.e_rays),
+            (&self.ne_rays, &self.sw_rays),
+            (&self.sw_rays, &self.ne_rays),
+            (&self.nw_rays, &self.se_rays),
+            (&self.se_rays, &self.nw_rays),
+        ]
+    }
+
+    /// The squares strictly between `from` and `to`, or nothing if they do not
+    /// share a rank, file or diagonal.
+    pub fn between(&self, from: usize, to: usize) -> Bitboard {
+        for (ray, _) in self.directions() {
+            if ray[from] & (1 << to) != 0 {
+                return ray[from] & !ray[to] & !(1 << to);
+            }
+        }
+
+        0
+    }
+
+    /// The whole rank, file or diagonal through `a` and `b`, or nothing if
+    /// they are not on one.
+    pub fn line(&self, a: usize, b: usize) -> Bitboard {
+        for (ray, opposite) in self.directions() {
+            if ray[a] & (1 << b) != 0 {
+                return ray[a] | opposite[a] | 1 << a;
+            }
+        }
+
+        0
+    }
+
+    /// Bishop attacks from `square`, worked out one direction at a time.
+    pub fn bishop_attacks(&self, square: usize, occupancy: Bitboard) -> Bitboard {
+        ray_attack(&self.ne_rays, true, square, occupancy)
+            | ray_attack(&self.nw_rays, true, square, occupancy)
+            | ray_attack(&self.se_rays, false, square, occupancy)
+            | ray_attack(&self.sw_rays, false, square, occupancy)
+    }
+}
+
+impl Default for Rays {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 macro_rules! define_ray {
@@ -75,53 +139,34 @@ define_ray!(se_ray, |row, col, offset| (row - offset, col + offset));
 fn set_bit(bitboard: Bitboard, row_col: (i64, i64)) -> Bitboard {
     let row = row_col.0;
     let col = row_col.1;
-    if row < 1 || row > 8 || col < 1 || col > 8 {
+    if !(1..=8).contains(&row) || !(1..=8).contains(&col) {
         return bitboard;
     }
     bitboard | (1 << ((col - 1) + (row - 1) * 8))
 }
 
-fn first_hit(ray: Bitboard, forward_ray: bool, occupancy: Bitboard) -> Option<usize> {
-    let intersection = ray & occupancy;
-    if intersection == 0 {
-        return None;
-    } else {
-        if forward_ray {
-            return Some(bit_scan(intersection));
-        } else {
-            return Some(bit_scan_backwards(intersection));
-        }
-    }
-}
-
-pub fn blocked_ray_attack(
-    ray: Bitboard,
-    ray_family: &Vec<Bitboard>,
+/// The squares a slider on `square` sees in one direction: the ray up to and
+/// including the first occupied square. `forward_ray` tells whether the
+/// direction runs towards higher square indices.
+pub fn ray_attack(
+    ray_family: &[Bitboard],
     forward_ray: bool,
-    own_occupancy: Bitboard,
-    enemy_occupancy: Bitboard,
+    square: usize,
+    occupancy: Bitboard,
 ) -> Bitboard {
-    let enemy_overlap = ray & enemy_occupancy;
-    let own_overlap = ray & own_occupancy;
-    let first_own_hit = first_hit(ray, forward_ray, own_overlap);
-    let first_enemy_hit = first_hit(ray, forward_ray, enemy_overlap);
-
-    match (first_own_hit, first_enemy_hit) {
-        (None, None) => ray,
-        (None, Some(idx)) => {
-            let ray_after = ray_family[idx];
-            return ray ^ ray_after;
-        }
-        (Some(idx), None) => {
-            let ray_after = ray_family[idx];
-            return ray ^ (ray_after | 1 << idx);
-        }
-        (Some(own_idx), Some(en_idx)) => {
-            let own_after = ray_family[own_idx];
-            let en_after = ray_family[en_idx];
-            return ray ^ ((own_after | 1 << own_idx) | en_after);
-        }
+    let ray = ray_family[square];
+    let blockers = ray & occupancy;
+    if blockers == 0 {
+        return ray;
     }
+
+    let first_blocker = if forward_ray {
+        bit_scan(blockers)
+    } else {
+        bit_scan_backwards(blockers)
+    };
+
+    ray ^ ray_family[first_blocker]
 }
 
 #[cfg(test)]
@@ -229,6 +274,26 @@ mod tests {
         assert_eq!(rays.ne_rays[idx], expected_ne_6_7);
     }
 
+    #[test]
+    fn test_between() {
+        let rays = Rays::new();
+        // a1-d4, h8-e5, e1-e4 and a knight's distance.
+        assert_eq!(rays.between(0, 27), 1 << 9 | 1 << 18);
+        assert_eq!(rays.between(63, 36), 1 << 54 | 1 << 45);
+        assert_eq!(rays.between(4, 28), 1 << 12 | 1 << 20);
+        assert_eq!(rays.between(4, 21), 0);
+        assert_eq!(rays.between(4, 5), 0);
+    }
+
+    #[test]
+    fn test_line() {
+        let rays = Rays::new();
+        assert_eq!(rays.line(9, 18), 0x8040_2010_0804_0201);
+        assert_eq!(rays.line(18, 9), 0x8040_2010_0804_0201);
+        assert_eq!(rays.line(3, 5), 0xff);
+        assert_eq!(rays.line(0, 17), 0);
+    }
+
     #[test]
     fn test_blocked_ray() {
         let (own_occupancy, enemy_occupancy) = get_occupancy_1();
@@ -237,22 +302,12 @@ mod tests {
         let col = 5;
         let idx = (row - 1) * 8 + col - 1;
 
-        let blocked_attack = blocked_ray_attack(
-            rays.nw_rays[idx],
-            &rays.nw_rays,
-            true,
-            own_occupancy,
-            enemy_occupancy,
-        );
+        let blocked_attack =
+            ray_attack(&rays.nw_rays, true, idx, own_occupancy | enemy_occupancy) & !own_occupancy;
         assert_eq!(blocked_attack, 1 << (idx + 7) | 1 << (idx + 14));
 
-        let blocked_attack = blocked_ray_attack(
-            rays.nw_rays[idx],
-            &rays.nw_rays,
-            true,
-            enemy_occupancy,
-            own_occupancy,
-        );
+        let blocked_attack = ray_attack(&rays.nw_rays, true, idx, enemy_occupancy | own_occupancy)
+            & !enemy_occupancy;
         assert_eq!(blocked_attack, 1 << (idx + 7));
     }
 
@@ -264,22 +319,12 @@ mod tests {
         let col = 5;
         let idx = (row - 1) * 8 + col - 1;
 
-        let blocked_attack = blocked_ray_attack(
-            rays.nw_rays[idx],
-            &rays.nw_rays,
-            true,
-            own_occupancy,
-            enemy_occupancy,
-        );
+        let blocked_attack =
+            ray_attack(&rays.nw_rays, true, idx, own_occupancy | enemy_occupancy) & !own_occupancy;
         assert_eq!(blocked_attack, 0);
 
-        let blocked_attack = blocked_ray_attack(
-            rays.nw_rays[idx],
-            &rays.nw_rays,
-            true,
-            enemy_occupancy,
-            own_occupancy,
-        );
+        let blocked_attack = ray_attack(&rays.nw_rays, true, idx, enemy_occupancy | own_occupancy)
+            & !enemy_occupancy;
         assert_eq!(blocked_attack, 1 << (idx + 7));
     }
 
@@ -292,13 +337,8 @@ mod tests {
         let col = 2;
         let idx = (row - 1) * 8 + col - 1;
 
-        let blocked_attack = blocked_ray_attack(
-            rays.s_rays[idx],
-            &rays.s_rays,
-            false,
-            own_occupancy,
-            enemy_occupancy,
-        );
+        let blocked_attack =
+            ray_attack(&rays.s_rays, false, idx, own_occupancy | enemy_occupancy) & !own_occupancy;
         assert_eq!(
             blocked_attack,
             1 << ((2 - 1) + (4 - 1) * 8)
@@ -306,13 +346,8 @@ mod tests {
                 | 1 << ((2 - 1) + (6 - 1) * 8)
         );
 
-        let blocked_attack = blocked_ray_attack(
-            rays.s_rays[idx],
-            &rays.s_rays,
-            true,
-            enemy_occupancy,
-            own_occupancy,
-        );
+        let blocked_attack = ray_attack(&rays.s_rays, false, idx, enemy_occupancy | own_occupancy)
+            & !enemy_occupancy;
         assert_eq!(
             blocked_attack,
             1 << ((2 - 1) + (5 - 1) * 8) | 1 << ((2 - 1) + (6 - 1) * 8)
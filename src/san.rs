@@ -0,0 +1,432 @@
+use crate::game::Game;
+use crate::position::*;
+use std::fmt;
+
+/// Why a SAN string could not be matched to a legal move. Offsets count
+/// characters from the start of the string.
+#[derive(Debug, PartialEq, Clone)]
+pub enum SanError {
+    Empty,
+    InvalidCharacter { offset: usize, character: char },
+    MissingDestination,
+    IllegalMove(String),
+    AmbiguousMove { san: String, candidates: Vec<Move> },
+}
+
+impl fmt::Display for SanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SanError::Empty => write!(f, "empty move"),
+            SanError::InvalidCharacter { offset, character } => {
+                write!(f, "invalid character '{}' at offset {}", character, offset)
+            }
+            SanError::MissingDestination => write!(f, "move has no destination square"),
+            SanError::IllegalMove(san) => write!(f, "'{}' is not a legal move", san),
+            SanError::AmbiguousMove { san, candidates } => {
+                let candidates = candidates
+                    .iter()
+                    .map(|m| m.to_string())
+                    .collect::<Vec<String>>();
+                write!(
+                    f,
+                    "'{}' is ambiguous, it could be {}",
+                    san,
+                    candidates.join(" or ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+fn piece_letter(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Pawn => 'P',
+        PieceType::Rook => 'R',
+        PieceType::Knight => 'N',
+        PieceType::Bishop => 'B',
+        PieceType::Queen => 'Q',
+        PieceType::King => 'K',
+    }
+}
+
+fn letter_piece(letter: char) -> Option<PieceType> {
+    match letter.to_ascii_uppercase() {
+        'R' => Some(PieceType::Rook),
+        'N' => Some(PieceType::Knight),
+        'B' => Some(PieceType::Bishop),
+        'Q' => Some(PieceType::Queen),
+        'K' => Some(PieceType::King),
+        _ => None,
+    }
+}
+
+fn file_char(square: usize) -> char {
+    (b'a' + (square % 8) as u8) as char
+}
+
+fn rank_char(square: usize) -> char {
+    (b'1' + (square / 8) as u8) as char
+}
+
+/// `m`, which must be legal, in Standard Algebraic Notation, e.g. `Nbd7`,
+/// `O-O-O`, `e8=Q+` or `exd6 e.p.`. The origin is only given where another
+/// legal move of the same kind of piece goes to the same square, and the
+/// check or mate marker is found by playing the move, which is why `game`
+/// is borrowed mutably; it is left as it was.
+pub fn to_san(game: &mut Game, m: Move) -> String {
+    let piece_type = game
+        .position
+        .piece_at(m.from())
+        .expect("No piece on the origin square")
+        .piece_type;
+
+    let mut san = String::new();
+    if m.is_castle() {
+        san.push_str(if m.to() % 8 == 6 { "O-O" } else { "O-O-O" });
+    } else if piece_type == PieceType::Pawn {
+        if m.is_capture() {
+            san.push(file_char(m.from()));
+            san.push('x');
+        }
+        san.push_str(&index_to_position(m.to()));
+        if let Some(promotion) = m.promotion() {
+            san.push('=');
+            san.push(piece_letter(promotion));
+        }
+    } else {
+        san.push(piece_letter(piece_type));
+        san.push_str(&disambiguation(game, m, piece_type));
+        if m.is_capture() {
+            san.push('x');
+        }
+        san.push_str(&index_to_position(m.to()));
+    }
+
+    if m.is_en_passant() {
+        san.push_str(" e.p.");
+    }
+
+    let undo = game.position.make_move(m);
+    if game.is_in_check() {
+        san.push(if game.legal_moves().is_empty() {
+            '#'
+        } else {
+            '+'
+        });
+    }
+    game.position.unmake_move(m, undo);
+
+    san
+}
+
+/// The file, rank or square of the origin of `m` needed to tell it apart
+/// from other moves of the same kind of piece to the same square: the file
+/// if that is enough, else the rank, else both.
+fn disambiguation(game: &Game, m: Move, piece_type: PieceType) -> String {
+    let others = game
+        .legal_moves()
+        .into_iter()
+        .filter(|other| {
+            other.to() == m.to()
+                && other.from() != m.from()
+                && game.position.piece_at(other.from()).unwrap().piece_type == piece_type
+        })
+        .map(|other| other.from())
+        .collect::<Vec<usize>>();
+
+    if others.is_empty() {
+        String::new()
+    } else if others.iter().all(|&from| from % 8 != m.from() % 8) {
+        file_char(m.from()).to_string()
+    } else if others.iter().all(|&from| from / 8 != m.from() / 8) {
+        rank_char(m.from()).to_string()
+    } else {
+        index_to_position(m.from())
+    }
+}
+
+/// The legal move `san` describes. Besides strict SAN this accepts what
+/// people tend to type: check, mate and annotation markers are ignored,
+/// castling may be written with zeros, captures need no `x`, the `=` before
+/// a promotion is optional, the origin may be given in full (`Ng1f3`,
+/// `e2e4`), piece letters other than `b` may be lower case, and a missing
+/// promotion piece means a queen.
+pub fn parse_san(game: &Game, san: &str) -> Result<Move, SanError> {
+    let leading = san.chars().take_while(|c| c.is_whitespace()).count();
+    let text = strip_markers(san.trim());
+    if text.is_empty() {
+        return Err(SanError::Empty);
+    }
+
+    let illegal = || SanError::IllegalMove(san.trim().to_string());
+    let moves = game.legal_moves();
+
+    let castling = text.replace('0', "O").to_ascii_uppercase().replace('-', "");
+    if castling == "OO" || castling == "OOO" {
+        let file = if castling == "OO" { 6 } else { 2 };
+        return moves
+            .into_iter()
+            .find(|m| m.is_castle() && m.to() % 8 == file)
+            .ok_or_else(illegal);
+    }
+
+    let mut chars = text
+        .chars()
+        .enumerate()
+        .map(|(offset, c)| (leading + offset, c))
+        .collect::<Vec<(usize, char)>>();
+
+    let mut promotion = None;
+    if chars.len() > 2 {
+        if let Some(piece_type) = letter_piece(chars[chars.len() - 1].1) {
+            if piece_type != PieceType::King {
+                promotion = Some(piece_type);
+                chars.pop();
+                if chars.last().map(|&(_, c)| c) == Some('=') {
+                    chars.pop();
+                }
+            }
+        }
+    }
+
+    if chars.len() < 2 {
+        return Err(SanError::MissingDestination);
+    }
+    let destination = chars.split_off(chars.len() - 2);
+    let destination = destination.iter().map(|&(_, c)| c).collect::<String>();
+    let to = position_to_index(&destination).map_err(|_| SanError::MissingDestination)?;
+
+    let mut piece_type = PieceType::Pawn;
+    let mut prefix = &chars[..];
+    if let Some(&(_, letter)) = prefix.first() {
+        if letter != 'b' {
+            if let Some(letter_type) = letter_piece(letter) {
+                piece_type = letter_type;
+                prefix = &prefix[1..];
+            }
+        }
+    }
+
+    let mut from_file = None;
+    let mut from_rank = None;
+    for &(offset, character) in prefix {
+        match character {
+            'a'..='h' if from_file.is_none() && from_rank.is_none() => {
+                from_file = Some(character as usize - 'a' as usize)
+            }
+            '1'..='8' if from_rank.is_none() => from_rank = Some(character as usize - '1' as usize),
+            'x' | 'X' | ':' | '-' => (),
+            _ => return Err(SanError::InvalidCharacter { offset, character }),
+        }
+    }
+
+    let matching = |piece_type: PieceType, from_file: Option<usize>| {
+        moves
+            .iter()
+            .copied()
+            .filter(|m| {
+                m.to() == to
+                    && game.position.piece_at(m.from()).unwrap().piece_type == piece_type
+                    && from_file.is_none_or(|file| m.from() % 8 == file)
+                    && from_rank.is_none_or(|rank| m.from() / 8 == rank)
+                    && m.promotion() == promotion.or(m.promotion().and(Some(PieceType::Queen)))
+            })
+            .collect::<Vec<Move>>()
+    };
+
+    let mut candidates = matching(piece_type, from_file);
+    // `Bxc4` for `bxc4` is a common slip, and never ambiguous with a bishop
+    // move since no bishop could make it.
+    if candidates.is_empty() && piece_type == PieceType::Bishop && from_file.is_none() {
+        candidates = matching(PieceType::Pawn, Some(1));
+    }
+
+    match candidates.len() {
+        0 => Err(illegal()),
+        1 => Ok(candidates[0]),
+        _ => Err(SanError::AmbiguousMove {
+            san: san.trim().to_string(),
+            candidates,
+        }),
+    }
+}
+
+/// `san` without the check, mate, annotation and en passant markers that
+/// may follow the move itself.
+fn strip_markers(san: &str) -> &str {
+    let mut text = san;
+    loop {
+        let stripped = text
+            .trim_end_matches(['+', '#', '!', '?'])
+            .trim_end_matches("e.p.")
+            .trim_end();
+        if stripped == text {
+            return text;
+        }
+        text = stripped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san(fen: &str, name: &str) -> String {
+        let mut game = Game::from_fen(fen).unwrap();
+        let m = game.parse_uci_move(name).unwrap();
+        to_san(&mut game, m)
+    }
+
+    fn parse(fen: &str, text: &str) -> Result<String, SanError> {
+        let game = Game::from_fen(fen).unwrap();
+        parse_san(&game, text).map(|m| m.to_string())
+    }
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    // Knights on b8 and f6 can both reach d7, rooks on a1 and a5 both a3,
+    // and the queens on e4, h4 and h1 all e1, with h4 sharing a file with
+    // one and a rank with the other.
+    const AMBIGUOUS: &str = "rn4k1/8/5n2/R7/4Q2Q/8/8/R2K3Q b - - 0 1";
+
+    #[test]
+    fn test_pawn_moves() {
+        assert_eq!(san(START, "e2e4"), "e4");
+        assert_eq!(san("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", "e4d5"), "exd5");
+    }
+
+    #[test]
+    fn test_en_passant() {
+        assert_eq!(
+            san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"),
+            "exd6 e.p."
+        );
+        // The check marker comes last.
+        let check = "8/2k5/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(san(check, "e5d6"), "exd6 e.p.+");
+        assert_eq!(parse(check, "exd6 e.p.+").unwrap(), "e5d6");
+    }
+
+    #[test]
+    fn test_castling() {
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san(fen, "e1g1"), "O-O");
+        assert_eq!(san(fen, "e1c1"), "O-O-O");
+    }
+
+    #[test]
+    fn test_promotion_with_check() {
+        assert_eq!(san("3k4/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8q"), "e8=Q+");
+        assert_eq!(san("3k4/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7e8n"), "e8=N");
+    }
+
+    #[test]
+    fn test_checkmate() {
+        let fen = "r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4";
+        assert_eq!(san(fen, "h5f7"), "Qxf7#");
+    }
+
+    #[test]
+    fn test_disambiguation() {
+        assert_eq!(san(AMBIGUOUS, "b8d7"), "Nbd7");
+        assert_eq!(san(AMBIGUOUS, "f6d7"), "Nfd7");
+
+        let white = AMBIGUOUS.replace(" b ", " w ");
+        assert_eq!(san(&white, "a1a3"), "R1a3");
+        assert_eq!(san(&white, "a5a3"), "R5a3");
+        assert_eq!(san(&white, "h4e1"), "Qh4e1");
+        assert_eq!(san(&white, "e4e1"), "Qee1");
+        assert_eq!(san(&white, "h1e1"), "Q1e1");
+    }
+
+    #[test]
+    fn test_no_disambiguation_for_pinned_piece() {
+        // The knight on e4 is pinned, so Nc3 can only be the other one.
+        let fen = "4r1k1/8/8/8/4N3/8/8/1N2K3 w - - 0 1";
+        assert_eq!(san(fen, "b1c3"), "Nc3");
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        assert_eq!(parse(START, "e4").unwrap(), "e2e4");
+        assert_eq!(parse(START, "Nf3").unwrap(), "g1f3");
+        assert_eq!(parse(AMBIGUOUS, "Nbd7").unwrap(), "b8d7");
+        let white = AMBIGUOUS.replace(" b ", " w ");
+        assert_eq!(parse(&white, "Qh4e1").unwrap(), "h4e1");
+        assert_eq!(parse(&white, "R1a3").unwrap(), "a1a3");
+        assert_eq!(
+            parse("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "O-O-O").unwrap(),
+            "e1c1"
+        );
+        assert_eq!(
+            parse("3k4/4P3/8/8/8/8/8/4K3 w - - 0 1", "e8=N").unwrap(),
+            "e7e8n"
+        );
+    }
+
+    #[test]
+    fn test_parse_tolerant() {
+        let castling = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(parse(castling, "0-0").unwrap(), "e1g1");
+        assert_eq!(parse(castling, "o-o-o+").unwrap(), "e1c1");
+        assert_eq!(parse(START, " Nf3!? ").unwrap(), "g1f3");
+        assert_eq!(parse(START, "nf3").unwrap(), "g1f3");
+        assert_eq!(parse(START, "Ng1f3").unwrap(), "g1f3");
+        assert_eq!(parse(START, "e2e4").unwrap(), "e2e4");
+        assert_eq!(parse(START, "e2-e4").unwrap(), "e2e4");
+
+        let promotion = "3k4/4P3/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(parse(promotion, "e8q").unwrap(), "e7e8q");
+        assert_eq!(parse(promotion, "e8").unwrap(), "e7e8q");
+        assert_eq!(parse(promotion, "e8=Q+").unwrap(), "e7e8q");
+
+        let en_passant = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        assert_eq!(parse(en_passant, "exd6 e.p.").unwrap(), "e5d6");
+        assert_eq!(parse(en_passant, "ed6").unwrap(), "e5d6");
+
+        let pawn_capture = "4k3/8/8/8/2p5/1P6/8/4K3 w - - 0 1";
+        assert_eq!(parse(pawn_capture, "bxc4").unwrap(), "b3c4");
+        assert_eq!(parse(pawn_capture, "Bxc4").unwrap(), "b3c4");
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse(START, "  "), Err(SanError::Empty));
+        assert_eq!(parse(START, "Nf"), Err(SanError::MissingDestination));
+        assert_eq!(
+            parse(START, "Nzf3"),
+            Err(SanError::InvalidCharacter {
+                offset: 1,
+                character: 'z'
+            })
+        );
+        assert_eq!(
+            parse(START, "e5"),
+            Err(SanError::IllegalMove("e5".to_string()))
+        );
+        assert_eq!(
+            parse(START, "O-O"),
+            Err(SanError::IllegalMove("O-O".to_string()))
+        );
+
+        let error = parse(AMBIGUOUS, "Nd7").unwrap_err();
+        assert!(matches!(error, SanError::AmbiguousMove { .. }));
+        assert_eq!(
+            error.to_string(),
+            "'Nd7' is ambiguous, it could be b8d7 or f6d7"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut game =
+            Game::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1")
+                .unwrap();
+
+        for m in game.legal_moves() {
+            let san = to_san(&mut game, m);
+            assert_eq!(parse_san(&game, &san), Ok(m), "{}", san);
+        }
+    }
+}
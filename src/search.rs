@@ -0,0 +1,718 @@
+use crate::game::Game;
+use crate::movegeneration::generate_tactical_moves;
+use crate::position::*;
+use crate::transpositiontable::{Bound, TranspositionTable};
+use crate::utils::extract_bits;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The score of being mated right now. Being mated `n` plies from the root
+/// scores `n` more, so quicker mates score better for the winner.
+pub const MATE: i32 = 30_000;
+
+/// Bigger than any score, as the initial bound of the alpha-beta window.
+const INFINITY: i32 = 32_000;
+
+/// How deep iterative deepening goes when nothing else stops it.
+pub const MAX_DEPTH: usize = 64;
+
+/// How deep `Game::search` looks when its limits do not say.
+pub const DEFAULT_DEPTH: usize = 5;
+
+/// Scores this close to `MATE` are mates rather than material.
+pub(crate) const MATE_BOUND: i32 = MATE - MAX_DEPTH as i32;
+
+/// What a search may spend before it has to answer. Everything left out is
+/// unlimited.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SearchLimits {
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Option<Duration>,
+    pub binc: Option<Duration>,
+    pub movestogo: Option<u32>,
+    /// Search until told to stop.
+    pub infinite: bool,
+}
+
+impl SearchLimits {
+    /// How long `color` should think about its move: `movetime` if given,
+    /// otherwise an even share of the clock over the moves to go (30 if
+    /// unknown) plus most of the increment, never more than half of what is
+    /// left. `None` if there is no time limit.
+    pub fn time_budget(&self, color: Color) -> Option<Duration> {
+        if self.infinite {
+            return None;
+        }
+        if self.movetime.is_some() {
+            return self.movetime;
+        }
+
+        let (time, increment) = match color {
+            Color::White => (self.wtime?, self.winc.unwrap_or_default()),
+            Color::Black => (self.btime?, self.binc.unwrap_or_default()),
+        };
+        let moves_to_go = self.movestogo.unwrap_or(30).max(1);
+
+        Some((time / moves_to_go + increment * 3 / 4).min(time / 2))
+    }
+}
+
+/// What a search found: the move to play, its score in centipawns from the
+/// point of view of the side to move, the line it expects, and how deep and
+/// how many nodes it looked.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub depth: usize,
+    pub nodes: u64,
+}
+
+impl SearchResult {
+    /// The number of moves to mate if the score is a forced mate: positive
+    /// when the side to move mates, negative when it is mated.
+    pub fn mate(&self) -> Option<i32> {
+        if self.score >= MATE_BOUND {
+            Some((MATE - self.score + 1) / 2)
+        } else if self.score <= -MATE_BOUND {
+            Some(-((MATE + self.score) / 2))
+        } else {
+            None
+        }
+    }
+}
+
+/// Looks for the best move in `game` within `limits`, or until `stop` is
+/// set, calling `report` after every completed depth of the iterative
+/// deepening. What it learns is kept in `table`, where the next search of
+/// the same game can find it.
+///
+/// Depth 1 is always searched to the end, so that there is a move to play
+/// however soon the search is stopped. An infinite search that runs out of
+/// depth waits for `stop`, as the protocols expect.
+pub fn search(
+    game: &mut Game,
+    limits: &SearchLimits,
+    table: &mut TranspositionTable,
+    stop: &AtomicBool,
+    report: &mut dyn FnMut(&SearchResult),
+) -> SearchResult {
+    let start = Instant::now();
+    let deadline = limits
+        .time_budget(game.position.active_color)
+        .map(|budget| start + budget);
+    table.new_search();
+    let mut searcher = Searcher {
+        game,
+        table,
+        stop,
+        deadline,
+        max_nodes: limits.nodes,
+        nodes: 0,
+        aborted: false,
+        previous_pv: vec![],
+    };
+
+    let moves = searcher.game.legal_moves();
+    let mut result = SearchResult {
+        best_move: moves.first().copied(),
+        ..Default::default()
+    };
+    if moves.is_empty() {
+        return result;
+    }
+
+    let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+    for depth in 1..=max_depth {
+        let mut pv = vec![];
+        let score = searcher.negamax(depth, -INFINITY, INFINITY, 0, &mut pv);
+        // A depth cut short proves nothing, mates included.
+        if searcher.gave_up() {
+            break;
+        }
+
+        result = SearchResult {
+            best_move: pv.first().copied(),
+            score,
+            pv: pv.clone(),
+            depth,
+            nodes: searcher.nodes,
+        };
+        report(&result);
+        searcher.previous_pv = pv;
+
+        // The first completed depth to see a mate finds the quickest one.
+        let proven_mate = result.mate().is_some();
+        if searcher.should_stop() || (proven_mate && !limits.infinite) {
+            break;
+        }
+    }
+    result.nodes = searcher.nodes;
+
+    while limits.infinite && !stop.load(Ordering::SeqCst) {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    result
+}
+
+/// The state of one search: the game it walks through with make and
+/// unmake, and what decides when it has to give up.
+struct Searcher<'a> {
+    game: &'a mut Game,
+    table: &'a mut TranspositionTable,
+    stop: &'a AtomicBool,
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    nodes: u64,
+    /// Set once the search ran out of time or nodes, or was stopped; the
+    /// scores of the unfinished depth mean nothing then.
+    aborted: bool,
+    /// The principal variation of the last completed depth, tried first.
+    previous_pv: Vec<Move>,
+}
+
+impl Searcher<'_> {
+    /// The score of the position for the side to move, searched `depth`
+    /// plies deep and then through `quiescence`, within the window
+    /// `alpha`..`beta`, with the best line found left in `pv`.
+    fn negamax(
+        &mut self,
+        depth: usize,
+        mut alpha: i32,
+        beta: i32,
+        ply: usize,
+        pv: &mut Vec<Move>,
+    ) -> i32 {
+        pv.clear();
+        if self.visit() {
+            return 0;
+        }
+
+        if ply > 0 && self.is_draw() {
+            return 0;
+        }
+        if depth == 0 {
+            return self.quiescence(alpha, beta, ply);
+        }
+
+        let hash = self.game.position.hash;
+        let entry = self.table.probe(hash, ply);
+        if let Some(entry) = entry.filter(|entry| ply > 0 && entry.depth as usize >= depth) {
+            let usable = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.score >= beta,
+                Bound::Upper => entry.score <= alpha,
+            };
+            if usable {
+                pv.extend(entry.best_move);
+                return entry.score;
+            }
+        }
+
+        let moves = self.game.legal_moves();
+        if moves.is_empty() {
+            return if self.game.is_in_check() {
+                -MATE + ply as i32
+            } else {
+                0
+            };
+        }
+
+        let original_alpha = alpha;
+        let mut child_pv = vec![];
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        for m in self.ordered(moves, ply, entry.and_then(|entry| entry.best_move)) {
+            let undo = self.game.make_move(m);
+            let score = -self.negamax(depth - 1, -beta, -alpha, ply + 1, &mut child_pv);
+            self.game.unmake_move(m, undo);
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(m);
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(m);
+                pv.extend_from_slice(&child_pv);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        if !self.gave_up() {
+            let bound = if best_score <= original_alpha {
+                Bound::Upper
+            } else if best_score >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            self.table
+                .store(hash, best_move, best_score, depth, bound, ply);
+        }
+
+        best_score
+    }
+
+    /// The score of a position at the end of the main search, settled by
+    /// playing out the captures and queen promotions until none is worth
+    /// making, so that a leaf in the middle of an exchange is not scored as
+    /// if the exchange were over. The side to move may stand pat on the
+    /// static evaluation instead, unless it is in check, where every evasion
+    /// is searched and having none is mate.
+    fn quiescence(&mut self, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        if self.visit() {
+            return 0;
+        }
+
+        let in_check = self.game.is_in_check();
+        let moves = generate_tactical_moves(self.game);
+        if in_check && moves.is_empty() {
+            return -MATE + ply as i32;
+        }
+
+        let mut best_score = -INFINITY;
+        if !in_check {
+            best_score = evaluate(&self.game.position);
+            if best_score >= beta {
+                return best_score;
+            }
+            alpha = alpha.max(best_score);
+        }
+
+        for m in self.ordered(moves, ply, None) {
+            let undo = self.game.make_move(m);
+            let score = -self.quiescence(-beta, -alpha, ply + 1);
+            self.game.unmake_move(m, undo);
+
+            best_score = best_score.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best_score
+    }
+
+    /// Whether the position is drawn whatever comes next: a repetition of
+    /// any earlier position, since repeating once more is always possible,
+    /// the fifty-move rule, or too little material to mate.
+    fn is_draw(&self) -> bool {
+        self.game.repetitions() >= 2
+            || self.game.position.halfmove_clock >= 100
+            || self.game.has_insufficient_material()
+    }
+
+    /// `moves` with the best move the transposition table knows first, then
+    /// the move of the previous principal variation at this ply, then
+    /// captures with the most valuable victim and least valuable attacker
+    /// first, then promotions, then the rest.
+    fn ordered(&self, mut moves: Vec<Move>, ply: usize, table_move: Option<Move>) -> Vec<Move> {
+        let pv_move = self.previous_pv.get(ply).copied();
+        let position = &self.game.position;
+        moves.sort_by_cached_key(|&m| {
+            if Some(m) == table_move {
+                return i32::MIN;
+            }
+            if Some(m) == pv_move {
+                return i32::MIN + 1;
+            }
+            let mut key = 0;
+            if m.is_capture() {
+                let victim = position
+                    .piece_at(m.to())
+                    .map_or(PieceType::Pawn, |piece| piece.piece_type);
+                let attacker = position
+                    .piece_at(m.from())
+                    .map_or(PieceType::Pawn, |piece| piece.piece_type);
+                key -= 10 * piece_value(victim) - piece_value(attacker);
+            }
+            if let Some(promotion) = m.promotion() {
+                key -= piece_value(promotion);
+            }
+            key
+        });
+        moves
+    }
+
+    /// Counts a node and returns whether the search has to give up on the
+    /// depth it is in.
+    fn visit(&mut self) -> bool {
+        self.nodes += 1;
+        // Checking the clock is not free, so only do it now and then.
+        if self.nodes.is_multiple_of(1024) && self.should_stop() {
+            self.aborted = true;
+        }
+        self.gave_up()
+    }
+
+    /// Whether the depth being searched is being abandoned, so that nothing
+    /// it found can be trusted. The first depth is always finished.
+    fn gave_up(&self) -> bool {
+        self.aborted && !self.previous_pv.is_empty()
+    }
+
+    fn should_stop(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            || self
+                .max_nodes
+                .is_some_and(|max_nodes| self.nodes >= max_nodes)
+    }
+}
+
+/// The material value of a piece in centipawns. Kings are never traded, so
+/// they are worth nothing here.
+pub fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0,
+    }
+}
+
+/// The static evaluation of `position` in centipawns from the point of view
+/// of the side to move: material plus a bonus for pieces on good squares.
+pub fn evaluate(position: &Position) -> i32 {
+    let mut white = 0;
+    for piece_type in [
+        PieceType::Pawn,
+        PieceType::Knight,
+        PieceType::Bishop,
+        PieceType::Rook,
+        PieceType::Queen,
+        PieceType::King,
+    ] {
+        let table = square_table(piece_type);
+        for square in extract_bits(position.bitboard(Color::White, piece_type)) {
+            // The tables are drawn with White's back rank at the bottom.
+            white += piece_value(piece_type) + table[square ^ 56];
+        }
+        for square in extract_bits(position.bitboard(Color::Black, piece_type)) {
+            white -= piece_value(piece_type) + table[square];
+        }
+    }
+
+    match position.active_color {
+        Color::White => white,
+        Color::Black => -white,
+    }
+}
+
+/// Piece-square bonuses, with a8 first and h1 last as seen from White.
+#[rustfmt::skip]
+fn square_table(piece_type: PieceType) -> &'static [i32; 64] {
+    const PAWN: [i32; 64] = [
+         0,  0,  0,  0,  0,  0,  0,  0,
+        50, 50, 50, 50, 50, 50, 50, 50,
+        10, 10, 20, 30, 30, 20, 10, 10,
+         5,  5, 10, 25, 25, 10,  5,  5,
+         0,  0,  0, 20, 20,  0,  0,  0,
+         5, -5,-10,  0,  0,-10, -5,  5,
+         5, 10, 10,-20,-20, 10, 10,  5,
+         0,  0,  0,  0,  0,  0,  0,  0,
+    ];
+    const KNIGHT: [i32; 64] = [
+        -50,-40,-30,-30,-30,-30,-40,-50,
+        -40,-20,  0,  0,  0,  0,-20,-40,
+        -30,  0, 10, 15, 15, 10,  0,-30,
+        -30,  5, 15, 20, 20, 15,  5,-30,
+        -30,  0, 15, 20, 20, 15,  0,-30,
+        -30,  5, 10, 15, 15, 10,  5,-30,
+        -40,-20,  0,  5,  5,  0,-20,-40,
+        -50,-40,-30,-30,-30,-30,-40,-50,
+    ];
+    const BISHOP: [i32; 64] = [
+        -20,-10,-10,-10,-10,-10,-10,-20,
+        -10,  0,  0,  0,  0,  0,  0,-10,
+        -10,  0,  5, 10, 10,  5,  0,-10,
+        -10,  5,  5, 10, 10,  5,  5,-10,
+        -10,  0, 10, 10, 10, 10,  0,-10,
+        -10, 10, 10, 10, 10, 10, 10,-10,
+        -10,  5,  0,  0,  0,  0,  5,-10,
+        -20,-10,-10,-10,-10,-10,-10,-20,
+    ];
+    const ROOK: [i32; 64] = [
+         0,  0,  0,  0,  0,  0,  0,  0,
+         5, 10, 10, 10, 10, 10, 10,  5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+        -5,  0,  0,  0,  0,  0,  0, -5,
+         0,  0,  0,  5,  5,  0,  0,  0,
+    ];
+    const QUEEN: [i32; 64] = [
+        -20,-10,-10, -5, -5,-10,-10,-20,
+        -10,  0,  0,  0,  0,  0,  0,-10,
+        -10,  0,  5,  5,  5,  5,  0,-10,
+         -5,  0,  5,  5,  5,  5,  0, -5,
+          0,  0,  5,  5,  5,  5,  0, -5,
+        -10,  5,  5,  5,  5,  5,  0,-10,
+        -10,  0,  5,  0,  0,  0,  0,-10,
+        -20,-10,-10, -5, -5,-10,-10,-20,
+    ];
+    const KING: [i32; 64] = [
+        -30,-40,-40,-50,-50,-40,-40,-30,
+        -30,-40,-40,-50,-50,-40,-40,-30,
+        -30,-40,-40,-50,-50,-40,-40,-30,
+        -30,-40,-40,-50,-50,-40,-40,-30,
+        -20,-30,-30,-40,-40,-30,-30,-20,
+        -10,-20,-20,-20,-20,-20,-20,-10,
+         20, 20,  0,  0,  0,  0, 20, 20,
+         20, 30, 10,  0,  0, 10, 30, 20,
+    ];
+
+    match piece_type {
+        PieceType::Pawn => &PAWN,
+        PieceType::Knight => &KNIGHT,
+        PieceType::Bishop => &BISHOP,
+        PieceType::Rook => &ROOK,
+        PieceType::Queen => &QUEEN,
+        PieceType::King => &KING,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_budget() {
+        let limits = SearchLimits {
+            wtime: Some(Duration::from_secs(60)),
+            btime: Some(Duration::from_secs(1)),
+            winc: Some(Duration::from_secs(1)),
+            movestogo: Some(20),
+            ..Default::default()
+        };
+        assert_eq!(
+            limits.time_budget(Color::White),
+            Some(Duration::from_millis(3750))
+        );
+        assert_eq!(
+            limits.time_budget(Color::Black),
+            Some(Duration::from_millis(50))
+        );
+
+        let movetime = SearchLimits {
+            movetime: Some(Duration::from_millis(500)),
+            ..Default::default()
+        };
+        assert_eq!(
+            movetime.time_budget(Color::Black),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(SearchLimits::default().time_budget(Color::White), None);
+    }
+
+    fn depth(depth: usize) -> SearchLimits {
+        SearchLimits {
+            depth: Some(depth),
+            ..Default::default()
+        }
+    }
+
+    fn best_move(fen: &str, limits: &SearchLimits) -> (String, SearchResult) {
+        let mut game = Game::from_fen(fen).unwrap();
+        let result = game.search(limits, &mut TranspositionTable::new(1));
+        (result.best_move.unwrap().to_uci(), result)
+    }
+
+    #[test]
+    fn test_mate_in_one() {
+        let (m, result) = best_move("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", &depth(3));
+        assert_eq!(m, "a1a8");
+        assert_eq!(result.score, MATE - 1);
+        assert_eq!(result.mate(), Some(1));
+        // The first depth to see the mate is enough.
+        assert_eq!(result.depth, 1);
+    }
+
+    #[test]
+    fn test_mate_in_two() {
+        // 1. Ra7 Kg8 2. Rb8#; checking at once lets the king out.
+        let (m, result) = best_move("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", &depth(5));
+        assert_eq!(m, "a2a7");
+        assert_eq!(result.mate(), Some(2));
+        assert_eq!(result.depth, 3);
+        assert_eq!(result.pv.len(), 3);
+    }
+
+    #[test]
+    fn test_mated_side_scores_negative() {
+        let (m, result) = best_move("7k/R7/8/8/8/8/8/1R4K1 b - - 0 1", &depth(5));
+        assert_eq!(m, "h8g8");
+        assert_eq!(result.mate(), Some(-1));
+        assert_eq!(result.score, -MATE + 2);
+    }
+
+    #[test]
+    fn test_takes_hanging_queen() {
+        let (m, result) = best_move("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1", &depth(2));
+        assert_eq!(m, "d2d5");
+        assert!(result.score > 400);
+    }
+
+    #[test]
+    fn test_sees_recapture_beyond_the_horizon() {
+        // At depth 1 the pawn on d5 looks free, but e6 takes the queen back.
+        let (m, result) = best_move("4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1", &depth(1));
+        assert_ne!(m, "d1d5");
+        assert!(result.score > 0);
+    }
+
+    #[test]
+    fn test_quiescence_finds_mate_in_check() {
+        let mut game = Game::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        let stop = AtomicBool::new(false);
+        let mut searcher = Searcher {
+            game: &mut game,
+            table: &mut TranspositionTable::new(1),
+            stop: &stop,
+            deadline: None,
+            max_nodes: None,
+            nodes: 0,
+            aborted: false,
+            previous_pv: vec![],
+        };
+        assert_eq!(searcher.quiescence(-INFINITY, INFINITY, 3), -MATE + 3);
+    }
+
+    #[test]
+    fn test_no_legal_moves() {
+        let mut game = Game::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(
+            game.search(&depth(3), &mut TranspositionTable::new(1)),
+            SearchResult::default()
+        );
+    }
+
+    #[test]
+    fn test_pv_is_legal() {
+        let mut game = Game::new();
+        let result = game.search(&depth(3), &mut TranspositionTable::new(1));
+        assert_eq!(result.depth, 3);
+        assert_eq!(result.pv.first().copied(), result.best_move);
+        for &m in &result.pv {
+            assert!(game.legal_moves().contains(&m), "{}", m);
+            game.make_move(m);
+        }
+    }
+
+    #[test]
+    fn test_node_limit_keeps_first_depth() {
+        let limits = SearchLimits {
+            nodes: Some(1),
+            ..Default::default()
+        };
+        let (_, result) = best_move(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+            &limits,
+        );
+        assert_eq!(result.depth, 1);
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn test_time_stop_does_not_count_as_mate() {
+        // Stopped at once, only depth 1 is finished, and it cannot see the
+        // mate in two.
+        let mut game = Game::from_fen("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1").unwrap();
+        let limits = SearchLimits {
+            movetime: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let mut table = TranspositionTable::new(1);
+        let stop = AtomicBool::new(false);
+        let result = search(&mut game, &limits, &mut table, &stop, &mut |_| ());
+        assert_eq!(result.depth, 1);
+        assert_eq!(result.mate(), None);
+    }
+
+    #[test]
+    fn test_unbounded_game_search_stops_at_default_depth() {
+        let mut table = TranspositionTable::new(1);
+        for limits in [
+            SearchLimits::default(),
+            SearchLimits {
+                infinite: true,
+                ..Default::default()
+            },
+        ] {
+            let result = Game::new().search(&limits, &mut table);
+            assert_eq!(result.depth, DEFAULT_DEPTH);
+            assert!(result.best_move.is_some());
+        }
+    }
+
+    #[test]
+    fn test_stop_ends_search() {
+        let mut game = Game::new();
+        let stop = AtomicBool::new(true);
+        let mut table = TranspositionTable::new(1);
+        let result = search(
+            &mut game,
+            &SearchLimits::default(),
+            &mut table,
+            &stop,
+            &mut |_| (),
+        );
+        assert_eq!(result.depth, 1);
+    }
+
+    #[test]
+    fn test_reports_every_depth() {
+        let mut game = Game::new();
+        let mut depths = vec![];
+        search(
+            &mut game,
+            &depth(3),
+            &mut TranspositionTable::new(1),
+            &AtomicBool::new(false),
+            &mut |result| depths.push(result.depth),
+        );
+        assert_eq!(depths, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_table_carries_over_between_searches() {
+        let mut game =
+            Game::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3")
+                .unwrap();
+        let mut table = TranspositionTable::new(1);
+        let stop = AtomicBool::new(false);
+        let first = search(&mut game, &depth(4), &mut table, &stop, &mut |_| ());
+        let second = search(&mut game, &depth(4), &mut table, &stop, &mut |_| ());
+
+        assert_eq!(second.best_move, first.best_move);
+        assert_eq!(second.score, first.score);
+        assert!(second.nodes < first.nodes / 2);
+    }
+
+    #[test]
+    fn test_evaluation_is_symmetric() {
+        assert_eq!(evaluate(&Position::new()), 0);
+
+        let white = Position::from_fen("4k3/8/8/8/3N4/8/4P3/4K3 w - - 0 1").unwrap();
+        let black = Position::from_fen("4k3/4p3/8/3n4/8/8/8/4K3 b - - 0 1").unwrap();
+        assert!(evaluate(&white) > 300);
+        assert_eq!(evaluate(&white), evaluate(&black));
+    }
+}
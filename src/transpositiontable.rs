@@ -0,0 +1,259 @@
+use crate::position::Move;
+use crate::search::MATE_BOUND;
+use std::mem;
+
+/// How big the table is unless told otherwise, in megabytes.
+pub const DEFAULT_SIZE_MB: usize = 16;
+
+/// The biggest table the `Hash` option allows, in megabytes.
+pub const MAX_SIZE_MB: usize = 4096;
+
+/// What a stored score says about the true score of its position.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Bound {
+    /// The score is exact.
+    Exact,
+    /// The search failed high: the true score is at least this.
+    Lower,
+    /// The search failed low: the true score is at most this.
+    Upper,
+}
+
+/// What a search learned about one position.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Entry {
+    pub key: u64,
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+    generation: u8,
+}
+
+/// Two entries per slot: one kept for the deepest search of the current
+/// generation, and one that takes whatever comes last.
+#[derive(Debug, Default, Clone, Copy)]
+struct Bucket {
+    depth_preferred: Option<Entry>,
+    always_replace: Option<Entry>,
+}
+
+/// A fixed-size table of search results keyed by Zobrist hash, so that a
+/// position reached again by another move order need not be searched again,
+/// and its best move is tried first when it must.
+///
+/// Mate scores count plies from the root, which differs from one path to a
+/// position to the next, so they are stored counting from the position
+/// itself and converted back when probed.
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    /// Bumped by every search, so that entries left over from earlier
+    /// searches give way to new ones however deep they were.
+    generation: u8,
+}
+
+impl TranspositionTable {
+    /// A table taking up about `megabytes` of memory, at least one bucket.
+    pub fn new(megabytes: usize) -> Self {
+        let buckets = (megabytes * 1024 * 1024 / mem::size_of::<Bucket>()).max(1);
+        Self {
+            buckets: vec![Bucket::default(); buckets],
+            generation: 0,
+        }
+    }
+
+    /// How big the table is, to the nearest megabyte.
+    pub fn megabytes(&self) -> usize {
+        let megabyte = 1024 * 1024;
+        (self.buckets.len() * mem::size_of::<Bucket>() + megabyte / 2) / megabyte
+    }
+
+    /// Throws everything away and makes the table about `megabytes` big.
+    pub fn resize(&mut self, megabytes: usize) {
+        *self = Self::new(megabytes);
+    }
+
+    /// Forgets every entry, as between games.
+    pub fn clear(&mut self) {
+        self.buckets.fill(Bucket::default());
+        self.generation = 0;
+    }
+
+    /// Marks the start of a new search; whatever earlier searches stored is
+    /// now the first to be replaced.
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// The entry for the position with hash `key`, with a mate score counted
+    /// from the root again for a position `ply` plies into the search.
+    pub fn probe(&self, key: u64, ply: usize) -> Option<Entry> {
+        let bucket = &self.buckets[self.index(key)];
+        [bucket.depth_preferred, bucket.always_replace]
+            .into_iter()
+            .flatten()
+            .find(|entry| entry.key == key)
+            .map(|entry| Entry {
+                score: score_from_table(entry.score, ply),
+                ..entry
+            })
+    }
+
+    /// Stores what the search found for the position with hash `key`, `ply`
+    /// plies from the root. The depth-preferred slot takes the entry if it
+    /// is empty, holds the same position, is left from an earlier search or
+    /// was searched no deeper; otherwise the always-replace slot does.
+    pub fn store(
+        &mut self,
+        key: u64,
+        best_move: Option<Move>,
+        score: i32,
+        depth: usize,
+        bound: Bound,
+        ply: usize,
+    ) {
+        let generation = self.generation;
+        let index = self.index(key);
+        let bucket = &mut self.buckets[index];
+
+        let mut entry = Entry {
+            key,
+            best_move,
+            score: score_to_table(score, ply),
+            depth: depth.min(u8::MAX as usize) as u8,
+            bound,
+            generation,
+        };
+        // A search that found no move should not forget the one it had.
+        if entry.best_move.is_none() {
+            entry.best_move = [bucket.depth_preferred, bucket.always_replace]
+                .into_iter()
+                .flatten()
+                .find(|old| old.key == key)
+                .and_then(|old| old.best_move);
+        }
+
+        let replace_deepest = bucket.depth_preferred.is_none_or(|old| {
+            old.key == key || old.generation != generation || old.depth <= entry.depth
+        });
+        if replace_deepest {
+            bucket.depth_preferred = Some(entry);
+            if bucket.always_replace.is_some_and(|old| old.key == key) {
+                bucket.always_replace = None;
+            }
+        } else {
+            bucket.always_replace = Some(entry);
+        }
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.buckets.len() as u64) as usize
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIZE_MB)
+    }
+}
+
+/// A mate score counted from the root turned into one counted from the
+/// position `ply` plies into the search.
+fn score_to_table(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_table(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::MoveFlags;
+    use crate::search::MATE;
+
+    fn e2e4() -> Option<Move> {
+        Some(Move::new(12, 28, MoveFlags::DOUBLE_PUSH))
+    }
+
+    #[test]
+    fn test_store_and_probe() {
+        let mut table = TranspositionTable::new(1);
+        table.store(42, e2e4(), 35, 4, Bound::Lower, 0);
+
+        let entry = table.probe(42, 0).unwrap();
+        assert_eq!(entry.best_move, e2e4());
+        assert_eq!(entry.score, 35);
+        assert_eq!(entry.depth, 4);
+        assert_eq!(entry.bound, Bound::Lower);
+        assert_eq!(table.probe(43, 0), None);
+    }
+
+    #[test]
+    fn test_mate_scores_are_relative_to_the_position() {
+        let mut table = TranspositionTable::new(1);
+        // Mate 5 plies from the root, found 3 plies in: mate in 2 plies.
+        table.store(7, None, MATE - 5, 2, Bound::Exact, 3);
+        assert_eq!(table.probe(7, 3).unwrap().score, MATE - 5);
+        assert_eq!(table.probe(7, 1).unwrap().score, MATE - 3);
+
+        table.store(8, None, -MATE + 4, 2, Bound::Exact, 2);
+        assert_eq!(table.probe(8, 6).unwrap().score, -MATE + 8);
+    }
+
+    #[test]
+    fn test_deeper_entry_survives_shallower_one() {
+        let mut table = TranspositionTable::new(0);
+        assert_eq!(table.buckets.len(), 1);
+
+        table.store(1, e2e4(), 10, 8, Bound::Exact, 0);
+        table.store(2, None, 20, 3, Bound::Exact, 0);
+        table.store(3, None, 30, 2, Bound::Exact, 0);
+        assert_eq!(table.probe(1, 0).unwrap().depth, 8);
+        assert_eq!(table.probe(2, 0), None);
+        assert_eq!(table.probe(3, 0).unwrap().score, 30);
+
+        // Once a new search starts, the old deep entry gives way.
+        table.new_search();
+        table.store(4, None, 40, 1, Bound::Exact, 0);
+        assert_eq!(table.probe(1, 0), None);
+        assert_eq!(table.probe(4, 0).unwrap().score, 40);
+    }
+
+    #[test]
+    fn test_keeps_best_move_of_same_position() {
+        let mut table = TranspositionTable::new(1);
+        table.store(5, e2e4(), 10, 3, Bound::Exact, 0);
+        table.store(5, None, -10, 4, Bound::Upper, 0);
+
+        let entry = table.probe(5, 0).unwrap();
+        assert_eq!(entry.best_move, e2e4());
+        assert_eq!(entry.depth, 4);
+    }
+
+    #[test]
+    fn test_clear_and_resize() {
+        let mut table = TranspositionTable::new(0);
+        table.store(9, e2e4(), 0, 1, Bound::Exact, 0);
+        table.new_search();
+        table.clear();
+        assert_eq!(table.probe(9, 0), None);
+        assert_eq!(table.generation, 0);
+
+        table.resize(1);
+        assert_eq!(table.buckets.len(), 1024 * 1024 / mem::size_of::<Bucket>());
+    }
+}
@@ -0,0 +1,416 @@
+use crate::game::Game;
+use crate::search::{search, SearchLimits, SearchResult};
+use crate::transpositiontable::{TranspositionTable, DEFAULT_SIZE_MB, MAX_SIZE_MB};
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The limits given by the arguments of a `go` command. Arguments that are
+/// not understood, such as `ponder` or `searchmoves`, are skipped, and
+/// negative times count as zero.
+pub fn parse_go(args: &[&str]) -> SearchLimits {
+    let mut limits = SearchLimits::default();
+    let mut args = args.iter();
+
+    while let Some(&arg) = args.next() {
+        let mut value = || args.next().and_then(|value| value.parse::<i64>().ok());
+        // A GUI sends a negative clock once the engine has overstepped it;
+        // that calls for the shortest think, not an unlimited one.
+        let millis = |value: Option<i64>| value.map(|ms| Duration::from_millis(ms.max(0) as u64));
+
+        match arg {
+            "depth" => limits.depth = value().map(|depth| depth.max(0) as usize),
+            "nodes" => limits.nodes = value().map(|nodes| nodes.max(0) as u64),
+            "movetime" => limits.movetime = millis(value()),
+            "wtime" => limits.wtime = millis(value()),
+            "btime" => limits.btime = millis(value()),
+            "winc" => limits.winc = millis(value()),
+            "binc" => limits.binc = millis(value()),
+            "movestogo" => limits.movestogo = value().map(|moves| moves.max(0) as u32),
+            "infinite" => limits.infinite = true,
+            _ => (),
+        }
+    }
+
+    limits
+}
+
+/// A UCI session: reads commands, keeps the position the GUI set up in a
+/// `Game`, with the moves leading to it in its history, and runs searches
+/// on a separate thread so that `stop` is heard while they run. Replies go
+/// to `out`. The transposition table lasts from one search to the next
+/// until `ucinewgame`.
+pub struct Uci<W> {
+    game: Game,
+    out: Arc<Mutex<W>>,
+    table: Arc<Mutex<TranspositionTable>>,
+    stop: Arc<AtomicBool>,
+    search: Option<JoinHandle<()>>,
+}
+
+impl<W: Write + Send + 'static> Uci<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            game: Game::new(),
+            out: Arc::new(Mutex::new(out)),
+            table: Arc::new(Mutex::new(TranspositionTable::default())),
+            stop: Arc::new(AtomicBool::new(false)),
+            search: None,
+        }
+    }
+
+    /// The game as set up by the last `position` command.
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    /// Handles commands from `input` until `quit` or the end of the input.
+    pub fn run<R: BufRead>(&mut self, input: R) -> io::Result<()> {
+        for line in input.lines() {
+            if !self.handle(&line?) {
+                break;
+            }
+        }
+        self.stop_search();
+        Ok(())
+    }
+
+    /// Handles a single command line. Returns `false` once the session
+    /// should end.
+    pub fn handle(&mut self, line: &str) -> bool {
+        let tokens = line.split_whitespace().collect::<Vec<&str>>();
+        let Some((&command, args)) = tokens.split_first() else {
+            return true;
+        };
+
+        match command {
+            "uci" => {
+                self.send(&format!(
+                    "id name chess_engine {}",
+                    env!("CARGO_PKG_VERSION")
+                ));
+                self.send(&format!("id author {}", env!("CARGO_PKG_AUTHORS")));
+                self.send(&format!(
+                    "option name Hash type spin default {} min 1 max {}",
+                    DEFAULT_SIZE_MB, MAX_SIZE_MB
+                ));
+                self.send("uciok");
+            }
+            "isready" => self.send("readyok"),
+            "ucinewgame" => {
+                self.stop_search();
+                self.game = Game::new();
+                self.table.lock().unwrap().clear();
+            }
+            "position" => {
+                self.stop_search();
+                if let Err(message) = self.set_position(args) {
+                    self.send(&format!("info string {}", message));
+                }
+            }
+            "setoption" => self.set_option(args),
+            "go" => {
+                self.stop_search();
+                self.go(parse_go(args));
+            }
+            "stop" => self.stop_search(),
+            "quit" => {
+                self.stop_search();
+                return false;
+            }
+            // `debug`, `register` and `ponderhit` need nothing from us.
+            "debug" | "register" | "ponderhit" => (),
+            _ => self.send(&format!("info string unknown command {}", command)),
+        }
+
+        true
+    }
+
+    fn send(&self, line: &str) {
+        send(&self.out, line);
+    }
+
+    /// `position startpos|fen <fen> [moves <move>...]`. The game is only
+    /// replaced if the whole command makes sense.
+    fn set_position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_at = args.iter().position(|&arg| arg == "moves");
+        let (setup, moves) = match moves_at {
+            Some(index) => (&args[..index], &args[index + 1..]),
+            None => (args, &[][..]),
+        };
+
+        let mut game = match setup.split_first() {
+            Some((&"startpos", [])) => Game::new(),
+            Some((&"fen", fen)) => Game::from_fen(&fen.join(" ")).map_err(|e| e.to_string())?,
+            _ => return Err("expected position startpos or position fen".to_string()),
+        };
+
+        for uci in moves {
+            let m = game.parse_uci_move(uci).map_err(|e| e.to_string())?;
+            game.make_move(m);
+        }
+
+        self.game = game;
+        Ok(())
+    }
+
+    /// `setoption name <name> [value <value>]`. Option names are not case
+    /// sensitive.
+    fn set_option(&mut self, args: &[&str]) {
+        let value_at = args.iter().position(|&arg| arg == "value");
+        let name = args[..value_at.unwrap_or(args.len())]
+            .iter()
+            .skip_while(|&&arg| arg == "name")
+            .copied()
+            .collect::<Vec<&str>>()
+            .join(" ");
+        let value = value_at.map_or(String::new(), |index| args[index + 1..].join(" "));
+
+        match name.to_lowercase().as_str() {
+            "hash" => match value.parse::<usize>() {
+                Ok(megabytes) if (1..=MAX_SIZE_MB).contains(&megabytes) => {
+                    self.stop_search();
+                    self.table.lock().unwrap().resize(megabytes);
+                }
+                _ => self.send(&format!("info string invalid Hash value {}", value)),
+            },
+            _ => self.send(&format!("info string unknown option {}", name)),
+        }
+    }
+
+    fn go(&mut self, limits: SearchLimits) {
+        let mut game = self.game.clone();
+        let out = Arc::clone(&self.out);
+        let table = Arc::clone(&self.table);
+        let stop = Arc::clone(&self.stop);
+        stop.store(false, Ordering::SeqCst);
+
+        self.search = Some(thread::spawn(move || {
+            let start = Instant::now();
+            let mut table = table.lock().unwrap();
+            let mut report = |result: &SearchResult| send(&out, &info(result, start.elapsed()));
+            let result = search(&mut game, &limits, &mut table, &stop, &mut report);
+
+            let best_move = result.best_move.map_or("0000".to_string(), |m| m.to_uci());
+            send(&out, &format!("bestmove {}", best_move));
+        }));
+    }
+
+    /// Stops the running search, if any, and waits for its `bestmove`.
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(search) = self.search.take() {
+            search.join().expect("The search thread panicked");
+        }
+    }
+}
+
+/// Writes `line` to `out` and flushes it, so the GUI sees it at once.
+pub(crate) fn send<W: Write>(out: &Mutex<W>, line: &str) {
+    let mut out = out.lock().unwrap();
+    // There is no one to tell if the GUI has gone away.
+    let _ = writeln!(out, "{}", line).and_then(|_| out.flush());
+}
+
+/// The `info` line for an intermediate search result.
+fn info(result: &SearchResult, elapsed: Duration) -> String {
+    let score = match result.mate() {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", result.score),
+    };
+    let mut info = format!(
+        "info depth {} score {} nodes {} time {}",
+        result.depth,
+        score,
+        result.nodes,
+        elapsed.as_millis()
+    );
+    if !result.pv.is_empty() {
+        info.push_str(" pv");
+        for m in &result.pv {
+            info.push(' ');
+            info.push_str(&m.to_uci());
+        }
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::{Color, Position};
+
+    fn session() -> Uci<Vec<u8>> {
+        Uci::new(vec![])
+    }
+
+    fn output(uci: &Uci<Vec<u8>>) -> String {
+        String::from_utf8(uci.out.lock().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut uci = session();
+        assert!(uci.handle("uci"));
+        assert!(uci.handle("isready"));
+
+        let output = output(&uci);
+        assert!(output.starts_with("id name chess_engine"));
+        assert!(output.ends_with("uciok\nreadyok\n"));
+    }
+
+    #[test]
+    fn test_position_startpos_with_moves() {
+        let mut uci = session();
+        uci.handle("position startpos moves e2e4 e7e5 g1f3");
+
+        assert_eq!(
+            uci.game().position.to_fen(),
+            "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+        assert_eq!(uci.game().history.len(), 3);
+    }
+
+    #[test]
+    fn test_position_fen() {
+        let mut uci = session();
+        uci.handle("position fen 4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 moves e2e4");
+        assert_eq!(
+            uci.game().position.to_fen(),
+            "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1"
+        );
+    }
+
+    #[test]
+    fn test_invalid_position_keeps_game() {
+        let mut uci = session();
+        uci.handle("position startpos moves e2e4");
+        uci.handle("position startpos moves e2e5");
+        uci.handle("position fen 8/8/8 w - - 0 1");
+
+        assert_eq!(uci.game().history.len(), 1);
+        let output = output(&uci);
+        assert!(output.contains("info string 'e2e5' is not a legal move"));
+        assert_eq!(output.lines().count(), 2);
+    }
+
+    #[test]
+    fn test_go_and_quit() {
+        let mut uci = session();
+        uci.handle("position startpos");
+        uci.handle("go depth 1");
+        assert!(!uci.handle("quit"));
+
+        let output = output(&uci);
+        assert!(output.starts_with("info depth 1 "));
+        let best_move = output.lines().last().unwrap();
+        let best_move = best_move.strip_prefix("bestmove ").unwrap();
+        assert!(uci.game().parse_uci_move(best_move).is_ok());
+    }
+
+    #[test]
+    fn test_go_infinite_waits_for_stop() {
+        let mut uci = session();
+        uci.handle("go infinite");
+        thread::sleep(Duration::from_millis(20));
+        assert!(!output(&uci).contains("bestmove"));
+
+        uci.handle("stop");
+        assert!(output(&uci)
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with("bestmove "));
+    }
+
+    #[test]
+    fn test_no_legal_moves() {
+        let mut uci = session();
+        uci.handle("position fen 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+        uci.handle("go movetime 10");
+        uci.handle("stop");
+        assert_eq!(output(&uci), "bestmove 0000\n");
+    }
+
+    #[test]
+    fn test_ucinewgame_resets_position() {
+        let mut uci = session();
+        uci.handle("position startpos moves e2e4");
+        uci.handle("ucinewgame");
+        assert!(uci.game().history.is_empty());
+        assert_eq!(uci.game().position.to_fen(), Position::new().to_fen());
+    }
+
+    #[test]
+    fn test_hash_option() {
+        let mut uci = session();
+        uci.handle("uci");
+        assert!(output(&uci).contains("\noption name Hash type spin default 16 "));
+
+        uci.handle("setoption name hash value 2");
+        assert_eq!(uci.table.lock().unwrap().megabytes(), 2);
+
+        uci.handle("setoption name Hash value 0");
+        uci.handle("setoption name Hash value lots");
+        assert_eq!(uci.table.lock().unwrap().megabytes(), 2);
+        assert!(output(&uci)
+            .ends_with("info string invalid Hash value 0\ninfo string invalid Hash value lots\n"));
+    }
+
+    #[test]
+    fn test_ucinewgame_clears_hash() {
+        let mut uci = session();
+        uci.handle("go depth 2");
+        uci.handle("stop");
+        let hash = Position::new().hash;
+        assert!(uci.table.lock().unwrap().probe(hash, 0).is_some());
+
+        uci.handle("ucinewgame");
+        assert!(uci.table.lock().unwrap().probe(hash, 0).is_none());
+    }
+
+    #[test]
+    fn test_unknown_input() {
+        let mut uci = session();
+        uci.handle("");
+        uci.handle("xyzzy");
+        uci.handle("setoption name Foo Bar value 3");
+        assert_eq!(
+            output(&uci),
+            "info string unknown command xyzzy\ninfo string unknown option Foo Bar\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_negative_clock() {
+        let limits = parse_go(&["wtime", "-100", "btime", "1000", "winc", "-5"]);
+        assert_eq!(limits.wtime, Some(Duration::ZERO));
+        assert_eq!(limits.winc, Some(Duration::ZERO));
+        assert_eq!(limits.btime, Some(Duration::from_secs(1)));
+        assert_eq!(limits.time_budget(Color::White), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let limits = parse_go(&[
+            "wtime",
+            "60000",
+            "btime",
+            "30000",
+            "winc",
+            "1000",
+            "movestogo",
+            "20",
+            "ponder",
+        ]);
+
+        assert_eq!(limits.wtime, Some(Duration::from_secs(60)));
+        assert_eq!(limits.btime, Some(Duration::from_secs(30)));
+        assert_eq!(limits.winc, Some(Duration::from_secs(1)));
+        assert_eq!(limits.movestogo, Some(20));
+        assert_eq!(limits.depth, None);
+        assert!(!limits.infinite);
+    }
+}
@@ -8,11 +8,12 @@ pub fn bit_scan(bit: u64) -> usize {
     assert!(bit != 0);
     let one_bit = (bit ^ (bit - 1)) ^ (!bit & (bit - 1));
     let remainder = (one_bit % 67) as usize;
-    return MOD67TABLE[remainder];
+    MOD67TABLE[remainder]
 }
 
 pub fn bit_scan_backwards(bit: u64) -> usize {
-    (bit as f64).log2().floor() as usize
+    assert!(bit != 0);
+    63 - bit.leading_zeros() as usize
 }
 
 pub fn extract_bits(mut bits: u64) -> Vec<usize> {
@@ -27,7 +28,7 @@ pub fn extract_bits(mut bits: u64) -> Vec<usize> {
 }
 
 pub fn index(row: i32, col: i32) -> Option<usize> {
-    if row < 1 || row > 8 || col < 1 || col > 8 {
+    if !(1..=8).contains(&row) || !(1..=8).contains(&col) {
         return None;
     }
 
@@ -42,13 +43,13 @@ pub fn rowcol(index: usize) -> (i32, i32) {
 }
 
 pub fn split_on(s: &str, sep: char) -> (&str, &str) {
-    for (i, item) in s.chars().enumerate() {
+    for (i, item) in s.char_indices() {
         if item == sep {
-            return (&s[0..i], &s[i + 1..]);
+            return (&s[0..i], &s[i + sep.len_utf8()..]);
         }
     }
 
-    (&s[..], "")
+    (s, "")
 }
 
 pub type Bitboard = u64;
@@ -68,7 +69,7 @@ pub fn bitboard_to_string(bitboard: Bitboard, mark: Option<usize>) -> String {
         match mark {
             Some(idx) => {
                 if i == idx {
-                    row.push_str("X");
+                    row.push('X');
                 } else {
                     row.push_str(&s);
                 }
@@ -77,7 +78,7 @@ pub fn bitboard_to_string(bitboard: Bitboard, mark: Option<usize>) -> String {
         }
 
         if (i + 1) % 8 == 0 {
-            row.push_str("\n");
+            row.push('\n');
             board.insert_str(0, &row);
             row.clear();
         }
@@ -90,7 +91,7 @@ pub fn print_bitboard(bitboard: Bitboard, marker: Option<usize>) {
 }
 
 pub fn set_bit(row: i32, col: i32) -> Bitboard {
-    if row < 1 || row > 8 || col < 1 || col > 8 {
+    if !(1..=8).contains(&row) || !(1..=8).contains(&col) {
         return 0;
     }
 
@@ -126,7 +127,7 @@ mod tests {
     #[test]
     fn bit_scan_works() {
         for i in 0..64 {
-            let bit = (1 as u64) << i;
+            let bit = 1_u64 << i;
             let index = bit_scan(bit);
 
             assert_eq!(i, index);
@@ -0,0 +1,636 @@
+use crate::game::{Game, Outcome};
+use crate::gametree::GameTree;
+use crate::position::*;
+use crate::search::{search, SearchLimits, SearchResult};
+use crate::transpositiontable::{TranspositionTable, MAX_SIZE_MB};
+use crate::uci::send;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The clock set by `level`: `moves` moves in `base` time, repeating, or
+/// the whole game if `moves` is 0, with `increment` added after each move.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Level {
+    moves: u32,
+    base: Duration,
+    increment: Duration,
+}
+
+/// An XBoard (CECP) session. The game is kept in a `GameTree` so that
+/// `undo` and `remove` can take moves back. The engine plays one color, or
+/// neither in force mode, and replies to the opponent's moves on its own,
+/// thinking on a separate thread so that `?` and `force` are heard
+/// meanwhile. Replies go to `out`. The transposition table lasts until the
+/// next `new`.
+pub struct XBoard<W> {
+    tree: GameTree,
+    out: Arc<Mutex<W>>,
+    table: Arc<Mutex<TranspositionTable>>,
+    stop: Arc<AtomicBool>,
+    // Set when the move being searched for should not be played.
+    abandon: Arc<AtomicBool>,
+    search: Option<JoinHandle<Option<Move>>>,
+    force: bool,
+    engine_color: Color,
+    post: bool,
+    level: Level,
+    move_time: Option<Duration>,
+    depth: Option<usize>,
+    engine_clock: Option<Duration>,
+    opponent_clock: Option<Duration>,
+}
+
+impl<W: Write + Send + 'static> XBoard<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            tree: GameTree::new(),
+            out: Arc::new(Mutex::new(out)),
+            table: Arc::new(Mutex::new(TranspositionTable::default())),
+            stop: Arc::new(AtomicBool::new(false)),
+            abandon: Arc::new(AtomicBool::new(false)),
+            search: None,
+            force: false,
+            engine_color: Color::Black,
+            post: false,
+            level: Level {
+                moves: 40,
+                base: Duration::from_secs(300),
+                increment: Duration::ZERO,
+            },
+            move_time: None,
+            depth: None,
+            engine_clock: None,
+            opponent_clock: None,
+        }
+    }
+
+    /// The game as it stands, including the engine's last move once it has
+    /// been played.
+    pub fn game(&self) -> &Game {
+        self.tree.game()
+    }
+
+    /// Handles commands from `input` until `quit` or the end of the input.
+    pub fn run<R: BufRead>(&mut self, input: R) -> io::Result<()> {
+        for line in input.lines() {
+            if !self.handle(&line?) {
+                break;
+            }
+        }
+        self.stop_search(false);
+        Ok(())
+    }
+
+    /// Handles a single command line. Returns `false` once the session
+    /// should end.
+    pub fn handle(&mut self, line: &str) -> bool {
+        if self
+            .search
+            .as_ref()
+            .is_some_and(|search| search.is_finished())
+        {
+            self.stop_search(true);
+        }
+
+        let tokens = line.split_whitespace().collect::<Vec<&str>>();
+        let Some((&command, args)) = tokens.split_first() else {
+            return true;
+        };
+        // Clocks go negative once a side has overstepped its time; count that
+        // as no time left rather than as no number at all.
+        let number = |index: usize| {
+            args.get(index)
+                .and_then(|arg| arg.parse::<i64>().ok())
+                .map(|number| number.max(0) as u64)
+        };
+
+        match command {
+            "protover" => self.send_features(),
+            "ping" => self.send(&format!("pong {}", args.first().unwrap_or(&""))),
+            "new" => {
+                self.stop_search(false);
+                self.tree = GameTree::new();
+                self.force = false;
+                self.engine_color = Color::Black;
+                self.depth = None;
+                self.engine_clock = None;
+                self.opponent_clock = None;
+                self.table.lock().unwrap().clear();
+            }
+            "setboard" => {
+                self.stop_search(false);
+                match GameTree::from_fen(&args.join(" ")) {
+                    Ok(tree) => self.tree = tree,
+                    Err(err) => self.send(&format!("tellusererror Illegal position: {}", err)),
+                }
+            }
+            "force" => {
+                self.stop_search(false);
+                self.force = true;
+            }
+            "go" => {
+                self.stop_search(false);
+                self.force = false;
+                self.engine_color = self.tree.position().active_color;
+                self.think();
+            }
+            "playother" => {
+                self.stop_search(false);
+                self.force = false;
+                self.engine_color = self.tree.position().active_color.opposite();
+            }
+            "usermove" => match args.first() {
+                Some(text) => self.user_move(text),
+                None => self.send("Error (missing move): usermove"),
+            },
+            "?" => self.stop_search(true),
+            "undo" => {
+                self.stop_search(false);
+                self.tree.undo();
+            }
+            "remove" => {
+                self.stop_search(false);
+                self.tree.undo();
+                self.tree.undo();
+            }
+            "level" => match parse_level(args) {
+                Some(level) => {
+                    self.level = level;
+                    self.move_time = None;
+                }
+                None => self.send(&format!("Error (invalid level): {}", line.trim())),
+            },
+            "st" => self.move_time = number(0).map(Duration::from_secs),
+            "sd" => self.depth = number(0).map(|depth| depth as usize),
+            "memory" => match number(0).filter(|&mb| (1..=MAX_SIZE_MB as u64).contains(&mb)) {
+                Some(megabytes) => {
+                    self.stop_search(true);
+                    self.table.lock().unwrap().resize(megabytes as usize);
+                }
+                None => self.send(&format!("Error (invalid memory): {}", line.trim())),
+            },
+            "time" => self.engine_clock = number(0).map(|cs| Duration::from_millis(cs * 10)),
+            "otim" => self.opponent_clock = number(0).map(|cs| Duration::from_millis(cs * 10)),
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "result" => {
+                self.stop_search(false);
+                self.force = true;
+            }
+            "quit" => {
+                self.stop_search(false);
+                return false;
+            }
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer"
+            | "name" | "rating" | "ics" | "draw" => (),
+            // Without the usermove feature moves come on their own.
+            _ if args.is_empty() && self.parse_move(command).is_some() => self.user_move(command),
+            _ => self.send(&format!("Error (unknown command): {}", command)),
+        }
+
+        true
+    }
+
+    fn send(&self, line: &str) {
+        send(&self.out, line);
+    }
+
+    fn send_features(&self) {
+        self.send(&format!(
+            "feature myname=\"chess_engine {}\" setboard=1 usermove=1 playother=1 \
+             ping=1 memory=1 san=0 colors=0 sigint=0 sigterm=0 reuse=1 analyze=0 done=1",
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+
+    /// A move in coordinate notation, or failing that in SAN.
+    fn parse_move(&self, text: &str) -> Option<Move> {
+        let game = self.tree.game();
+        game.parse_uci_move(text)
+            .ok()
+            .or_else(|| game.parse_san(text).ok())
+    }
+
+    /// Plays the opponent's move and, unless in force mode, answers it.
+    fn user_move(&mut self, text: &str) {
+        self.stop_search(false);
+        match self.parse_move(text) {
+            Some(m) => {
+                self.tree.play(m);
+                if !self.force && self.tree.position().active_color == self.engine_color {
+                    self.think();
+                }
+            }
+            None => self.send(&format!("Illegal move: {}", text)),
+        }
+    }
+
+    /// What the engine may spend on its next move.
+    fn limits(&self) -> SearchLimits {
+        let mut limits = SearchLimits {
+            depth: self.depth,
+            movetime: self.move_time,
+            ..Default::default()
+        };
+        if self.move_time.is_some() {
+            return limits;
+        }
+
+        let engine_clock = self.engine_clock.unwrap_or(self.level.base);
+        let opponent_clock = self.opponent_clock.unwrap_or(self.level.base);
+        let (wtime, btime) = match self.engine_color {
+            Color::White => (engine_clock, opponent_clock),
+            Color::Black => (opponent_clock, engine_clock),
+        };
+        limits.wtime = Some(wtime);
+        limits.btime = Some(btime);
+        limits.winc = Some(self.level.increment);
+        limits.binc = Some(self.level.increment);
+
+        if self.level.moves > 0 {
+            let played =
+                (self.tree.position().fullmove_number as u32).saturating_sub(1) % self.level.moves;
+            limits.movestogo = Some(self.level.moves - played);
+        }
+
+        limits
+    }
+
+    /// Starts looking for the engine's move, unless the game is over. The
+    /// search thread sends the move, and the result if the move ends the
+    /// game; the move is played on the tree once the thread is joined.
+    ///
+    /// A draw the opponent's move has made available to claim is claimed
+    /// instead, since the GUI will not end the game on its own.
+    fn think(&mut self) {
+        match self.tree.game().outcome() {
+            Some(outcome @ (Outcome::ThreefoldRepetition | Outcome::FiftyMoveRule)) => {
+                self.send(&result_line(outcome));
+                return;
+            }
+            Some(_) => return,
+            None => (),
+        }
+
+        let mut game = self.tree.game().clone();
+        let limits = self.limits();
+        let post = self.post;
+        let out = Arc::clone(&self.out);
+        let table = Arc::clone(&self.table);
+        let stop = Arc::clone(&self.stop);
+        let abandon = Arc::clone(&self.abandon);
+        stop.store(false, Ordering::SeqCst);
+        abandon.store(false, Ordering::SeqCst);
+
+        self.search = Some(thread::spawn(move || {
+            let start = Instant::now();
+            let mut table = table.lock().unwrap();
+            let mut report = |result: &SearchResult| {
+                if post {
+                    send(&out, &thinking(result, start.elapsed()));
+                }
+            };
+            let result = search(&mut game, &limits, &mut table, &stop, &mut report);
+
+            // Deciding and sending under the lock means `stop_search` either
+            // sees the move sent or keeps it from being sent.
+            let mut out = out.lock().unwrap();
+            if abandon.load(Ordering::SeqCst) {
+                return None;
+            }
+            let m = result.best_move?;
+            let _ = writeln!(out, "move {}", m.to_uci());
+            game.make_move(m);
+            if let Some(outcome) = game.outcome() {
+                let _ = writeln!(out, "{}", result_line(outcome));
+            }
+            let _ = out.flush();
+            Some(m)
+        }));
+    }
+
+    /// Stops the engine thinking. With `keep_move` the move it has found so
+    /// far is sent and played; without it the move is dropped, unless it had
+    /// already been sent.
+    fn stop_search(&mut self, keep_move: bool) {
+        if !keep_move {
+            let _out = self.out.lock().unwrap();
+            self.abandon.store(true, Ordering::SeqCst);
+        }
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(search) = self.search.take() {
+            if let Some(m) = search.join().expect("The search thread panicked") {
+                self.tree.play(m);
+            }
+        }
+    }
+}
+
+/// `level MPS BASE INC`, with `BASE` in minutes or as `minutes:seconds` and
+/// `INC` in seconds.
+fn parse_level(args: &[&str]) -> Option<Level> {
+    let [moves, base, increment] = args else {
+        return None;
+    };
+
+    let base = match base.split_once(':') {
+        Some((minutes, seconds)) => {
+            minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?
+        }
+        None => base.parse::<u64>().ok()? * 60,
+    };
+    let increment = increment.parse::<f64>().ok().filter(|inc| *inc >= 0.0)?;
+
+    Some(Level {
+        moves: moves.parse().ok()?,
+        base: Duration::from_secs(base),
+        increment: Duration::from_secs_f64(increment),
+    })
+}
+
+/// A line of thinking output for `post`: depth, score in centipawns, time in
+/// centiseconds, nodes and the principal variation. Mate in `n` is scored
+/// 100000 + `n`, and being mated in `n` -100000 - `n`, as XBoard expects.
+fn thinking(result: &SearchResult, elapsed: Duration) -> String {
+    let score = match result.mate() {
+        Some(moves) if moves > 0 => 100_000 + moves,
+        Some(moves) => -100_000 + moves,
+        None => result.score,
+    };
+    let mut line = format!(
+        "{} {} {} {}",
+        result.depth,
+        score,
+        elapsed.as_millis() / 10,
+        result.nodes
+    );
+    for m in &result.pv {
+        line.push(' ');
+        line.push_str(&m.to_uci());
+    }
+    line
+}
+
+/// The `result` line claiming how the game ended.
+fn result_line(outcome: Outcome) -> String {
+    match outcome {
+        Outcome::Checkmate {
+            winner: Color::White,
+        } => "1-0 {White mates}".to_string(),
+        Outcome::Checkmate {
+            winner: Color::Black,
+        } => "0-1 {Black mates}".to_string(),
+        draw => {
+            let reason = match draw {
+                Outcome::Stalemate => "Stalemate",
+                Outcome::FiftyMoveRule | Outcome::SeventyFiveMoveRule => "Fifty move rule",
+                Outcome::ThreefoldRepetition | Outcome::FivefoldRepetition => "Draw by repetition",
+                _ => "Insufficient material",
+            };
+            format!("1/2-1/2 {{{}}}", reason)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> XBoard<Vec<u8>> {
+        let mut xboard = XBoard::new(vec![]);
+        xboard.handle("xboard");
+        xboard.handle("protover 2");
+        xboard.out.lock().unwrap().clear();
+        xboard
+    }
+
+    /// What was sent so far, once any move the engine is thinking about is
+    /// in.
+    fn output(xboard: &mut XBoard<Vec<u8>>) -> String {
+        if let Some(search) = xboard.search.take() {
+            if let Some(m) = search.join().unwrap() {
+                xboard.tree.play(m);
+            }
+        }
+        String::from_utf8(xboard.out.lock().unwrap().clone()).unwrap()
+    }
+
+    #[test]
+    fn test_features() {
+        let mut xboard = XBoard::new(vec![]);
+        xboard.handle("xboard");
+        xboard.handle("protover 2");
+        xboard.handle("ping 7");
+
+        let output = output(&mut xboard);
+        assert!(output.starts_with("feature myname=\"chess_engine "));
+        assert!(output.contains(" usermove=1 "));
+        assert!(output.ends_with("done=1\npong 7\n"));
+    }
+
+    #[test]
+    fn test_engine_answers_as_black() {
+        let mut xboard = session();
+        xboard.handle("new");
+        xboard.handle("sd 2");
+        xboard.handle("usermove e2e4");
+
+        let output = output(&mut xboard);
+        assert!(output.starts_with("move "), "{}", output);
+        assert_eq!(xboard.game().history.len(), 2);
+        assert_eq!(xboard.game().position.active_color, Color::White);
+    }
+
+    #[test]
+    fn test_force_mode_and_go() {
+        let mut xboard = session();
+        xboard.handle("new");
+        xboard.handle("sd 2");
+        xboard.handle("force");
+        xboard.handle("usermove e2e4");
+        xboard.handle("e7e5");
+        assert_eq!(output(&mut xboard), "");
+        assert_eq!(xboard.game().history.len(), 2);
+
+        xboard.handle("go");
+        assert!(output(&mut xboard).starts_with("move "));
+        assert_eq!(xboard.game().history.len(), 3);
+        assert_eq!(xboard.engine_color, Color::White);
+    }
+
+    #[test]
+    fn test_illegal_and_unknown() {
+        let mut xboard = session();
+        xboard.handle("usermove e2e5");
+        xboard.handle("frobnicate");
+        assert_eq!(
+            output(&mut xboard),
+            "Illegal move: e2e5\nError (unknown command): frobnicate\n"
+        );
+    }
+
+    #[test]
+    fn test_undo_and_remove() {
+        let mut xboard = session();
+        xboard.handle("force");
+        for m in ["e2e4", "e7e5", "g1f3"] {
+            xboard.handle(&format!("usermove {}", m));
+        }
+
+        xboard.handle("undo");
+        assert_eq!(xboard.game().history.len(), 2);
+        xboard.handle("remove");
+        assert!(xboard.game().history.is_empty());
+        assert_eq!(xboard.game().position.to_fen(), Position::new().to_fen());
+    }
+
+    #[test]
+    fn test_setboard() {
+        let mut xboard = session();
+        xboard.handle("setboard 4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        assert_eq!(
+            xboard.game().position.to_fen(),
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"
+        );
+
+        xboard.handle("setboard 8/8/8 w - - 0 1");
+        assert!(output(&mut xboard).starts_with("tellusererror Illegal position"));
+    }
+
+    #[test]
+    fn test_post_thinking() {
+        let mut xboard = session();
+        xboard.handle("post");
+        xboard.handle("sd 1");
+        xboard.handle("go");
+
+        let output = output(&mut xboard);
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2, "{}", output);
+        let fields = lines[0].split_whitespace().collect::<Vec<&str>>();
+        assert_eq!(fields[0], "1");
+        assert!(fields.len() >= 5);
+        assert!(lines[1].starts_with("move "));
+    }
+
+    #[test]
+    fn test_no_move_once_game_is_over() {
+        let mut xboard = session();
+        xboard.handle("setboard 7k/8/6K1/8/8/8/8/Q7 w - - 0 1");
+        xboard.handle("force");
+        xboard.handle("usermove a1a8");
+        xboard.handle("go");
+
+        assert_eq!(output(&mut xboard), "");
+        assert_eq!(
+            result_line(xboard.game().outcome().unwrap()),
+            "1-0 {White mates}"
+        );
+        assert_eq!(result_line(Outcome::Stalemate), "1/2-1/2 {Stalemate}");
+    }
+
+    #[test]
+    fn test_claims_threefold_repetition() {
+        let mut xboard = session();
+        xboard.handle("force");
+        for m in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"] {
+            xboard.handle(&format!("usermove {}", m));
+        }
+        xboard.handle("playother");
+        xboard.handle("usermove f6g8");
+
+        assert_eq!(output(&mut xboard), "1/2-1/2 {Draw by repetition}\n");
+        assert_eq!(xboard.game().history.len(), 8);
+    }
+
+    #[test]
+    fn test_time_controls() {
+        let mut xboard = session();
+        xboard.handle("level 40 5 0");
+        assert_eq!(
+            xboard.level,
+            Level {
+                moves: 40,
+                base: Duration::from_secs(300),
+                increment: Duration::ZERO,
+            }
+        );
+        xboard.handle("level 0 2:30 12");
+        assert_eq!(xboard.level.base, Duration::from_secs(150));
+        assert_eq!(xboard.level.increment, Duration::from_secs(12));
+
+        xboard.handle("time 6000");
+        xboard.handle("otim 5000");
+        let limits = xboard.limits();
+        assert_eq!(limits.btime, Some(Duration::from_secs(60)));
+        assert_eq!(limits.wtime, Some(Duration::from_secs(50)));
+        assert_eq!(limits.movestogo, None);
+
+        xboard.handle("level 40 5 0");
+        xboard.handle("setboard 4k3/8/8/8/8/8/4P3/4K3 b - - 0 45");
+        assert_eq!(xboard.limits().movestogo, Some(36));
+        // A FEN may number the first move 0.
+        xboard.handle("setboard 4k3/8/8/8/8/8/4P3/4K3 w - - 0 0");
+        assert_eq!(xboard.limits().movestogo, Some(40));
+
+        xboard.handle("st 3");
+        xboard.handle("sd 4");
+        let limits = xboard.limits();
+        assert_eq!(limits.movetime, Some(Duration::from_secs(3)));
+        assert_eq!(limits.depth, Some(4));
+        assert_eq!(limits.wtime, None);
+
+        xboard.handle("level x 5 0");
+        assert!(output(&mut xboard).starts_with("Error (invalid level)"));
+    }
+
+    #[test]
+    fn test_memory_and_new_reset_table() {
+        let mut xboard = session();
+        xboard.handle("memory 2");
+        assert_eq!(xboard.table.lock().unwrap().megabytes(), 2);
+        xboard.handle("memory 0");
+        assert!(output(&mut xboard).starts_with("Error (invalid memory)"));
+
+        xboard.handle("sd 2");
+        xboard.handle("go");
+        output(&mut xboard);
+        let hash = Position::new().hash;
+        assert!(xboard.table.lock().unwrap().probe(hash, 0).is_some());
+
+        xboard.handle("new");
+        assert!(xboard.table.lock().unwrap().probe(hash, 0).is_none());
+    }
+
+    #[test]
+    fn test_negative_clock_and_new() {
+        let mut xboard = session();
+        xboard.handle("force");
+        xboard.handle("time -50");
+        xboard.handle("otim 6000");
+        assert_eq!(xboard.engine_clock, Some(Duration::ZERO));
+        assert_eq!(xboard.limits().btime, Some(Duration::ZERO));
+        assert_eq!(
+            xboard.limits().time_budget(Color::Black),
+            Some(Duration::ZERO)
+        );
+
+        xboard.handle("new");
+        assert_eq!(xboard.engine_clock, None);
+        assert_eq!(xboard.opponent_clock, None);
+    }
+
+    #[test]
+    fn test_result_stops_engine() {
+        let mut xboard = session();
+        xboard.handle("result 1-0 {White mates}");
+        assert!(xboard.force);
+        xboard.handle("usermove e2e4");
+        assert_eq!(output(&mut xboard), "");
+        assert_eq!(xboard.game().history.len(), 1);
+    }
+}
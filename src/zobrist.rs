@@ -0,0 +1,110 @@
+use crate::position::{CastlingRights, Color, PieceType};
+
+/// The random numbers a position's Zobrist hash is built from: one per piece
+/// on each square, one for Black to move, one per combination of castling
+/// rights and one per en passant file.
+struct ZobristKeys {
+    pieces: [[[u64; 64]; 6]; 2],
+    black_to_move: u64,
+    castling: [u64; 16],
+    en_passant_file: [u64; 8],
+}
+
+// Generated at compile time so that hashes are the same in every run, which
+// keeps them usable as keys in anything written to disk.
+static KEYS: ZobristKeys = ZobristKeys::generate();
+
+impl ZobristKeys {
+    const fn generate() -> Self {
+        let mut state = 0x2545_F491_4F6C_DD1D;
+        let mut keys = ZobristKeys {
+            pieces: [[[0; 64]; 6]; 2],
+            black_to_move: 0,
+            castling: [0; 16],
+            en_passant_file: [0; 8],
+        };
+
+        let mut color = 0;
+        while color < 2 {
+            let mut piece_type = 0;
+            while piece_type < 6 {
+                let mut square = 0;
+                while square < 64 {
+                    keys.pieces[color][piece_type][square] = splitmix64(&mut state);
+                    square += 1;
+                }
+                piece_type += 1;
+            }
+            color += 1;
+        }
+
+        keys.black_to_move = splitmix64(&mut state);
+
+        // Rights 0 (none) keep a zero key so a position without castling
+        // rights hashes the same as one where they were never considered.
+        let mut rights = 1;
+        while rights < 16 {
+            keys.castling[rights] = splitmix64(&mut state);
+            rights += 1;
+        }
+
+        let mut file = 0;
+        while file < 8 {
+            keys.en_passant_file[file] = splitmix64(&mut state);
+            file += 1;
+        }
+
+        keys
+    }
+}
+
+const fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// The key for a piece of `color` and `piece_type` standing on `square`.
+pub fn piece_key(color: Color, piece_type: PieceType, square: usize) -> u64 {
+    KEYS.pieces[color as usize][piece_type as usize][square]
+}
+
+/// The key toggled whenever the side to move changes.
+pub fn black_to_move_key() -> u64 {
+    KEYS.black_to_move
+}
+
+pub fn castling_key(castling_rights: CastlingRights) -> u64 {
+    KEYS.castling[castling_rights.bits() as usize]
+}
+
+/// The key for an en passant capture on `file`, counted from 0 for the a-file.
+pub fn en_passant_key(file: usize) -> u64 {
+    KEYS.en_passant_file[file]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_are_distinct() {
+        let mut keys = vec![KEYS.black_to_move];
+        keys.extend(KEYS.pieces.iter().flatten().flatten());
+        keys.extend(&KEYS.castling[1..]);
+        keys.extend(&KEYS.en_passant_file);
+
+        assert!(keys.iter().all(|&key| key != 0));
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), 1 + 2 * 6 * 64 + 15 + 8);
+    }
+
+    #[test]
+    fn test_no_castling_rights_has_no_key() {
+        assert_eq!(castling_key(CastlingRights::NONE), 0);
+        assert_ne!(castling_key(CastlingRights::ALL), 0);
+    }
+}
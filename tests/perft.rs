@@ -0,0 +1,278 @@
+//! Perft node counts for the reference positions collected on
+//! https://www.chessprogramming.org/Perft_Results.
+//!
+//! The deeper counts take a while in a debug build and are ignored by
+//! default; run them with `cargo test --release -- --include-ignored`.
+
+use chess_engine::movegeneration::generate_moves_by_filtering;
+use chess_engine::{generate_moves, generate_tactical_moves, king_is_in_check, Game, PieceType};
+use std::process::Command;
+
+const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+const POSITION_3: &str = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+const POSITION_4: &str = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+const POSITION_4_MIRRORED: &str =
+    "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1";
+const POSITION_5: &str = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+const POSITION_6: &str = "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10";
+
+fn perft(fen: &str, depth: usize) -> u64 {
+    let mut game = Game::from_fen(fen).unwrap_or_else(|e| panic!("'{}': {}", fen, e));
+    chess_engine::perft::perft(&mut game, depth)
+}
+
+/// Checks `expected[i]` against perft at depth `i + 1`.
+fn assert_perft(fen: &str, expected: &[u64]) {
+    for (i, &nodes) in expected.iter().enumerate() {
+        assert_eq!(perft(fen, i + 1), nodes, "depth {} of '{}'", i + 1, fen);
+    }
+}
+
+#[test]
+fn perft_start_position() {
+    assert_perft(START, &[20, 400, 8902, 197281]);
+}
+
+#[test]
+#[ignore]
+fn perft_start_position_deep() {
+    assert_eq!(perft(START, 5), 4865609);
+}
+
+#[test]
+fn perft_kiwipete() {
+    assert_perft(KIWIPETE, &[48, 2039, 97862]);
+}
+
+#[test]
+#[ignore]
+fn perft_kiwipete_deep() {
+    assert_eq!(perft(KIWIPETE, 4), 4085603);
+}
+
+#[test]
+fn perft_position_3() {
+    assert_perft(POSITION_3, &[14, 191, 2812, 43238]);
+}
+
+#[test]
+#[ignore]
+fn perft_position_3_deep() {
+    assert_eq!(perft(POSITION_3, 5), 674624);
+    assert_eq!(perft(POSITION_3, 6), 11030083);
+}
+
+#[test]
+fn perft_position_4() {
+    assert_perft(POSITION_4, &[6, 264, 9467]);
+    assert_perft(POSITION_4_MIRRORED, &[6, 264, 9467]);
+}
+
+#[test]
+#[ignore]
+fn perft_position_4_deep() {
+    assert_eq!(perft(POSITION_4, 4), 422333);
+    assert_eq!(perft(POSITION_4_MIRRORED, 4), 422333);
+}
+
+#[test]
+fn perft_position_5() {
+    assert_perft(POSITION_5, &[44, 1486, 62379]);
+}
+
+#[test]
+#[ignore]
+fn perft_position_5_deep() {
+    assert_eq!(perft(POSITION_5, 4), 2103487);
+}
+
+#[test]
+fn perft_position_6() {
+    assert_perft(POSITION_6, &[46, 2079, 89890]);
+}
+
+#[test]
+#[ignore]
+fn perft_position_6_deep() {
+    assert_eq!(perft(POSITION_6, 4), 3894594);
+}
+
+// Edge cases from Martin Sedlak's collection, each published at one depth.
+
+#[test]
+fn perft_self_stalemate() {
+    assert_eq!(perft("K1k5/8/P7/8/8/8/8/8 w - - 0 1", 6), 2217);
+}
+
+#[test]
+fn perft_stalemate_and_checkmate() {
+    assert_eq!(perft("8/8/2k5/5q2/5n2/8/5K2/8 b - - 0 1", 4), 23527);
+}
+
+#[test]
+#[ignore]
+fn perft_stalemate_and_checkmate_deep() {
+    assert_eq!(perft("8/k1P5/8/1K6/8/8/8/8 w - - 0 1", 7), 567584);
+}
+
+#[test]
+fn perft_underpromote_to_give_check() {
+    assert_eq!(perft("8/P1k5/K7/8/8/8/8/8 w - - 0 1", 6), 92683);
+}
+
+#[test]
+fn perft_promote_to_give_check() {
+    assert_eq!(perft("4k3/1P6/8/8/8/8/K7/8 w - - 0 1", 6), 217342);
+}
+
+#[test]
+#[ignore]
+fn perft_promote_out_of_check() {
+    assert_eq!(perft("2K2r2/4P3/8/8/8/8/8/3k4 w - - 0 1", 6), 3821001);
+}
+
+#[test]
+#[ignore]
+fn perft_avoid_illegal_en_passant_capture() {
+    assert_eq!(perft("3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1", 6), 1134888);
+    assert_eq!(perft("8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1", 6), 1015133);
+}
+
+#[test]
+#[ignore]
+fn perft_en_passant_capture_checks_opponent() {
+    assert_eq!(perft("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", 6), 1440467);
+}
+
+#[test]
+#[ignore]
+fn perft_castling_gives_check() {
+    assert_eq!(perft("5k2/8/8/8/8/8/8/4K2R w K - 0 1", 6), 661072);
+    assert_eq!(perft("3k4/8/8/8/8/8/8/R3K3 w Q - 0 1", 6), 803711);
+}
+
+#[test]
+#[ignore]
+fn perft_castling_rights() {
+    assert_eq!(
+        perft("r3k2r/1b4bq/8/8/8/8/7B/R3K2R w KQkq - 0 1", 4),
+        1274206
+    );
+}
+
+#[test]
+#[ignore]
+fn perft_castling_prevented() {
+    assert_eq!(
+        perft("r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1", 4),
+        1720476
+    );
+}
+
+#[test]
+#[ignore]
+fn perft_discovered_check() {
+    assert_eq!(perft("8/8/1P2K3/8/2n5/1q6/8/5k2 b - - 0 1", 5), 1004658);
+}
+
+/// Walks the move tree to `depth` and checks at every node that the legal
+/// move generator agrees with making each pseudo-legal move and testing for
+/// check, and that the tactical generator picks out the captures and queen
+/// promotions, or every move when in check.
+fn assert_generators_agree(game: &mut Game, depth: usize) {
+    let mut moves = generate_moves(game);
+    let mut reference = generate_moves_by_filtering(game);
+    moves.sort_by_key(|m| m.to_string());
+    reference.sort_by_key(|m| m.to_string());
+    assert_eq!(moves, reference, "moves of '{}'", game.position.to_fen());
+
+    let mut tactical = generate_tactical_moves(game);
+    tactical.sort_by_key(|m| m.to_string());
+    let in_check = king_is_in_check(game, game.position.active_color);
+    let expected = moves
+        .iter()
+        .copied()
+        .filter(|m| {
+            in_check
+                || m.promotion()
+                    .map_or(m.is_capture(), |p| p == PieceType::Queen)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tactical,
+        expected,
+        "tactical moves of '{}'",
+        game.position.to_fen()
+    );
+
+    if depth > 1 {
+        for m in moves {
+            let undo = game.position.make_move(m);
+            assert_generators_agree(game, depth - 1);
+            game.position.unmake_move(m, undo);
+        }
+    }
+}
+
+const PARITY_POSITIONS: &[&str] = &[
+    START,
+    KIWIPETE,
+    POSITION_3,
+    POSITION_4,
+    POSITION_4_MIRRORED,
+    POSITION_5,
+    POSITION_6,
+    "3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1",
+    "8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1",
+    "8/8/1P2K3/8/2n5/1q6/8/5k2 b - - 0 1",
+    "4k3/8/8/8/1b6/8/3N4/r3K2R w K - 0 1",
+];
+
+#[test]
+fn legal_generator_matches_filtering() {
+    for fen in PARITY_POSITIONS {
+        assert_generators_agree(&mut Game::from_fen(fen).unwrap(), 2);
+    }
+}
+
+#[test]
+#[ignore]
+fn legal_generator_matches_filtering_deep() {
+    for fen in PARITY_POSITIONS {
+        assert_generators_agree(&mut Game::from_fen(fen).unwrap(), 4);
+    }
+}
+
+#[test]
+fn perft_subcommand() {
+    let output = Command::new(env!("CARGO_BIN_EXE_chess_engine"))
+        .args(["perft", "2", KIWIPETE])
+        .output()
+        .expect("Failed to run chess_engine");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Nodes searched: 2039"), "{}", stdout);
+}
+
+#[test]
+fn perft_subcommand_rejects_invalid_fen() {
+    let output = Command::new(env!("CARGO_BIN_EXE_chess_engine"))
+        .args(["perft", "1", "8/8/8/8/8/8/8/8 w - - 0 1"])
+        .output()
+        .expect("Failed to run chess_engine");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn divide_subcommand_rejects_depth_zero() {
+    let output = Command::new(env!("CARGO_BIN_EXE_chess_engine"))
+        .args(["divide", "0"])
+        .output()
+        .expect("Failed to run chess_engine");
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("usage:"), "{}", stderr);
+}
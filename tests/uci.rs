@@ -0,0 +1,31 @@
+//! Drives the binary over stdin and stdout the way a chess GUI would.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn uci_session() {
+    let mut engine = Command::new(env!("CARGO_BIN_EXE_chess_engine"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run chess_engine");
+
+    engine
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(
+            b"uci\nisready\nucinewgame\nposition startpos moves e2e4 e7e5\ngo depth 1\nquit\n",
+        )
+        .unwrap();
+
+    let output = engine.wait_with_output().unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<&str>>();
+    assert!(lines.contains(&"uciok"), "{}", stdout);
+    assert!(lines.contains(&"readyok"), "{}", stdout);
+    assert!(lines.last().unwrap().starts_with("bestmove "), "{}", stdout);
+}
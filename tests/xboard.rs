@@ -0,0 +1,26 @@
+//! Drives the binary over stdin and stdout the way an XBoard GUI would.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+#[test]
+fn xboard_session() {
+    let mut engine = Command::new(env!("CARGO_BIN_EXE_chess_engine"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to run chess_engine");
+    let mut input = engine.stdin.take().unwrap();
+    let mut output = BufReader::new(engine.stdout.take().unwrap()).lines();
+
+    input
+        .write_all(b"xboard\nprotover 2\nnew\nsd 1\nusermove e2e4\n")
+        .unwrap();
+    assert!(output.next().unwrap().unwrap().starts_with("feature "));
+    // Quitting drops a move still being thought about, so wait for it.
+    assert!(output.next().unwrap().unwrap().starts_with("move "));
+
+    input.write_all(b"ping 1\nquit\n").unwrap();
+    assert_eq!(output.next().unwrap().unwrap(), "pong 1");
+    assert!(engine.wait().unwrap().success());
+}